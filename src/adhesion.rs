@@ -0,0 +1,196 @@
+use crate::model::Layer;
+use crate::polygons::{convex_hull, holes, offset_area, Polygon, Vertex};
+use crate::profile::Profile;
+
+#[derive(Debug, Clone)]
+pub struct RaftSettings {
+    /// how far the raft reaches beyond the model
+    pub margin: f32,
+    /// number and height of the thick layers on the bed
+    pub base_layers: usize,
+    pub base_layer_height: f32,
+    /// number and height of the layers the model is printed on
+    pub interface_layers: usize,
+    pub interface_layer_height: f32,
+    /// vertical distance between the raft and the model
+    pub gap: f32,
+}
+
+impl Default for RaftSettings {
+    fn default() -> Self {
+        return RaftSettings {
+            margin: 3.,
+            base_layers: 1,
+            base_layer_height: 0.3,
+            interface_layers: 2,
+            interface_layer_height: 0.2,
+            gap: 0.1,
+        };
+    }
+}
+
+impl RaftSettings {
+    /// the base is as thick as the first layer, the interface as thick as the other layers
+    pub fn from_profile(profile: &Profile) -> RaftSettings {
+        return RaftSettings {
+            margin: profile.adhesion.raft_margin,
+            base_layer_height: profile.print.first_layer_height,
+            interface_layer_height: profile.print.layer_height,
+            gap: profile.adhesion.raft_gap,
+            ..RaftSettings::default()
+        };
+    }
+}
+
+/// Loops around the whole first layer at the given distance, without touching it.
+/// The loops are `line_width` apart.
+pub fn skirt(first: &Layer, distance: f32, loops: usize, line_width: f32) -> Vec<Polygon> {
+    let points: Vec<Vertex> = first
+        .polygons
+        .iter()
+        .flat_map(|p| p.points.iter().copied())
+        .collect();
+    if points.len() < 3 {
+        return vec![];
+    }
+    let hull = convex_hull(&points);
+    return (0..loops)
+        .filter_map(|i| hull.offset(distance + i as f32 * line_width))
+        .collect();
+}
+
+/// Concentric loops attached to the outside of every part of the first layer.
+pub fn brim(first: &Layer, loops: usize, line_width: f32) -> Vec<Polygon> {
+    let outlines: Vec<&Polygon> = first
+        .polygons
+        .iter()
+        .zip(holes(&first.polygons))
+        .filter(|(_, hole)| !hole)
+        .map(|(p, _)| p)
+        .collect();
+    return (0..loops)
+        .flat_map(|i| {
+            // the first loop is half a line width away, so it just touches the model
+            let distance = (i as f32 + 0.5) * line_width;
+            outlines.iter().filter_map(move |p| p.offset(distance))
+        })
+        .collect();
+}
+
+/// Creates the layers of a raft below the first layer of the model.
+/// The raft covers the first layer's outlines (holes are filled) enlarged by the margin.
+pub fn raft(layers: &[Layer], settings: &RaftSettings) -> Vec<Layer> {
+    if layers.is_empty() {
+        return vec![];
+    }
+    let first = &layers[0];
+    let outlines: Vec<Polygon> = first
+        .polygons
+        .iter()
+        .zip(holes(&first.polygons))
+        .filter(|(_, hole)| !hole)
+        .map(|(p, _)| p.clone())
+        .collect();
+    let footprint = offset_area(&outlines, settings.margin);
+
+    let mut bottom = first.height - first.thickness / 2.;
+    let thicknesses = std::iter::repeat_n(settings.base_layer_height, settings.base_layers).chain(
+        std::iter::repeat_n(settings.interface_layer_height, settings.interface_layers),
+    );
+    let mut raft = Vec::new();
+    for thickness in thicknesses {
+        raft.push(Layer {
+            height: bottom + thickness / 2.,
+            thickness,
+            polygons: footprint.clone(),
+        });
+        bottom += thickness;
+    }
+    return raft;
+}
+
+/// Puts the model on top of a raft, every layer of the model is moved up by the raft's thickness.
+/// Returns the raft's layers followed by the model's layers.
+pub fn add_raft(layers: &[Layer], settings: &RaftSettings) -> Vec<Layer> {
+    let raft = raft(layers, settings);
+    let shift = settings.base_layers as f32 * settings.base_layer_height
+        + settings.interface_layers as f32 * settings.interface_layer_height
+        + settings.gap;
+    return raft
+        .into_iter()
+        .chain(layers.iter().map(|layer| Layer {
+            height: layer.height + shift,
+            thickness: layer.thickness,
+            polygons: layer.polygons.clone(),
+        }))
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Point2;
+
+    /// a 10 mm square sliced into layers of 0.2 mm, the first one on the bed
+    fn layers() -> Vec<Layer> {
+        return (0..3)
+            .map(|i| Layer {
+                height: 0.1 + 0.2 * i as f32,
+                thickness: 0.2,
+                polygons: vec![Polygon::rectangle(
+                    Point2::new(0., 0.),
+                    Point2::new(10., 10.),
+                )],
+            })
+            .collect();
+    }
+
+    #[test]
+    fn raft_below_the_model() {
+        let settings = RaftSettings::default();
+        let layers = add_raft(&layers(), &settings);
+        assert_eq!(layers.len(), 6);
+        // one base and two interface layers stacked from the bed up
+        let tops: Vec<f32> = layers.iter().map(|l| l.height + l.thickness / 2.).collect();
+        let expected = [0.3, 0.5, 0.7, 1.0, 1.2, 1.4];
+        for (top, expected) in tops.iter().zip(expected.iter()) {
+            assert!((top - expected).abs() < 1e-5, "{:?}", tops);
+        }
+        assert_eq!(layers[0].thickness, 0.3);
+        assert!(layers[3..].iter().all(|l| l.thickness == 0.2));
+        // the raft reaches the margin beyond the model
+        let raft = &layers[0].polygons;
+        assert_eq!(raft.len(), 1);
+        assert!(raft[0].contains(Point2::new(-2.9, 5.)));
+        assert!(!raft[0].contains(Point2::new(-3.1, 5.)));
+    }
+
+    /// distance of the middle of every line of the loop from the polygon, the corners of
+    /// offset loops are mitered
+    fn distances(line: &Polygon, polygon: &Polygon) -> Vec<f32> {
+        return line
+            .edges()
+            .map(|(a, b)| polygon.distance(a + (b - a) / 2.))
+            .collect();
+    }
+
+    #[test]
+    fn skirt_and_brim() {
+        let first = &layers()[0];
+        let square = &first.polygons[0];
+        let skirt = skirt(first, 5., 2, 0.4);
+        assert_eq!(skirt.len(), 2);
+        for (i, line) in skirt.iter().enumerate() {
+            let distance = 5. + 0.4 * i as f32;
+            assert!(distances(line, square)
+                .iter()
+                .all(|d| (d - distance).abs() < 1e-3));
+        }
+        let brim = brim(first, 3, 0.4);
+        assert_eq!(brim.len(), 3);
+        // the first loop is half a line away, so the line touches the model
+        assert!(distances(&brim[0], square)
+            .iter()
+            .all(|d| (d - 0.2).abs() < 1e-3));
+    }
+}
@@ -0,0 +1,240 @@
+//! Replaces runs of short segments by circular arcs, which the g-code writer emits as
+//! `G2`/`G3` moves.
+
+use crate::polygons::{scalar, Vertex};
+use crate::predicates::{orient2d, Orientation};
+use cgmath::*;
+use std::f64::consts::PI;
+
+/// a move from the end of the previous segment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Segment<S = f32> {
+    Line(Vertex<S>),
+    Arc {
+        end: Vertex<S>,
+        center: Vertex<S>,
+        /// direction in the slicing plane, which is also the direction on the printer
+        clockwise: bool,
+    },
+}
+
+impl<S: BaseFloat> Segment<S> {
+    pub fn end(&self) -> Vertex<S> {
+        return match self {
+            Segment::Line(end) => *end,
+            Segment::Arc { end, .. } => *end,
+        };
+    }
+
+    /// length of the segment starting at `start`
+    pub fn length(&self, start: Vertex<S>) -> S {
+        return match self {
+            Segment::Line(end) => (end - start).magnitude(),
+            Segment::Arc {
+                end,
+                center,
+                clockwise,
+            } => (start - center).magnitude() * sweep(start, *end, *center, *clockwise),
+        };
+    }
+}
+
+/// runs with fewer points are left as lines, they gain nothing from an arc
+const MIN_POINTS: usize = 4;
+/// arcs with a larger radius are nearly straight, lines describe them as well
+const MAX_RADIUS: f64 = 1000.;
+
+/// Describes a polyline, starting at its first point, by lines and arcs. An arc replaces
+/// a run of at least four points if all of them and the segments between them are within
+/// `tolerance` of the arc and the run turns in one direction.
+pub fn fit_arcs<S: BaseFloat>(points: &[Vertex<S>], tolerance: S) -> Vec<Segment<S>> {
+    let mut segments = Vec::new();
+    let mut i = 0;
+    while i + 1 < points.len() {
+        let mut best: Option<(usize, Vertex<S>, bool)> = None;
+        let mut j = i + MIN_POINTS - 1;
+        while j < points.len() {
+            match arc(&points[i..=j], tolerance) {
+                Some((center, clockwise)) => best = Some((j, center, clockwise)),
+                None => break,
+            }
+            j += 1;
+        }
+        match best {
+            Some((j, center, clockwise)) => {
+                segments.push(Segment::Arc {
+                    end: points[j],
+                    center,
+                    clockwise,
+                });
+                i = j;
+            }
+            None => {
+                segments.push(Segment::Line(points[i + 1]));
+                i += 1;
+            }
+        }
+    }
+    return segments;
+}
+
+/// the center and direction of an arc through the points, if it fits them
+fn arc<S: BaseFloat>(points: &[Vertex<S>], tolerance: S) -> Option<(Vertex<S>, bool)> {
+    let (first, last) = (points[0], points[points.len() - 1]);
+    let center = circumcenter(first, points[points.len() / 2], last)?;
+    let radius = (first - center).magnitude();
+    if radius > scalar(MAX_RADIUS) {
+        return None;
+    }
+    let clockwise = orient2d(points[0], points[1], points[2]) == Orientation::Clockwise;
+    let mut swept = S::zero();
+    for (k, w) in points.windows(2).enumerate() {
+        let (a, b) = (w[0], w[1]);
+        if ((b - center).magnitude() - radius).abs() > tolerance {
+            return None;
+        }
+        // the distance between the middle of a chord and the arc
+        let half = (b - a).magnitude() / scalar(2.);
+        if half >= radius || radius - (radius * radius - half * half).sqrt() > tolerance {
+            return None;
+        }
+        if k + 2 < points.len()
+            && (orient2d(a, b, points[k + 2]) == Orientation::Clockwise) != clockwise
+        {
+            return None;
+        }
+        swept += sweep(a, b, center, clockwise);
+    }
+    // a full circle has the same start and end, which g-code can not describe as one arc
+    if swept > scalar(1.5 * PI) {
+        return None;
+    }
+    return Some((center, clockwise));
+}
+
+/// the center of the circle through three points, `None` if they are on a line
+fn circumcenter<S: BaseFloat>(a: Vertex<S>, b: Vertex<S>, c: Vertex<S>) -> Option<Vertex<S>> {
+    if orient2d(a, b, c) == Orientation::Collinear {
+        return None;
+    }
+    let (ab, ac) = (b - a, c - a);
+    let d = ab.perp_dot(ac) * scalar(2.);
+    let (lb, lc) = (ab.magnitude2(), ac.magnitude2());
+    let offset = Vector2::new(ac.y * lb - ab.y * lc, ab.x * lc - ac.x * lb) / d;
+    return Some(a + offset);
+}
+
+/// angle from `start` to `end` around `center` in the given direction, between 0 and 2π
+fn sweep<S: BaseFloat>(start: Vertex<S>, end: Vertex<S>, center: Vertex<S>, clockwise: bool) -> S {
+    let angle = |p: Vertex<S>| (p.y - center.y).atan2(p.x - center.x);
+    let mut delta = angle(end) - angle(start);
+    if clockwise {
+        delta = -delta;
+    }
+    if delta < S::zero() {
+        delta += scalar(2. * PI);
+    }
+    return delta;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// points on a circle around the origin from `start` to `end` (degrees)
+    fn arc_points(radius: f32, start: f32, end: f32, count: usize) -> Vec<Vertex> {
+        return (0..count)
+            .map(|i| {
+                let a = Deg(start + (end - start) * i as f32 / (count - 1) as f32);
+                Point2::new(a.cos() * radius, a.sin() * radius)
+            })
+            .collect();
+    }
+
+    fn length(start: Vertex, segments: &[Segment]) -> f32 {
+        let mut current = start;
+        let mut sum = 0.;
+        for segment in segments {
+            sum += segment.length(current);
+            current = segment.end();
+        }
+        return sum;
+    }
+
+    #[test]
+    fn runs_on_a_circle_become_one_arc() {
+        let points = arc_points(10., 0., 90., 16);
+        let segments = fit_arcs(&points, 0.05);
+        match segments.as_slice() {
+            [Segment::Arc {
+                end,
+                center,
+                clockwise,
+            }] => {
+                assert_eq!(*end, points[15]);
+                assert!(center.to_vec().magnitude() < 1e-3);
+                assert!(!clockwise);
+            }
+            other => panic!("expected one arc, got {:?}", other),
+        }
+        assert!((length(points[0], &segments) - 5. * std::f32::consts::PI).abs() < 1e-3);
+
+        let reversed: Vec<Vertex> = points.iter().rev().copied().collect();
+        match fit_arcs(&reversed, 0.05).as_slice() {
+            [Segment::Arc { clockwise, .. }] => assert!(clockwise),
+            other => panic!("expected one arc, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn arcs_stay_within_the_tolerance() {
+        // few points on a small circle are too far apart for a tight tolerance
+        let points = arc_points(1., 0., 90., 5);
+        let lines = fit_arcs(&points, 0.001);
+        assert!(lines.iter().all(|s| matches!(s, Segment::Line(_))));
+        assert_eq!(lines.len(), 4);
+        assert!(matches!(
+            fit_arcs(&points, 0.1).as_slice(),
+            [Segment::Arc { .. }]
+        ));
+
+        // a zigzag turns both ways and a line has no center
+        let zigzag: Vec<Vertex> = (0..8)
+            .map(|i| Point2::new(i as f32, (i % 2) as f32 * 0.001))
+            .collect();
+        assert!(fit_arcs(&zigzag, 0.01)
+            .iter()
+            .all(|s| matches!(s, Segment::Line(_))));
+    }
+
+    #[test]
+    fn curves_turning_both_ways_get_an_arc_each() {
+        // an S made of two half circles
+        let mut points = arc_points(5., 180., 0., 20);
+        points.extend(
+            arc_points(5., 180., 360., 20)
+                .iter()
+                .skip(1)
+                .map(|p| p + Vector2::new(10., 0.)),
+        );
+        let segments = fit_arcs(&points, 0.05);
+        let arcs: Vec<bool> = segments
+            .iter()
+            .filter_map(|s| match s {
+                Segment::Arc { clockwise, .. } => Some(*clockwise),
+                Segment::Line(_) => None,
+            })
+            .collect();
+        assert_eq!(arcs, vec![true, false]);
+        assert_eq!(segments.last().map(|s| s.end()), points.last().copied());
+    }
+
+    #[test]
+    fn full_circles_are_split() {
+        let points = arc_points(10., 0., 360., 64);
+        let segments = fit_arcs(&points, 0.05);
+        assert!(segments.len() > 1);
+        assert_eq!(segments.last().map(|s| s.end()), Some(points[63]));
+        assert!((length(points[0], &segments) - 20. * std::f32::consts::PI).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,297 @@
+//! Routes travel moves around the inside of the layer instead of across its perimeters
+//! (combing). Oozing material then ends up inside of the part, where it is not visible,
+//! and the filament only has to be retracted when the nozzle leaves an island.
+
+use crate::model::Layer;
+use crate::polygons::{inside, islands, offset_area, scalar, segments_cross, Polygon, Vertex};
+use cgmath::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// the islands of a layer prepared for routing
+pub struct Comber<S = f32> {
+    islands: Vec<Island<S>>,
+}
+
+struct Island<S> {
+    polygons: Vec<Polygon<S>>,
+    /// the edges of the contours with their bounding boxes
+    edges: Vec<Edge<S>>,
+    /// the corners of the island moved away from the contours, the route goes through them
+    waypoints: Vec<Vertex<S>>,
+    /// the visibility graph of the waypoints, the visible waypoints and their distance
+    graph: Vec<Vec<(usize, S)>>,
+}
+
+struct Edge<S> {
+    start: Vertex<S>,
+    end: Vertex<S>,
+    min: Vertex<S>,
+    max: Vertex<S>,
+}
+
+impl<S: BaseFloat> Comber<S> {
+    /// `clearance` is the distance between the waypoints and the contours
+    pub fn new(layer: &Layer<S>, clearance: S) -> Comber<S> {
+        let closed: Vec<Polygon<S>> = layer
+            .polygons
+            .iter()
+            .filter(|p| p.points.len() > 3 && p.points.first() == p.points.last())
+            .cloned()
+            .collect();
+        return Comber {
+            islands: islands(&closed)
+                .into_iter()
+                .map(|polygons| {
+                    let waypoints = offset_area(&polygons, -clearance)
+                        .iter()
+                        .flat_map(|p| p.points[..p.points.len() - 1].to_vec())
+                        .collect();
+                    Island::new(polygons, waypoints)
+                })
+                .collect(),
+        };
+    }
+
+    /// A route from `from` to `to` which stays inside of the layer, including both ends.
+    /// Returns `None` if the points are on different islands or outside of the layer,
+    /// in which case the travel move crosses the contours.
+    pub fn route(&self, from: Vertex<S>, to: Vertex<S>) -> Option<Vec<Vertex<S>>> {
+        let island = self
+            .islands
+            .iter()
+            .find(|island| inside(&island.polygons, from))?;
+        if !inside(&island.polygons, to) {
+            return None;
+        }
+        return island.shortest_path(from, to);
+    }
+}
+
+impl<S: BaseFloat> Island<S> {
+    fn new(polygons: Vec<Polygon<S>>, waypoints: Vec<Vertex<S>>) -> Island<S> {
+        let edges = polygons
+            .iter()
+            .flat_map(|p| p.edges())
+            .map(|(start, end)| Edge {
+                start,
+                end,
+                min: Point2::new(start.x.min(end.x), start.y.min(end.y)),
+                max: Point2::new(start.x.max(end.x), start.y.max(end.y)),
+            })
+            .collect();
+        let mut island = Island {
+            polygons,
+            edges,
+            waypoints,
+            graph: vec![],
+        };
+        // the graph is built once, each travel move only adds its two ends
+        let mut graph = vec![Vec::new(); island.waypoints.len()];
+        for i in 0..island.waypoints.len() {
+            for j in i + 1..island.waypoints.len() {
+                let (a, b) = (island.waypoints[i], island.waypoints[j]);
+                if island.visible(a, b) {
+                    let d = (b - a).magnitude();
+                    graph[i].push((j, d));
+                    graph[j].push((i, d));
+                }
+            }
+        }
+        island.graph = graph;
+        return island;
+    }
+
+    /// true if the straight line stays inside of the island
+    fn visible(&self, a: Vertex<S>, b: Vertex<S>) -> bool {
+        if a == b {
+            return true;
+        }
+        let (min, max) = (
+            Point2::new(a.x.min(b.x), a.y.min(b.y)),
+            Point2::new(a.x.max(b.x), a.y.max(b.y)),
+        );
+        // only edges whose bounding box meets the one of the line can cross it
+        let crossing = self
+            .edges
+            .iter()
+            .filter(|e| {
+                e.min.x <= max.x && min.x <= e.max.x && e.min.y <= max.y && min.y <= e.max.y
+            })
+            .any(|e| segments_cross(a, b, e.start, e.end));
+        return !crossing && inside(&self.polygons, a + (b - a) * scalar(0.5));
+    }
+
+    /// A* search over the visibility graph of the waypoints together with the start and
+    /// the goal
+    fn shortest_path(&self, from: Vertex<S>, to: Vertex<S>) -> Option<Vec<Vertex<S>>> {
+        if self.visible(from, to) {
+            return Some(vec![from, to]);
+        }
+        // the waypoints followed by the start and the goal
+        let mut nodes = self.waypoints.clone();
+        nodes.push(from);
+        nodes.push(to);
+        let (start, goal) = (nodes.len() - 2, nodes.len() - 1);
+        let visible_from = |p: Vertex<S>| -> Vec<(usize, S)> {
+            return self
+                .waypoints
+                .iter()
+                .enumerate()
+                .filter(|(_, w)| self.visible(p, **w))
+                .map(|(i, w)| (i, (*w - p).magnitude()))
+                .collect();
+        };
+        let from_start = visible_from(from);
+        let mut to_goal = vec![None; self.waypoints.len()];
+        for (i, d) in visible_from(to) {
+            to_goal[i] = Some(d);
+        }
+
+        let mut distance = vec![S::infinity(); nodes.len()];
+        let mut previous: Vec<Option<usize>> = vec![None; nodes.len()];
+        let mut done = vec![false; nodes.len()];
+        let mut queue = BinaryHeap::new();
+        distance[start] = S::zero();
+        queue.push(Node {
+            estimate: (to - from).magnitude(),
+            index: start,
+        });
+        while let Some(Node { index, .. }) = queue.pop() {
+            if index == goal {
+                let mut route = vec![nodes[goal]];
+                let mut current = goal;
+                while let Some(p) = previous[current] {
+                    route.push(nodes[p]);
+                    current = p;
+                }
+                route.reverse();
+                return Some(route);
+            }
+            if done[index] {
+                continue;
+            }
+            done[index] = true;
+            let neighbours: Vec<(usize, S)> = if index == start {
+                from_start.clone()
+            } else {
+                let mut n = self.graph[index].clone();
+                n.extend(to_goal[index].map(|d| (goal, d)));
+                n
+            };
+            for (next, step) in neighbours {
+                if done[next] {
+                    continue;
+                }
+                let d = distance[index] + step;
+                if d < distance[next] {
+                    distance[next] = d;
+                    previous[next] = Some(index);
+                    queue.push(Node {
+                        estimate: d + (to - nodes[next]).magnitude(),
+                        index: next,
+                    });
+                }
+            }
+        }
+        return None;
+    }
+}
+
+/// an entry of the A* queue, the node with the smallest estimate comes first
+struct Node<S> {
+    estimate: S,
+    index: usize,
+}
+
+impl<S: BaseFloat> PartialEq for Node<S> {
+    fn eq(&self, other: &Self) -> bool {
+        return self.estimate == other.estimate;
+    }
+}
+
+impl<S: BaseFloat> Eq for Node<S> {}
+
+impl<S: BaseFloat> PartialOrd for Node<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl<S: BaseFloat> Ord for Node<S> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        return other
+            .estimate
+            .partial_cmp(&self.estimate)
+            .unwrap_or(Ordering::Equal);
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// a closed counter clockwise polygon with outward normals
+    fn polygon(corners: &[(f32, f32)]) -> Polygon {
+        let mut points: Vec<Vertex> = corners.iter().map(|(x, y)| Point2::new(*x, *y)).collect();
+        points.push(points[0]);
+        let normals = points
+            .windows(2)
+            .map(|e| Vector2::new(e[1].y - e[0].y, e[0].x - e[1].x).normalize())
+            .collect();
+        return Polygon {
+            points,
+            normals,
+            material: 0,
+        };
+    }
+
+    /// a U open to the top and a square beside it
+    pub fn u_and_square() -> Layer {
+        let u = [
+            (0., 0.),
+            (30., 0.),
+            (30., 30.),
+            (20., 30.),
+            (20., 10.),
+            (10., 10.),
+            (10., 30.),
+            (0., 30.),
+        ];
+        return Layer {
+            height: 0.1,
+            thickness: 0.2,
+            polygons: vec![
+                polygon(&u),
+                polygon(&[(40., 0.), (50., 0.), (50., 10.), (40., 10.)]),
+            ],
+        };
+    }
+
+    #[test]
+    fn routes_go_around_the_gap() {
+        let layer = u_and_square();
+        let comber = Comber::new(&layer, 0.5);
+        let (from, to) = (Point2::new(5., 25.), Point2::new(25., 25.));
+        let route = comber.route(from, to).unwrap();
+        assert_eq!(route.first(), Some(&from));
+        assert_eq!(route.last(), Some(&to));
+        assert!(route.len() > 2);
+        for step in route.windows(2) {
+            for (start, end) in layer.polygons.iter().flat_map(|p| p.edges()) {
+                assert!(!segments_cross(step[0], step[1], start, end));
+            }
+            assert!(inside(&layer.polygons, step[0] + (step[1] - step[0]) * 0.5));
+        }
+        // the way down the arms and across the bottom is about 50 mm
+        let length: f32 = route.windows(2).map(|s| (s[1] - s[0]).magnitude()).sum();
+        assert!(length < 52., "{}", length);
+
+        // visible points are connected directly
+        let straight = comber.route(Point2::new(5., 5.), Point2::new(25., 5.));
+        assert_eq!(straight.map(|r| r.len()), Some(2));
+        // there is no way to another island or outside of the layer
+        assert_eq!(comber.route(from, Point2::new(45., 5.)), None);
+        assert_eq!(comber.route(from, Point2::new(15., 25.)), None);
+    }
+}
@@ -0,0 +1,265 @@
+//! Writes slices as 2D drawings in the ASCII DXF format of AutoCAD R12.
+//!
+//! Every closed polygon becomes a `POLYLINE` entity with one `VERTEX` per point.
+//! R12 has no lightweight polylines, but it is the version understood by nearly all
+//! CAD and laser cutting software.
+//!
+//! The y axis of a drawing points up while the one of the slices points down in the svg
+//! drawings and the layer images, so y is negated to show the slices the same way.
+
+use crate::model::Layer;
+use crate::polygons::Polygon;
+use crate::profile::Profile;
+use serde::Deserialize;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// Unit of the coordinates in the drawing, the slices are always in mm. R12 files cannot
+/// name their unit, so the program reading them has to be told which one is used.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Units {
+    Millimeters,
+    Centimeters,
+    Meters,
+    Inches,
+}
+
+impl Units {
+    /// number of mm per unit
+    fn size(&self) -> f32 {
+        return match self {
+            Units::Millimeters => 1.,
+            Units::Centimeters => 10.,
+            Units::Meters => 1000.,
+            Units::Inches => 25.4,
+        };
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DxfSettings {
+    pub units: Units,
+    /// the dxf layer of a slice is named after this prefix and the index of the slice
+    pub layer_prefix: String,
+    /// places each slice at its height instead of at zero
+    pub elevation: bool,
+}
+
+impl Default for DxfSettings {
+    fn default() -> Self {
+        return DxfSettings {
+            units: Units::Millimeters,
+            layer_prefix: "slice_".to_string(),
+            elevation: false,
+        };
+    }
+}
+
+impl DxfSettings {
+    pub fn from_profile(profile: &Profile) -> DxfSettings {
+        return DxfSettings {
+            units: profile.export.dxf_units,
+            elevation: profile.export.dxf_elevation,
+            ..DxfSettings::default()
+        };
+    }
+}
+
+/// writes all slices into one drawing, each slice on its own dxf layer
+pub fn write_dxf<W: Write>(w: &mut W, layers: &[Layer], settings: &DxfSettings) -> io::Result<()> {
+    let indexed: Vec<(usize, &Layer)> = layers.iter().enumerate().collect();
+    return write_drawing(w, &indexed, settings);
+}
+
+/// writes one drawing per slice into the directory, which is created if needed.
+/// The files are named after the index of the slice, e.g. `00042.dxf`
+pub fn write_layer_files(layers: &[Layer], settings: &DxfSettings, dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for (i, layer) in layers.iter().enumerate() {
+        let mut file = BufWriter::new(File::create(dir.join(format!("{:05}.dxf", i)))?);
+        write_drawing(&mut file, &[(i, layer)], settings)?;
+        file.flush()?;
+    }
+    return Ok(());
+}
+
+fn write_drawing<W: Write>(
+    w: &mut W,
+    layers: &[(usize, &Layer)],
+    settings: &DxfSettings,
+) -> io::Result<()> {
+    let scale = 1. / settings.units.size();
+    let name = |index: usize| format!("{}{:05}", settings.layer_prefix, index);
+
+    section(w, "HEADER")?;
+    pair(w, 9, "$ACADVER")?;
+    pair(w, 1, "AC1009")?;
+    pair(w, 0, "ENDSEC")?;
+
+    section(w, "TABLES")?;
+    pair(w, 0, "TABLE")?;
+    pair(w, 2, "LTYPE")?;
+    pair(w, 70, 1)?;
+    pair(w, 0, "LTYPE")?;
+    pair(w, 2, "CONTINUOUS")?;
+    pair(w, 70, 0)?;
+    pair(w, 3, "Solid line")?;
+    pair(w, 72, 65)?;
+    pair(w, 73, 0)?;
+    pair(w, 40, 0.)?;
+    pair(w, 0, "ENDTAB")?;
+    pair(w, 0, "TABLE")?;
+    pair(w, 2, "LAYER")?;
+    pair(w, 70, layers.len())?;
+    for (index, _) in layers {
+        pair(w, 0, "LAYER")?;
+        pair(w, 2, name(*index))?;
+        pair(w, 70, 0)?;
+        pair(w, 62, 7)?;
+        pair(w, 6, "CONTINUOUS")?;
+    }
+    pair(w, 0, "ENDTAB")?;
+    pair(w, 0, "ENDSEC")?;
+
+    section(w, "ENTITIES")?;
+    for (index, layer) in layers {
+        let elevation = if settings.elevation {
+            layer.height * scale
+        } else {
+            0.
+        };
+        for polygon in &layer.polygons {
+            polyline(w, polygon, &name(*index), elevation, scale)?;
+        }
+    }
+    pair(w, 0, "ENDSEC")?;
+    pair(w, 0, "EOF")?;
+    return Ok(());
+}
+
+/// a closed polyline, the repeated last point of the polygon is left out
+fn polyline<W: Write>(
+    w: &mut W,
+    polygon: &Polygon,
+    layer: &str,
+    elevation: f32,
+    scale: f32,
+) -> io::Result<()> {
+    let mut points = polygon.points.as_slice();
+    if points.len() > 1 && points.first() == points.last() {
+        points = &points[..points.len() - 1];
+    }
+    if points.len() < 2 {
+        return Ok(());
+    }
+    pair(w, 0, "POLYLINE")?;
+    pair(w, 8, layer)?;
+    // vertices follow
+    pair(w, 66, 1)?;
+    pair(w, 10, 0.)?;
+    pair(w, 20, 0.)?;
+    pair(w, 30, elevation)?;
+    // closed
+    pair(w, 70, 1)?;
+    for p in points {
+        pair(w, 0, "VERTEX")?;
+        pair(w, 8, layer)?;
+        pair(w, 10, p.x * scale)?;
+        pair(w, 20, -p.y * scale)?;
+        pair(w, 30, elevation)?;
+    }
+    pair(w, 0, "SEQEND")?;
+    pair(w, 8, layer)?;
+    return Ok(());
+}
+
+fn section<W: Write>(w: &mut W, name: &str) -> io::Result<()> {
+    pair(w, 0, "SECTION")?;
+    return pair(w, 2, name);
+}
+
+/// a group code followed by its value, each on its own line
+fn pair<W: Write, T: Display>(w: &mut W, code: u32, value: T) -> io::Result<()> {
+    return write!(w, "{:>3}\n{}\n", code, value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Point2;
+
+    /// the layer and the points of every polyline of a drawing
+    fn read_polylines(dxf: &str) -> Vec<(String, Vec<[f32; 3]>)> {
+        let lines: Vec<&str> = dxf.lines().collect();
+        assert_eq!(lines.len() % 2, 0);
+        let pairs: Vec<(u32, &str)> = lines
+            .chunks(2)
+            .map(|p| (p[0].trim().parse().unwrap(), p[1]))
+            .collect();
+        let mut polylines: Vec<(String, Vec<[f32; 3]>)> = Vec::new();
+        let mut entity = "";
+        for (code, value) in pairs {
+            match (code, entity) {
+                (0, _) => {
+                    entity = value;
+                    if value == "POLYLINE" {
+                        polylines.push((String::new(), vec![]));
+                    } else if value == "VERTEX" {
+                        polylines.last_mut().unwrap().1.push([0.; 3]);
+                    }
+                }
+                (8, "POLYLINE") => polylines.last_mut().unwrap().0 = value.to_string(),
+                (10, "VERTEX") | (20, "VERTEX") | (30, "VERTEX") => {
+                    let point = polylines.last_mut().unwrap().1.last_mut().unwrap();
+                    point[(code / 10 - 1) as usize] = value.parse().unwrap();
+                }
+                _ => {}
+            }
+        }
+        return polylines;
+    }
+
+    fn layers() -> Vec<Layer> {
+        return (0..2)
+            .map(|i| Layer {
+                height: 1. + i as f32,
+                thickness: 1.,
+                polygons: vec![Polygon::rectangle(
+                    Point2::new(0., 0.),
+                    Point2::new(25.4, 50.8 + i as f32 * 25.4),
+                )],
+            })
+            .collect();
+    }
+
+    #[test]
+    fn slices_round_trip() {
+        let settings = DxfSettings {
+            units: Units::Inches,
+            elevation: true,
+            ..DxfSettings::default()
+        };
+        let mut dxf = Vec::new();
+        write_dxf(&mut dxf, &layers(), &settings).unwrap();
+        let dxf = String::from_utf8(dxf).unwrap();
+        assert!(dxf.ends_with("  0\nEOF\n"));
+
+        let polylines = read_polylines(&dxf);
+        assert_eq!(polylines.len(), 2);
+        for (i, ((name, points), layer)) in polylines.iter().zip(layers()).enumerate() {
+            assert_eq!(*name, format!("slice_{:05}", i));
+            let polygon = &layer.polygons[0];
+            // the repeated last point is left out
+            assert_eq!(points.len(), polygon.points.len() - 1);
+            for (read, p) in points.iter().zip(&polygon.points) {
+                // in inches, y negated and at the height of the slice
+                assert!((read[0] - p.x / 25.4).abs() < 1e-5);
+                assert!((read[1] + p.y / 25.4).abs() < 1e-5);
+                assert!((read[2] - layer.height / 25.4).abs() < 1e-5);
+            }
+        }
+    }
+}
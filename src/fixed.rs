@@ -0,0 +1,404 @@
+//! Fixed point coordinates for the slices.
+//!
+//! Floats round differently depending on the order of the operations, so points which
+//! should be the same often differ in their last bits and comparing them fails. Fixed point
+//! coordinates are integer nanometres, which makes comparisons and predicates exact and
+//! the results the same on every machine. `i64` reaches thousands of kilometres, and the
+//! products of two coordinates needed by the predicates fit into `i128`.
+//!
+//! Offsets are computed on the grid directly. Cleaning, simplifying and placing seams reuse
+//! the float implementations on the grid units stored as doubles, which hold every unit
+//! below 2^53 exactly, so their exact predicates decide on the grid points themselves. Only
+//! new crossings are rounded back to the grid.
+
+use crate::intersect;
+use crate::model::Layer;
+use crate::polygons::{AbstractPolygon, FillRule, Normal, Polygon, Vertex};
+use crate::predicates::Orientation;
+use crate::seam::{self, SeamPosition};
+use crate::simplify::{self, Simplification};
+use crate::toolpath::{infill, with_travel, Path, PathKind, ToolpathSettings};
+use cgmath::*;
+
+pub type FixedVertex = Point2<i64>;
+pub type FixedPolygon = AbstractPolygon<FixedVertex>;
+
+/// grid units per millimetre, one unit is a nanometre
+pub const UNITS_PER_MM: f64 = 1e6;
+
+/// millimetres as grid units, rounded to the closest one
+pub fn to_units(mm: f64) -> i64 {
+    return (mm * UNITS_PER_MM).round() as i64;
+}
+
+pub fn to_float(p: FixedVertex) -> Vertex {
+    return Point2::new(
+        (p.x as f64 / UNITS_PER_MM) as f32,
+        (p.y as f64 / UNITS_PER_MM) as f32,
+    );
+}
+
+/// a layer with fixed point coordinates, see `Layer`
+#[derive(Debug, Clone)]
+pub struct FixedLayer {
+    pub height: f32,
+    pub thickness: f32,
+    pub polygons: Vec<FixedPolygon>,
+}
+
+impl Layer {
+    pub fn to_fixed(&self) -> FixedLayer {
+        return FixedLayer {
+            height: self.height,
+            thickness: self.thickness,
+            polygons: self.polygons.iter().map(|p| p.to_fixed()).collect(),
+        };
+    }
+}
+
+impl FixedLayer {
+    pub fn to_layer(&self) -> Layer {
+        return Layer {
+            height: self.height,
+            thickness: self.thickness,
+            polygons: self.polygons.iter().map(|p| p.to_polygon()).collect(),
+        };
+    }
+
+    /// the layer in grid units, see `from_units`
+    fn to_units(&self) -> Layer<f64> {
+        return Layer {
+            height: self.height as f64,
+            thickness: self.thickness as f64,
+            polygons: self.polygons.iter().map(|p| p.to_units()).collect(),
+        };
+    }
+
+    /// the layer from grid units, points between the grid points are rounded to the closest
+    fn from_units(layer: &Layer<f64>) -> FixedLayer {
+        return FixedLayer {
+            height: layer.height as f32,
+            thickness: layer.thickness as f32,
+            polygons: layer
+                .polygons
+                .iter()
+                .map(|p| p.snap(|v| Point2::new(v.x.round() as i64, v.y.round() as i64)))
+                .collect(),
+        };
+    }
+
+    /// resolves crossings and overlaps like `intersect::clean`
+    pub fn clean(&self, rule: FillRule) -> FixedLayer {
+        let units = self.to_units();
+        return FixedLayer::from_units(&Layer {
+            height: units.height,
+            thickness: units.thickness,
+            polygons: intersect::clean(&units.polygons, rule),
+        });
+    }
+
+    /// Generates the paths of the layer like `toolpath::layer_toolpaths`. The perimeters and
+    /// the area of the infill are offset on the grid, only the paths have float coordinates.
+    pub fn toolpaths(&self, index: usize, settings: &ToolpathSettings) -> Vec<Path> {
+        let w = settings.line_width as f64;
+        let mut materials: Vec<usize> = self.polygons.iter().map(|p| p.material).collect();
+        materials.sort_unstable();
+        materials.dedup();
+
+        let mut paths: Vec<Path> = Vec::new();
+        for material in materials {
+            let polygons: Vec<FixedPolygon> = self
+                .polygons
+                .iter()
+                .filter(|p| p.material == material)
+                .cloned()
+                .collect();
+            for wall in 0..settings.walls {
+                let distance = to_units(-(wall as f64 + 0.5) * w);
+                paths.extend(offset_area(&polygons, distance).iter().map(|p| Path {
+                    kind: PathKind::Perimeter,
+                    points: p.to_polygon().points,
+                    material,
+                }));
+            }
+            if settings.infill > 0. {
+                let area: Vec<Polygon> =
+                    offset_area(&polygons, to_units(-(settings.walls as f64) * w))
+                        .iter()
+                        .map(|p| p.to_polygon())
+                        .collect();
+                paths.extend(infill(
+                    &area,
+                    settings.line_width / settings.infill.min(1.),
+                    index % 2 == 1,
+                    material,
+                ));
+            }
+        }
+        return with_travel(paths);
+    }
+}
+
+/// simplifies the layers like `simplify::simplify_layers`, `tolerance` is given in units
+pub fn simplify_layers(layers: &mut [FixedLayer], tolerance: i64, method: Simplification) {
+    let mut units: Vec<Layer<f64>> = layers.iter().map(|l| l.to_units()).collect();
+    simplify::simplify_layers(&mut units, tolerance as f64, method);
+    for (layer, simplified) in layers.iter_mut().zip(units.iter()) {
+        *layer = FixedLayer::from_units(simplified);
+    }
+}
+
+/// starts the closed polygons at their seam like `seam::place_seams`
+pub fn place_seams(layers: &mut [FixedLayer], position: SeamPosition) {
+    let mut units: Vec<Layer<f64>> = layers.iter().map(|l| l.to_units()).collect();
+    seam::place_seams(&mut units, position);
+    for (layer, placed) in layers.iter_mut().zip(units.iter()) {
+        *layer = FixedLayer::from_units(placed);
+    }
+}
+
+impl<S: BaseFloat> Polygon<S> {
+    /// The polygon on the grid. Points which end up on the same grid point are merged,
+    /// the lines between them vanish together with their normals.
+    pub fn to_fixed(&self) -> FixedPolygon {
+        return self.snap(|p| {
+            Point2::new(
+                to_units(p.x.to_f64().unwrap()),
+                to_units(p.y.to_f64().unwrap()),
+            )
+        });
+    }
+
+    /// the polygon with every point moved to the grid point `grid` returns for it
+    fn snap(&self, grid: impl Fn(Vertex<S>) -> FixedVertex) -> FixedPolygon {
+        let normal = |n: &Normal<S>| n.cast::<f32>().unwrap();
+        let mut points: Vec<FixedVertex> = Vec::with_capacity(self.points.len());
+        let mut normals: Vec<Normal> = Vec::with_capacity(self.normals.len());
+        for (i, p) in self.points.iter().enumerate() {
+            let p = grid(*p);
+            if points.last() == Some(&p) {
+                continue;
+            }
+            if i > 0 {
+                normals.push(normal(&self.normals[i - 1]));
+            }
+            points.push(p);
+        }
+        // polygons can have a normal for the line back to their first point
+        if !self.points.is_empty() && self.normals.len() >= self.points.len() {
+            normals.extend(self.normals[self.points.len() - 1..].iter().map(normal));
+        }
+        return FixedPolygon {
+            points,
+            normals,
+            material: self.material,
+        };
+    }
+}
+
+impl FixedPolygon {
+    pub fn to_polygon(&self) -> Polygon {
+        return Polygon {
+            points: self.points.iter().map(|p| to_float(*p)).collect(),
+            normals: self.normals.clone(),
+            material: self.material,
+        };
+    }
+
+    /// the polygon in grid units, which doubles hold exactly
+    fn to_units(&self) -> Polygon<f64> {
+        return Polygon {
+            points: self
+                .points
+                .iter()
+                .map(|p| Point2::new(p.x as f64, p.y as f64))
+                .collect(),
+            normals: self.normals.iter().map(|n| n.cast().unwrap()).collect(),
+            material: self.material,
+        };
+    }
+
+    /// twice the signed area in square units, positive if the points are ordered counter
+    /// clockwise
+    pub fn area2(&self) -> i128 {
+        return self
+            .edges()
+            .map(|(a, b)| a.x as i128 * b.y as i128 - b.x as i128 * a.y as i128)
+            .sum();
+    }
+
+    /// checks if the point lies inside of the closed polygon, exact
+    pub fn contains(&self, point: FixedVertex) -> bool {
+        return inside(std::slice::from_ref(self), point);
+    }
+
+    /// Moves every line of the closed polygon by `distance` units away from the area it
+    /// encloses, like `Polygon::offset`. The corners are computed in double precision and
+    /// rounded to the grid, so the result only depends on the input.
+    pub fn offset(&self, distance: i64) -> Option<FixedPolygon> {
+        let area = self.area2();
+        if area == 0 {
+            return None;
+        }
+        // direction of the outside, relative to the walking direction
+        let outside = if area > 0 { 1. } else { -1. };
+        let distance_f = distance as f64;
+
+        // drop lines without length
+        let edges: Vec<(FixedVertex, FixedVertex, Normal)> = self
+            .edges()
+            .zip(self.normals.iter())
+            .filter(|((a, b), _)| a != b)
+            .map(|((a, b), n)| (a, b, *n))
+            .collect();
+        if edges.len() < 3 {
+            return None;
+        }
+
+        let perpendicular = |(a, b, _): &(FixedVertex, FixedVertex, Normal)| {
+            let d = Vector2::new((b.x - a.x) as f64, (b.y - a.y) as f64).normalize();
+            return Vector2::new(d.y, -d.x) * outside;
+        };
+        let moved = |corner: FixedVertex, offset: Vector2<f64>| {
+            return Point2::new(
+                corner.x + (offset.x * distance_f).round() as i64,
+                corner.y + (offset.y * distance_f).round() as i64,
+            );
+        };
+        let float = |n: Vector2<f64>| Vector2::new(n.x as f32, n.y as f32);
+
+        let mut points: Vec<FixedVertex> = Vec::new();
+        let mut normals: Vec<Normal> = Vec::new();
+        for i in 0..edges.len() {
+            let previous = &edges[(i + edges.len() - 1) % edges.len()];
+            let current = &edges[i];
+            let (n1, n2) = (perpendicular(previous), perpendicular(current));
+            let corner = current.0;
+            let cos = n1.dot(n2);
+            // the normal of the new line keeps the orientation of the original one
+            let normal = if float(n2).dot(current.2) < 0. {
+                -float(n2)
+            } else {
+                float(n2)
+            };
+            // miters longer than twice the distance are cut off
+            if cos > -0.5 {
+                points.push(moved(corner, (n1 + n2) / (1. + cos)));
+                normals.push(normal);
+            } else {
+                let bevel = if float(n1).dot(previous.2) < 0. {
+                    -float(n1)
+                } else {
+                    float(n1)
+                };
+                points.push(moved(corner, n1));
+                normals.push(bevel.lerp(normal, 0.5).normalize());
+                points.push(moved(corner, n2));
+                normals.push(normal);
+            }
+        }
+        points.push(points[0]);
+
+        let result = FixedPolygon {
+            points,
+            normals,
+            material: self.material,
+        };
+        let new_area = result.area2();
+        if new_area.signum() != area.signum() || (distance < 0 && new_area.abs() > area.abs()) {
+            return None;
+        }
+        return Some(result);
+    }
+}
+
+/// the side of the line from `a` to `b` that `c` is on, exact
+pub fn orient(a: FixedVertex, b: FixedVertex, c: FixedVertex) -> Orientation {
+    let cross =
+        (b.x - a.x) as i128 * (c.y - a.y) as i128 - (b.y - a.y) as i128 * (c.x - a.x) as i128;
+    return match cross.signum() {
+        1 => Orientation::CounterClockwise,
+        -1 => Orientation::Clockwise,
+        _ => Orientation::Collinear,
+    };
+}
+
+/// checks if the point lies inside of an odd number of the closed polygons
+pub fn inside(polygons: &[FixedPolygon], point: FixedVertex) -> bool {
+    let crossings = polygons
+        .iter()
+        .flat_map(|p| p.edges())
+        .filter(|(a, b)| {
+            // edges going up count if the point is left of them, edges going down if it
+            // is right of them, which is the same crossing of the ray to the right
+            if a.y <= point.y && point.y < b.y {
+                return orient(*a, *b, point) == Orientation::CounterClockwise;
+            }
+            if b.y <= point.y && point.y < a.y {
+                return orient(*a, *b, point) == Orientation::Clockwise;
+            }
+            return false;
+        })
+        .count();
+    return crossings % 2 == 1;
+}
+
+/// marks the polygons that are holes, which are the ones inside of an odd number of others
+pub fn holes(polygons: &[FixedPolygon]) -> Vec<bool> {
+    return polygons
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let containing = polygons
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| *j != i && other.contains(p.points[0]))
+                .count();
+            containing % 2 == 1
+        })
+        .collect();
+}
+
+/// Offsets the area described by the polygons by `distance` units, see
+/// `polygons::offset_area`.
+pub fn offset_area(polygons: &[FixedPolygon], distance: i64) -> Vec<FixedPolygon> {
+    return polygons
+        .iter()
+        .zip(holes(polygons))
+        .filter_map(|(p, hole)| p.offset(if hole { -distance } else { distance }))
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(min: f32, max: f32) -> FixedPolygon {
+        return Polygon::rectangle(Point2::new(min, min), Point2::new(max, max)).to_fixed();
+    }
+
+    #[test]
+    fn clean_on_the_grid() {
+        let layer = FixedLayer {
+            height: 0.,
+            thickness: 0.2,
+            polygons: vec![square(0., 2.), square(1., 3.)],
+        };
+        let cleaned = layer.clean(FillRule::NonZero);
+        assert_eq!(cleaned.polygons.len(), 1);
+        // 7 mm² in square nanometres, twice
+        assert_eq!(cleaned.polygons[0].area2(), 14_000_000_000_000);
+        let hole = FixedLayer {
+            height: 0.,
+            thickness: 0.2,
+            polygons: vec![square(0., 3.), square(1., 2.)],
+        };
+        let areas: Vec<i128> = hole
+            .clean(FillRule::EvenOdd)
+            .polygons
+            .iter()
+            .map(|p| p.area2())
+            .collect();
+        assert_eq!(areas.iter().sum::<i128>(), 16_000_000_000_000);
+    }
+}
@@ -0,0 +1,504 @@
+//! Writes the toolpaths as g-code for printers running Marlin or a compatible firmware.
+//!
+//! Extrusion is relative (`M83`). Materials are printed by the tools given in the
+//! `ToolSettings`, a tool change is emitted at the start of the first path of a new tool,
+//! surrounded by the change scripts and followed by purging the new tool.
+
+use crate::arcs::{fit_arcs, Segment};
+use crate::comb::Comber;
+use crate::model::Layer;
+use crate::polygons::scalar;
+use crate::profile::Profile;
+use crate::toolpath::{Path, PathKind};
+use crate::tools::ToolSettings;
+use cgmath::*;
+use std::f64::consts::PI;
+use std::fmt::Display;
+use std::io::{self, Write};
+
+/// lifting the nozzle during travel moves, so it does not hit printed parts
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZHop<S = f32> {
+    Never,
+    /// lifts by the given height (mm) during travel moves with retraction
+    Retracted(S),
+    /// lifts by the given height (mm) during every travel move
+    Always(S),
+}
+
+#[derive(Debug, Clone)]
+pub struct GcodeSettings<S = f32> {
+    /// speed of extruding moves (mm/s)
+    pub print_speed: S,
+    /// speed of travel moves (mm/s)
+    pub travel_speed: S,
+    pub line_width: S,
+    /// thickness of the first layer, the layers above it move up by its difference to the
+    /// thickness the first layer was sliced with
+    pub first_layer_height: S,
+    pub filament_diameter: S,
+    /// length the filament is pulled back before a travel move (mm)
+    pub retraction: S,
+    /// retraction speed (mm/s)
+    pub retraction_speed: S,
+    /// travel moves shorter than this do not retract
+    pub retraction_distance: S,
+    /// Distance kept from the contours by travel moves routed inside of the layer,
+    /// these moves do not retract. `None` travels in straight lines.
+    pub combing: Option<S>,
+    pub z_hop: ZHop<S>,
+    /// Extruded runs which follow a circle this closely are printed as `G2`/`G3` arcs,
+    /// `None` prints lines only, for firmware without arc support.
+    pub arc_tolerance: Option<S>,
+    /// nozzle temperature (°C)
+    pub temperature: S,
+    /// bed temperature (°C)
+    pub bed_temperature: S,
+    pub start_gcode: String,
+    pub end_gcode: String,
+    /// added to the x and z coordinates of the slices to place the model on the bed
+    pub origin: Vector2<S>,
+    pub tools: ToolSettings<S>,
+}
+
+impl<S: BaseFloat> Default for GcodeSettings<S> {
+    fn default() -> Self {
+        return GcodeSettings::from_profile(&Profile::default());
+    }
+}
+
+impl<S: BaseFloat> GcodeSettings<S> {
+    /// takes the settings from a profile, the model is placed in the center of the bed
+    pub fn from_profile(profile: &Profile) -> GcodeSettings<S> {
+        let printer = &profile.printer;
+        let material = &profile.material;
+        let print = &profile.print;
+        let value = |v: f32| scalar::<S>(v as f64);
+        let z_hop = if print.z_hop <= 0. {
+            ZHop::Never
+        } else if print.z_hop_always {
+            ZHop::Always(value(print.z_hop))
+        } else {
+            ZHop::Retracted(value(print.z_hop))
+        };
+        return GcodeSettings {
+            print_speed: scalar(40.),
+            travel_speed: scalar(120.),
+            line_width: value(printer.nozzle_diameter),
+            first_layer_height: value(print.first_layer_height),
+            filament_diameter: value(material.filament_diameter),
+            retraction: value(material.retraction),
+            retraction_speed: value(material.retraction_speed),
+            retraction_distance: scalar(2.),
+            combing: if print.combing {
+                Some(value(printer.nozzle_diameter))
+            } else {
+                None
+            },
+            z_hop,
+            arc_tolerance: if print.arc_tolerance > 0. {
+                Some(value(print.arc_tolerance))
+            } else {
+                None
+            },
+            temperature: value(material.temperature),
+            bed_temperature: value(material.bed_temperature),
+            start_gcode: printer.start_gcode.clone(),
+            end_gcode: printer.end_gcode.clone(),
+            origin: Vector2::new(value(printer.bed_size[0]), value(printer.bed_size[1]))
+                / scalar(2.),
+            tools: ToolSettings::from_profile(profile),
+        };
+    }
+
+    /// length of filament needed for a line of the given length in a layer of the given thickness
+    fn extrusion(&self, length: S, thickness: S) -> S {
+        let filament = scalar::<S>(PI) * (self.filament_diameter / scalar(2.)).powi(2);
+        return length * self.line_width * thickness / filament;
+    }
+}
+
+/// Writes a complete print. `toolpaths` holds the paths of every layer, as returned by
+/// `layer_toolpaths` or `plan_tools` for several tools. The first layer is printed on the
+/// bed, the slicing plane (x, z) is mapped to the x and y axes of the printer.
+pub fn write_gcode<S: BaseFloat + Display, W: Write>(
+    w: &mut W,
+    layers: &[Layer<S>],
+    toolpaths: &[Vec<Path<S>>],
+    settings: &GcodeSettings<S>,
+) -> io::Result<()> {
+    writeln!(w, "; layers: {}", layers.len())?;
+    writeln!(w, "M140 S{:.0}", settings.bed_temperature)?;
+    writeln!(w, "M104 S{:.0}", settings.temperature)?;
+    writeln!(w, "{}", settings.start_gcode)?;
+    writeln!(w, "M190 S{:.0}", settings.bed_temperature)?;
+    writeln!(w, "M109 S{:.0}", settings.temperature)?;
+    writeln!(w, "M83")?;
+
+    // layer heights are measured at the center of a layer, the nozzle moves to its top
+    let (bottom, raise) = match layers.first() {
+        Some(layer) => (
+            layer.height - layer.thickness / scalar(2.),
+            settings.first_layer_height - layer.thickness,
+        ),
+        None => (S::zero(), S::zero()),
+    };
+    let position = |v: &Point2<S>| *v + settings.origin;
+    let mut tool: Option<usize> = None;
+    // the position of the nozzle if it is known
+    let mut nozzle: Option<Point2<S>> = None;
+    for (i, (layer, paths)) in layers.iter().zip(toolpaths).enumerate() {
+        let z = layer.height + layer.thickness / scalar(2.) - bottom + raise;
+        let thickness = if i == 0 {
+            settings.first_layer_height
+        } else {
+            layer.thickness
+        };
+        writeln!(w, "; layer {}", i)?;
+        writeln!(
+            w,
+            "G0 Z{:.3} F{:.0}",
+            z,
+            settings.travel_speed * scalar(60.)
+        )?;
+        let comber = settings
+            .combing
+            .map(|clearance| Comber::new(layer, clearance));
+        for path in paths {
+            if path.points.is_empty() {
+                continue;
+            }
+            let next = settings.tools.tool(path.material);
+            // travel moves keep the old tool, the new one is purged where its first path
+            // starts, which is the prime tower if there is one
+            if tool != Some(next) && (tool.is_none() || path.kind != PathKind::Travel) {
+                tool_change(w, tool, next, settings)?;
+                tool = Some(next);
+                // the change scripts may move the nozzle
+                nozzle = None;
+            }
+            if path.kind == PathKind::Travel {
+                travel(w, &path.points, z, comber.as_ref(), settings)?;
+                nozzle = path.points.last().copied();
+                continue;
+            }
+            // after a travel move the nozzle is already there
+            if nozzle != Some(path.points[0]) {
+                let start = position(&path.points[0]);
+                writeln!(w, "G0 X{:.3} Y{:.3}", start.x, start.y)?;
+            }
+            let segments = match settings.arc_tolerance {
+                Some(tolerance) => fit_arcs(&path.points, tolerance),
+                None => path.points[1..].iter().map(|p| Segment::Line(*p)).collect(),
+            };
+            let mut current = path.points[0];
+            for segment in segments {
+                let end = position(&segment.end());
+                let e = settings.extrusion(segment.length(current), thickness);
+                let speed = settings.print_speed * scalar(60.);
+                match segment {
+                    Segment::Line(_) => {
+                        writeln!(w, "G1 X{:.3} Y{:.3} E{:.5} F{:.0}", end.x, end.y, e, speed)?
+                    }
+                    Segment::Arc {
+                        center, clockwise, ..
+                    } => {
+                        // the center is given relative to the start of the arc
+                        let offset = center - current;
+                        writeln!(
+                            w,
+                            "{} X{:.3} Y{:.3} I{:.3} J{:.3} E{:.5} F{:.0}",
+                            if clockwise { "G2" } else { "G3" },
+                            end.x,
+                            end.y,
+                            offset.x,
+                            offset.y,
+                            e,
+                            speed
+                        )?
+                    }
+                }
+                current = segment.end();
+            }
+            nozzle = Some(current);
+        }
+    }
+    writeln!(w, "{}", settings.end_gcode)?;
+    return Ok(());
+}
+
+/// A travel move, routed inside of the layer if combing is enabled. The filament is only
+/// retracted if the move cannot stay inside.
+fn travel<S: BaseFloat + Display, W: Write>(
+    w: &mut W,
+    points: &[Point2<S>],
+    z: S,
+    comber: Option<&Comber<S>>,
+    settings: &GcodeSettings<S>,
+) -> io::Result<()> {
+    let (from, to) = (points[0], points[points.len() - 1]);
+    let combed = comber.and_then(|c| c.route(from, to));
+    let route = combed.as_deref().unwrap_or(points);
+    let retract = combed.is_none()
+        && settings.retraction > S::zero()
+        && length(route) > settings.retraction_distance;
+    let hop = match settings.z_hop {
+        ZHop::Retracted(height) if retract => height,
+        ZHop::Always(height) => height,
+        _ => S::zero(),
+    };
+    let speed = settings.travel_speed * scalar(60.);
+
+    if retract {
+        retraction(w, -settings.retraction, settings)?;
+    }
+    if hop > S::zero() {
+        writeln!(w, "G0 Z{:.3} F{:.0}", z + hop, speed)?;
+    }
+    for p in &route[1..] {
+        let p = *p + settings.origin;
+        writeln!(w, "G0 X{:.3} Y{:.3} F{:.0}", p.x, p.y, speed)?;
+    }
+    if hop > S::zero() {
+        writeln!(w, "G0 Z{:.3} F{:.0}", z, speed)?;
+    }
+    if retract {
+        retraction(w, settings.retraction, settings)?;
+    }
+    return Ok(());
+}
+
+fn length<S: BaseFloat>(points: &[Point2<S>]) -> S {
+    return points
+        .iter()
+        .zip(points.iter().skip(1))
+        .fold(S::zero(), |sum, (a, b)| sum + (b - a).magnitude());
+}
+
+/// Selects the next tool. The first tool of the print is selected without the change scripts,
+/// as it is already heated and primed by the start g-code.
+fn tool_change<S: BaseFloat + Display, W: Write>(
+    w: &mut W,
+    previous: Option<usize>,
+    next: usize,
+    settings: &GcodeSettings<S>,
+) -> io::Result<()> {
+    let tools = &settings.tools;
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return writeln!(w, "T{}", next),
+    };
+    if tools.retraction > S::zero() {
+        retraction(w, -tools.retraction, settings)?;
+    }
+    writeln!(w, "{}", tools.script(&tools.change_before, previous, next))?;
+    writeln!(w, "T{}", next)?;
+    writeln!(w, "{}", tools.script(&tools.change_after, previous, next))?;
+    if tools.purge > S::zero() {
+        retraction(w, tools.purge, settings)?;
+    }
+    return Ok(());
+}
+
+/// pulls the filament back for negative lengths and pushes it forward for positive ones
+fn retraction<S: BaseFloat + Display, W: Write>(
+    w: &mut W,
+    length: S,
+    settings: &GcodeSettings<S>,
+) -> io::Result<()> {
+    return writeln!(
+        w,
+        "G1 E{:.5} F{:.0}",
+        length,
+        settings.retraction_speed * scalar(60.)
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adhesion::{add_raft, RaftSettings};
+    use crate::comb::tests::u_and_square;
+    use crate::polygons::Polygon;
+    use crate::toolpath::with_travel;
+    use crate::tools::{plan_tools, PrimeTower};
+
+    fn line(from: (f32, f32), to: (f32, f32)) -> Path {
+        return Path {
+            kind: PathKind::Perimeter,
+            points: vec![Point2::new(from.0, from.1), Point2::new(to.0, to.1)],
+            material: 0,
+        };
+    }
+
+    /// the values of a parameter, e.g. `Z`, in all lines starting with the command
+    fn values(gcode: &str, command: &str, parameter: char) -> Vec<f32> {
+        return gcode
+            .lines()
+            .filter(|l| l.starts_with(command))
+            .filter_map(|l| l.split(' ').find(|p| p.starts_with(parameter)))
+            .map(|p| p[1..].parse().unwrap())
+            .collect();
+    }
+
+    fn write(layers: &[Layer], toolpaths: &[Vec<Path>], settings: &GcodeSettings) -> String {
+        let mut out = Vec::new();
+        write_gcode(&mut out, layers, toolpaths, settings).unwrap();
+        return String::from_utf8(out).unwrap();
+    }
+
+    #[test]
+    fn layers_of_a_raft_have_their_own_thickness() {
+        let model = vec![Layer {
+            height: 0.1,
+            thickness: 0.2,
+            polygons: vec![Polygon::rectangle(
+                Point2::new(0., 0.),
+                Point2::new(10., 10.),
+            )],
+        }];
+        let layers = add_raft(&model, &RaftSettings::default());
+        let toolpaths = vec![vec![line((0., 0.), (10., 0.))]; layers.len()];
+        let settings = GcodeSettings {
+            arc_tolerance: None,
+            ..GcodeSettings::default()
+        };
+        let gcode = write(&layers, &toolpaths, &settings);
+        let z = values(&gcode, "G0 Z", 'Z');
+        assert_eq!(z, vec![0.3, 0.5, 0.7, 1.0]);
+        // the base layer is 1.5 times as thick as the others
+        let e = values(&gcode, "G1 X", 'E');
+        assert_eq!(e.len(), 4);
+        assert!((e[0] / e[1] - 1.5).abs() < 1e-3);
+        assert!((e[3] - e[1]).abs() < 1e-5);
+    }
+
+    #[test]
+    fn first_layer_height() {
+        let layers: Vec<Layer> = (0..3)
+            .map(|i| Layer {
+                height: 0.1 + 0.2 * i as f32,
+                thickness: 0.2,
+                polygons: vec![],
+            })
+            .collect();
+        let toolpaths = vec![vec![line((0., 0.), (10., 0.))]; layers.len()];
+        let settings = GcodeSettings {
+            first_layer_height: 0.3,
+            arc_tolerance: None,
+            ..GcodeSettings::default()
+        };
+        let gcode = write(&layers, &toolpaths, &settings);
+        assert_eq!(values(&gcode, "G0 Z", 'Z'), vec![0.3, 0.5, 0.7]);
+        let e = values(&gcode, "G1 X", 'E');
+        assert!((e[0] / e[1] - 1.5).abs() < 1e-3);
+        assert!((e[2] - e[1]).abs() < 1e-5);
+    }
+
+    #[test]
+    fn tools_are_changed_and_purged_on_the_prime_tower() {
+        // a square of each material, the second one printed by tool 1
+        let squares = [
+            Polygon::rectangle(Point2::new(0., 0.), Point2::new(10., 10.)),
+            Polygon {
+                material: 1,
+                ..Polygon::rectangle(Point2::new(0., 20.), Point2::new(10., 30.))
+            },
+        ];
+        let mut layers: Vec<Layer> = (0..2)
+            .map(|i| Layer {
+                height: 0.1 + 0.2 * i as f32,
+                thickness: 0.2,
+                polygons: squares.to_vec(),
+            })
+            .collect();
+        let paths: Vec<Path> = squares
+            .iter()
+            .map(|p| Path {
+                kind: PathKind::Perimeter,
+                points: p.points.clone(),
+                material: p.material,
+            })
+            .collect();
+        let mut settings = GcodeSettings {
+            combing: None,
+            arc_tolerance: None,
+            origin: Vector2::zero(),
+            ..GcodeSettings::default()
+        };
+        let tower = PrimeTower::beside(&layers, 10., 5.);
+        let footprint = tower.footprint();
+        settings.tools.prime_tower = Some(tower);
+        let toolpaths = plan_tools(&mut layers, &[paths.clone(), paths], &settings.tools, 0.4);
+        let gcode = write(&layers, &toolpaths, &settings);
+
+        let purge = format!(
+            "G1 E{:.5} F{:.0}",
+            settings.tools.purge,
+            settings.retraction_speed * 60.
+        );
+        let mut position = Point2::new(f32::NAN, f32::NAN);
+        let (mut changes, mut purges) = (0, 0);
+        for line in gcode.lines() {
+            let value = |parameter: char| {
+                line.split(' ')
+                    .find(|p| p.starts_with(parameter))
+                    .map(|p| p[1..].parse::<f32>().unwrap())
+            };
+            if let (Some(x), Some(y)) = (value('X'), value('Y')) {
+                position = Point2::new(x, y);
+            }
+            if line.starts_with('T') {
+                changes += 1;
+            }
+            if line == purge {
+                assert!(footprint.contains(position), "purged at {:?}", position);
+                purges += 1;
+            }
+        }
+        // the first tool is selected without a change, then each layer switches once
+        assert_eq!(changes, 3);
+        assert_eq!(purges, 2);
+    }
+
+    #[test]
+    fn travel_moves_are_combed_or_retracted_and_lifted() {
+        let layers = vec![u_and_square()];
+        // the arms of the U and the square beside it
+        let toolpaths = vec![with_travel(vec![
+            line((2., 25.), (8., 25.)),
+            line((22., 25.), (28., 25.)),
+            line((42., 5.), (48., 5.)),
+        ])];
+        let settings = GcodeSettings {
+            combing: Some(0.4),
+            z_hop: ZHop::Retracted(0.4),
+            arc_tolerance: None,
+            origin: Vector2::zero(),
+            ..GcodeSettings::default()
+        };
+        let gcode = write(&layers, &toolpaths, &settings);
+        let moves: Vec<&str> = gcode
+            .lines()
+            .skip_while(|l| !l.starts_with("; layer"))
+            .filter(|l| l.starts_with("G0") || l.starts_with("G1"))
+            .collect();
+        let position = |line: &str| line.starts_with("G1 X") || line.starts_with("G0 X");
+        let at = |x: &str| moves.iter().position(|l| l.contains(x)).unwrap();
+        // the ends of the lines
+        let (left, right, square) = (at("X8.000"), at("X28.000"), at("X48.000"));
+
+        // around the gap between the arms without retracting
+        assert!(moves[left + 1..right].iter().all(|l| position(l)));
+        assert!(right - left > 2);
+        // to the square with retraction and z hop
+        let hop: Vec<&str> = moves[right + 1..square].iter().map(|l| &l[..4]).collect();
+        assert_eq!(hop, vec!["G1 E", "G0 Z", "G0 X", "G0 Z", "G1 E"]);
+        assert_eq!(values(&gcode, "G0 Z", 'Z'), vec![0.3, 0.7, 0.3]);
+        // only the first path needs a move to its start, the travel moves end at the others
+        let starts = moves
+            .iter()
+            .filter(|l| l.starts_with("G0 X") && !l.contains('F'));
+        assert_eq!(starts.count(), 1);
+    }
+}
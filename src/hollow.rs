@@ -0,0 +1,270 @@
+use crate::intersect::{difference, intersection, union};
+use crate::model::{Layer, Slice};
+use crate::polygons::{inside, offset_area, scanline, Polygon, Vertex};
+use crate::profile::Profile;
+use cgmath::*;
+
+#[derive(Debug, Clone)]
+pub struct HollowSettings {
+    /// thickness of the remaining shell
+    pub wall: f32,
+    /// optional lattice filling the inside to support the shell
+    pub lattice: Option<LatticeSettings>,
+}
+
+impl Default for HollowSettings {
+    fn default() -> Self {
+        return HollowSettings {
+            wall: 2.,
+            lattice: None,
+        };
+    }
+}
+
+impl HollowSettings {
+    pub fn from_profile(profile: &Profile) -> HollowSettings {
+        let resin = &profile.resin;
+        return HollowSettings {
+            wall: resin.hollow_wall,
+            lattice: if resin.lattice_spacing > 0. {
+                Some(LatticeSettings {
+                    spacing: resin.lattice_spacing,
+                    thickness: resin.lattice_thickness,
+                })
+            } else {
+                None
+            },
+        };
+    }
+}
+
+/// a grid of vertical pillars connected by horizontal struts
+#[derive(Debug, Clone)]
+pub struct LatticeSettings {
+    /// distance between the pillars and between the struts
+    pub spacing: f32,
+    /// width of the pillars and struts
+    pub thickness: f32,
+}
+
+/// a vertical hole that lets resin drain out of the cavity
+#[derive(Debug, Clone)]
+pub struct DrainHole {
+    /// center of the hole in the slicing plane
+    pub position: Vertex,
+    pub diameter: f32,
+    /// height at which the hole starts
+    pub bottom: f32,
+    /// length of the hole
+    pub depth: f32,
+}
+
+/// Removes the inside of the layers, leaving a shell of the given wall thickness.
+/// The layers have to be sorted by height and are expected to be filled with the even-odd rule.
+///
+/// The cavity of a layer is its area shrunk by the wall thickness. To keep the top and bottom
+/// closed, it is clipped to the shrunk areas of all layers up to a wall thickness above and
+/// below. Layers closer than that to the bottom or top of the model stay solid.
+pub fn hollow(layers: &[Layer], settings: &HollowSettings) -> Vec<Layer> {
+    let (bottom, top) = match (layers.first(), layers.last()) {
+        (Some(first), Some(last)) => (
+            first.height - first.thickness / 2.,
+            last.height + last.thickness / 2.,
+        ),
+        _ => return vec![],
+    };
+    let wall = settings.wall;
+    let shrunk: Vec<Slice> = layers
+        .iter()
+        .map(|layer| offset_area(&layer.polygons, -wall))
+        .collect();
+
+    return layers
+        .iter()
+        .enumerate()
+        .map(|(i, layer)| {
+            if layer.height - wall < bottom || layer.height + wall > top {
+                return layer.clone();
+            }
+            // the heights are compared, so layers may have different thicknesses
+            let cavity = layers
+                .iter()
+                .zip(&shrunk)
+                .enumerate()
+                .filter(|(j, (other, _))| *j != i && (other.height - layer.height).abs() <= wall)
+                .fold(shrunk[i].clone(), |cavity, (_, (_, other))| {
+                    intersection(&cavity, other)
+                });
+            let mut polygons = difference(&layer.polygons, &cavity);
+            if let Some(lattice) = &settings.lattice {
+                let parts = lattice_cross_section(&cavity, layer.height, lattice);
+                polygons = union(&polygons, &intersection(&parts, &cavity));
+            }
+            return Layer {
+                height: layer.height,
+                thickness: layer.thickness,
+                polygons,
+            };
+        })
+        .collect();
+}
+
+/// cross-section of the lattice within the cavity at the given height
+fn lattice_cross_section(cavity: &[Polygon], height: f32, lattice: &LatticeSettings) -> Slice {
+    let points: Vec<Vertex> = cavity.iter().flat_map(|p| p.points.clone()).collect();
+    if points.is_empty() {
+        return vec![];
+    }
+    let w = lattice.thickness / 2.;
+    let spacing = lattice.spacing;
+    let min = points
+        .iter()
+        .fold(points[0], |m, p| Point2::new(m.x.min(p.x), m.y.min(p.y)));
+    let max = points
+        .iter()
+        .fold(points[0], |m, p| Point2::new(m.x.max(p.x), m.y.max(p.y)));
+    let grid = |from: f32, to: f32| {
+        let first = (from / spacing).ceil() as i32;
+        let last = (to / spacing).floor() as i32;
+        (first..=last).map(move |i| i as f32 * spacing)
+    };
+
+    // the parts overlap each other, they are merged by the caller
+    let mut parts: Slice = Vec::new();
+    // pillars, only where they fit completely into the cavity
+    for x in grid(min.x, max.x) {
+        for z in grid(min.y, max.y) {
+            let corners = [(-w, -w), (w, -w), (w, w), (-w, w)];
+            if corners
+                .iter()
+                .all(|(dx, dz)| inside(cavity, Point2::new(x + dx, z + dz)))
+            {
+                parts.push(Polygon::rectangle(
+                    Point2::new(x - w, z - w),
+                    Point2::new(x + w, z + w),
+                ));
+            }
+        }
+    }
+
+    // struts at every multiple of the spacing in height
+    if (height - (height / spacing).round() * spacing).abs() < w {
+        for z in grid(min.y, max.y) {
+            for (x0, x1) in inner_spans(&scanline(cavity, z), w) {
+                parts.push(Polygon::rectangle(
+                    Point2::new(x0, z - w),
+                    Point2::new(x1, z + w),
+                ));
+            }
+        }
+        // scan in z direction by swapping the coordinates
+        let swapped: Vec<Polygon> = cavity
+            .iter()
+            .map(|p| Polygon {
+                points: p.points.iter().map(|v| Point2::new(v.y, v.x)).collect(),
+                normals: p.normals.clone(),
+                material: p.material,
+            })
+            .collect();
+        for x in grid(min.x, max.x) {
+            for (z0, z1) in inner_spans(&scanline(&swapped, x), w) {
+                parts.push(Polygon::rectangle(
+                    Point2::new(x - w, z0),
+                    Point2::new(x + w, z1),
+                ));
+            }
+        }
+    }
+    return parts;
+}
+
+/// filled ranges of a scanline, shrunk by `margin` on both sides
+fn inner_spans(crossings: &[f32], margin: f32) -> Vec<(f32, f32)> {
+    return crossings
+        .chunks(2)
+        .filter(|c| c.len() == 2 && c[1] - c[0] > 2. * margin)
+        .map(|c| (c[0] + margin, c[1] - margin))
+        .collect();
+}
+
+/// Punches vertical drain holes into the layers.
+/// A hole is only cut where its center lies in solid material, inside of a cavity the
+/// layer is already open.
+pub fn drain(layers: &[Layer], holes: &[DrainHole], segments: usize) -> Vec<Layer> {
+    return layers
+        .iter()
+        .map(|layer| {
+            let circles: Slice = holes
+                .iter()
+                .filter(|hole| {
+                    let within =
+                        layer.height >= hole.bottom && layer.height <= hole.bottom + hole.depth;
+                    within && inside(&layer.polygons, hole.position)
+                })
+                .map(|hole| Polygon::circle(hole.position, hole.diameter / 2., segments))
+                .collect();
+            if circles.is_empty() {
+                return layer.clone();
+            }
+            return Layer {
+                height: layer.height,
+                thickness: layer.thickness,
+                polygons: difference(&layer.polygons, &circles),
+            };
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::tests::boxes;
+
+    #[test]
+    fn shell_is_closed_on_all_sides() {
+        let layers = boxes(&[([0., 0., 0.], [10., 10., 10.])]).layers(0.5, 0.01);
+        let hollowed = hollow(&layers, &HollowSettings::default());
+        let center = Point2::new(5., 5.);
+        for (layer, hollowed) in layers.iter().zip(&hollowed) {
+            let open = layer.height > 2. && layer.height < 8.;
+            assert_eq!(inside(&hollowed.polygons, center), !open);
+            assert!(inside(&hollowed.polygons, Point2::new(1., 5.)));
+        }
+    }
+
+    #[test]
+    fn cavity_is_clipped_below_a_step() {
+        // a narrow box on a wide one, below the step only the part under the narrow box
+        // has material above it
+        let layers = boxes(&[
+            ([0., 0., 0.], [20., 10., 10.]),
+            ([0., 10., 0.], [10., 20., 10.]),
+        ])
+        .layers(0.5, 0.01);
+        let hollowed = hollow(&layers, &HollowSettings::default());
+        let layer = hollowed
+            .iter()
+            .find(|l| (l.height - 9.25).abs() < 1e-4)
+            .unwrap();
+        assert!(!inside(&layer.polygons, Point2::new(5., 5.)));
+        assert!(inside(&layer.polygons, Point2::new(15., 5.)));
+    }
+
+    #[test]
+    fn drain_hole_through_the_bottom() {
+        let layers = boxes(&[([0., 0., 0.], [10., 10., 10.])]).layers(0.5, 0.01);
+        let hole = DrainHole {
+            position: Point2::new(5., 5.),
+            diameter: 2.,
+            bottom: 0.,
+            depth: 4.,
+        };
+        let drained = drain(&hollow(&layers, &HollowSettings::default()), &[hole], 16);
+        for layer in &drained {
+            assert_eq!(
+                inside(&layer.polygons, Point2::new(5., 5.)),
+                layer.height > 8.
+            );
+        }
+    }
+}
@@ -0,0 +1,324 @@
+//! Serializable form of a sliced model, used to exchange contours with other tools.
+//!
+//! The same schema is written as JSON or as CBOR, a compact binary encoding of the same
+//! data model. Every document starts with a `version` field, readers reject versions they
+//! do not know. Fields may be added without changing the version, readers ignore fields
+//! they do not know.
+//!
+//! ```text
+//! {
+//!   "version": 1,
+//!   "layer_height": 0.2,
+//!   "layers": [
+//!     {
+//!       "height": 0.1,
+//!       "contours": [
+//!         {
+//!           "hole": false,
+//!           "points": [[x, y], ...],    closed, the last point repeats the first one
+//!           "normals": [[x, y], ...],   one per edge, pointing out of the material
+//!           "material": 0               index of the material, 0 if missing
+//!         }
+//!       ]
+//!     }
+//!   ]
+//! }
+//! ```
+
+use crate::model::Layer;
+use crate::polygons::{holes, Polygon};
+use cgmath::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+pub const VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlicedModel {
+    pub version: u32,
+    pub layer_height: f32,
+    pub layers: Vec<LayerData>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LayerData {
+    pub height: f32,
+    pub contours: Vec<Contour>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Contour {
+    /// true if the contour encloses empty space within an outer contour
+    pub hole: bool,
+    pub points: Vec<[f32; 2]>,
+    pub normals: Vec<[f32; 2]>,
+    /// index of the material, missing in older documents
+    #[serde(default)]
+    pub material: usize,
+}
+
+/// only the version, read before the rest of the document
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+#[derive(Debug)]
+pub enum InterchangeError {
+    Io(io::Error),
+    Json(serde_json::Error),
+    Cbor(serde_cbor::Error),
+    /// the document was written with a schema version this reader does not support
+    Version(u32),
+    /// the document is well formed but its content is inconsistent
+    Invalid(String),
+}
+
+impl fmt::Display for InterchangeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InterchangeError::Io(err) => write!(f, "{}", err),
+            InterchangeError::Json(err) => write!(f, "invalid json: {}", err),
+            InterchangeError::Cbor(err) => write!(f, "invalid cbor: {}", err),
+            InterchangeError::Version(version) => {
+                write!(f, "unsupported version {}, expected {}", version, VERSION)
+            }
+            InterchangeError::Invalid(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl std::error::Error for InterchangeError {}
+
+impl From<io::Error> for InterchangeError {
+    fn from(err: io::Error) -> Self {
+        return InterchangeError::Io(err);
+    }
+}
+
+impl From<serde_json::Error> for InterchangeError {
+    fn from(err: serde_json::Error) -> Self {
+        return InterchangeError::Json(err);
+    }
+}
+
+impl From<serde_cbor::Error> for InterchangeError {
+    fn from(err: serde_cbor::Error) -> Self {
+        return InterchangeError::Cbor(err);
+    }
+}
+
+/// the encodings a sliced model can be written in
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Json,
+    Cbor,
+}
+
+impl Encoding {
+    /// picks the encoding from the file extension, `.cbor` for CBOR and JSON otherwise
+    pub fn from_path(path: &Path) -> Encoding {
+        return match path.extension().and_then(|e| e.to_str()) {
+            Some("cbor") => Encoding::Cbor,
+            _ => Encoding::Json,
+        };
+    }
+}
+
+impl SlicedModel {
+    /// converts the closed polygons of the layers, open lines left over from slicing
+    /// are not part of the format
+    pub fn from_layers(layers: &[Layer]) -> SlicedModel {
+        return SlicedModel {
+            version: VERSION,
+            // the layers of a slicing are equally thick
+            layer_height: layers.first().map(|l| l.thickness).unwrap_or(0.),
+            layers: layers
+                .iter()
+                .map(|layer| LayerData {
+                    height: layer.height,
+                    contours: layer
+                        .polygons
+                        .iter()
+                        .zip(holes(&layer.polygons))
+                        .filter(|(p, _)| p.points.first() == p.points.last())
+                        .filter_map(|(p, hole)| contour(p, hole))
+                        .collect(),
+                })
+                .collect(),
+        };
+    }
+
+    pub fn to_layers(&self) -> Vec<Layer> {
+        return self
+            .layers
+            .iter()
+            .map(|layer| Layer {
+                height: layer.height,
+                thickness: self.layer_height,
+                polygons: layer
+                    .contours
+                    .iter()
+                    .map(|c| Polygon {
+                        points: c.points.iter().map(|[x, y]| Point2::new(*x, *y)).collect(),
+                        normals: c
+                            .normals
+                            .iter()
+                            .map(|[x, y]| Vector2::new(*x, *y))
+                            .collect(),
+                        material: c.material,
+                    })
+                    .collect(),
+            })
+            .collect();
+    }
+
+    /// checks the version and that the contours are closed and have one normal per edge
+    pub fn validate(&self) -> Result<(), InterchangeError> {
+        if self.version != VERSION {
+            return Err(InterchangeError::Version(self.version));
+        }
+        for (i, layer) in self.layers.iter().enumerate() {
+            for (j, contour) in layer.contours.iter().enumerate() {
+                let invalid = |reason: &str| {
+                    InterchangeError::Invalid(format!("layer {} contour {}: {}", i, j, reason))
+                };
+                if contour.points.len() < 4 {
+                    return Err(invalid("needs at least three distinct points"));
+                }
+                if contour.points.first() != contour.points.last() {
+                    return Err(invalid("is not closed"));
+                }
+                if contour.normals.len() != contour.points.len() - 1 {
+                    return Err(invalid("needs one normal per edge"));
+                }
+            }
+        }
+        return Ok(());
+    }
+
+    /// validates and writes the model
+    pub fn write<W: Write>(&self, w: &mut W, encoding: Encoding) -> Result<(), InterchangeError> {
+        self.validate()?;
+        match encoding {
+            Encoding::Json => serde_json::to_writer(w, self)?,
+            Encoding::Cbor => serde_cbor::to_writer(w, self)?,
+        }
+        return Ok(());
+    }
+
+    /// reads and validates a sliced model
+    pub fn read<R: Read>(r: &mut R, encoding: Encoding) -> Result<SlicedModel, InterchangeError> {
+        let mut content = Vec::new();
+        r.read_to_end(&mut content)?;
+        // the version is checked first, a newer schema might not match the fields below
+        let header: Header = match encoding {
+            Encoding::Json => serde_json::from_slice(&content)?,
+            Encoding::Cbor => serde_cbor::from_slice(&content)?,
+        };
+        if header.version != VERSION {
+            return Err(InterchangeError::Version(header.version));
+        }
+        let model: SlicedModel = match encoding {
+            Encoding::Json => serde_json::from_slice(&content)?,
+            Encoding::Cbor => serde_cbor::from_slice(&content)?,
+        };
+        model.validate()?;
+        return Ok(model);
+    }
+
+    pub fn save(&self, path: &Path, encoding: Encoding) -> Result<(), InterchangeError> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write(&mut writer, encoding)?;
+        writer.flush()?;
+        return Ok(());
+    }
+
+    /// reads a model, the encoding is chosen by the file extension
+    pub fn load(path: &Path) -> Result<SlicedModel, InterchangeError> {
+        let mut reader = BufReader::new(File::open(path)?);
+        return SlicedModel::read(&mut reader, Encoding::from_path(path));
+    }
+}
+
+/// Converts a polygon, edges without length are left out since their normal is undefined.
+/// Polygons with less than three distinct points left are dropped.
+fn contour(polygon: &Polygon, hole: bool) -> Option<Contour> {
+    let mut points = Vec::new();
+    let mut normals = Vec::new();
+    if let Some(first) = polygon.points.first() {
+        points.push([first.x, first.y]);
+    }
+    for ((_, end), normal) in polygon.edges().zip(&polygon.normals) {
+        if points.last() != Some(&[end.x, end.y]) {
+            points.push([end.x, end.y]);
+            normals.push([normal.x, normal.y]);
+        }
+    }
+    if points.len() < 4 {
+        return None;
+    }
+    return Some(Contour {
+        hole,
+        points,
+        normals,
+        material: polygon.material,
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a square with a square hole on two layers
+    fn layers() -> Vec<Layer> {
+        return (0..2)
+            .map(|i| Layer {
+                height: 0.1 + 0.2 * i as f32,
+                thickness: 0.2,
+                polygons: vec![
+                    Polygon::rectangle(Point2::new(0., 0.), Point2::new(10., 10.)),
+                    Polygon::rectangle(Point2::new(3., 3.), Point2::new(7., 7.)),
+                ],
+            })
+            .collect();
+    }
+
+    #[test]
+    fn layers_round_trip_in_both_encodings() {
+        let model = SlicedModel::from_layers(&layers());
+        assert_eq!(model.layer_height, 0.2);
+        let hole: Vec<bool> = model.layers[0].contours.iter().map(|c| c.hole).collect();
+        assert_eq!(hole, vec![false, true]);
+
+        for encoding in [Encoding::Json, Encoding::Cbor].iter() {
+            let mut data = Vec::new();
+            model.write(&mut data, *encoding).unwrap();
+            let read = SlicedModel::read(&mut data.as_slice(), *encoding).unwrap();
+            assert_eq!(read, model);
+            for (read, layer) in read.to_layers().iter().zip(layers()) {
+                assert_eq!(read.height, layer.height);
+                assert_eq!(read.polygons, layer.polygons);
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_versions_and_open_contours_are_rejected() {
+        let json = r#"{"version": 2, "something": "new"}"#;
+        match SlicedModel::read(&mut json.as_bytes(), Encoding::Json) {
+            Err(InterchangeError::Version(2)) => {}
+            other => panic!("expected a version error, got {:?}", other),
+        }
+
+        let mut model = SlicedModel::from_layers(&layers());
+        model.layers[1].contours[0].points.pop();
+        assert!(matches!(
+            model.write(&mut Vec::new(), Encoding::Json),
+            Err(InterchangeError::Invalid(_))
+        ));
+    }
+}
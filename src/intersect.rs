@@ -0,0 +1,667 @@
+//! Finds contours which cross themselves or each other and turns them into simple polygons.
+//!
+//! The crossings are found with a sweep line (Bentley-Ottmann): a vertical line moves from
+//! left to right over the edges, only edges that are neighbours on the line can cross next,
+//! so only those are tested against each other.
+
+use crate::model::Slice;
+use crate::polygons::{get_line_intersection, scalar, FillRule, Polygon, Vertex};
+use crate::predicates::{orient2d, Orientation};
+use cgmath::*;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+
+/// a point where edges cross or touch, other than neighbouring edges meeting at their corner
+#[derive(Debug, Clone)]
+pub struct Intersection<S = f32> {
+    pub point: Vertex<S>,
+    /// the edges through the point as pairs of polygon and edge index
+    pub edges: Vec<(usize, usize)>,
+}
+
+impl<S> Intersection<S> {
+    /// true if the point is where a polygon crosses itself, otherwise polygons overlap
+    pub fn is_self_intersection(&self) -> bool {
+        return self.edges.iter().all(|(p, _)| *p == self.edges[0].0);
+    }
+}
+
+/// an edge with its end points ordered from left to right
+#[derive(Debug, Clone, Copy)]
+struct Segment<S> {
+    left: Vertex<S>,
+    right: Vertex<S>,
+    polygon: usize,
+    edge: usize,
+}
+
+impl<S: BaseFloat> Segment<S> {
+    /// true if the segment is below the point, or right of it for vertical segments
+    fn below(&self, p: Vertex<S>) -> bool {
+        return orient2d(self.left, self.right, p) == Orientation::CounterClockwise;
+    }
+
+    /// true if the segment lies on the line through the point
+    fn through(&self, p: Vertex<S>) -> bool {
+        return orient2d(self.left, self.right, p) == Orientation::Collinear;
+    }
+
+    /// Order of two segments passing through the same point, right of the point. The one
+    /// whose right end is below the line of the other one is the lower one.
+    fn compare(&self, other: &Segment<S>) -> Ordering {
+        return match orient2d(self.left, self.right, other.right) {
+            Orientation::CounterClockwise => Ordering::Less,
+            Orientation::Clockwise => Ordering::Greater,
+            Orientation::Collinear => Ordering::Equal,
+        };
+    }
+}
+
+/// points are swept from left to right and from bottom to top
+fn before<S: BaseFloat>(a: Vertex<S>, b: Vertex<S>) -> Ordering {
+    return (a.x, a.y)
+        .partial_cmp(&(b.x, b.y))
+        .unwrap_or(Ordering::Equal);
+}
+
+/// the next point the sweep line stops at, the queue returns the leftmost point first
+struct Event<S> {
+    point: Vertex<S>,
+    /// the segment starting here
+    start: Option<usize>,
+    /// the segment ending here
+    end: Option<usize>,
+    /// the two segments crossing here, their rounded crossing might not be on them exactly
+    crossing: Option<[usize; 2]>,
+}
+
+impl<S: BaseFloat> PartialEq for Event<S> {
+    fn eq(&self, other: &Self) -> bool {
+        return self.point == other.point;
+    }
+}
+
+impl<S: BaseFloat> Eq for Event<S> {}
+
+impl<S: BaseFloat> PartialOrd for Event<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl<S: BaseFloat> Ord for Event<S> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        return before(other.point, self.point);
+    }
+}
+
+/// reports all points where the closed polygons of a slice cross or touch
+pub fn find_intersections<S: BaseFloat>(slice: &[Polygon<S>]) -> Vec<Intersection<S>> {
+    let segments = segments(slice);
+    let mut queue = BinaryHeap::new();
+    for (i, s) in segments.iter().enumerate() {
+        queue.push(Event {
+            point: s.left,
+            start: Some(i),
+            end: None,
+            crossing: None,
+        });
+        queue.push(Event {
+            point: s.right,
+            start: None,
+            end: Some(i),
+            crossing: None,
+        });
+    }
+    // segments crossing the sweep line, from bottom to top
+    let mut status: Vec<usize> = Vec::new();
+    let mut active = vec![false; segments.len()];
+    // pairs of segments whose crossing is already queued
+    let mut seen: HashSet<[usize; 2]> = HashSet::new();
+    let mut result = Vec::new();
+
+    while let Some(event) = queue.pop() {
+        let p = event.point;
+        let mut starting: Vec<usize> = event.start.into_iter().collect();
+        // the segments known to pass the point
+        let mut known: Vec<usize> = event.end.into_iter().collect();
+        known.extend(event.crossing.iter().flatten());
+        while queue.peek().map(|e| e.point) == Some(p) {
+            let e = queue.pop().unwrap();
+            starting.extend(e.start);
+            known.extend(e.end);
+            known.extend(e.crossing.iter().flatten());
+        }
+        // the segments through the point are next to each other on the sweep line
+        let lower = status.partition_point(|s| segments[*s].below(p));
+        let upper = lower
+            + status[lower..]
+                .iter()
+                .take_while(|s| segments[**s].through(p))
+                .count();
+        let mut through: Vec<usize> = (lower..upper).collect();
+        // crossings rounded off the segments are close to the others
+        for s in known.iter().filter(|s| active[**s]) {
+            through.extend(find_near(&status, lower, *s));
+        }
+        through.sort_unstable();
+        through.dedup();
+        let through: Vec<usize> = through
+            .into_iter()
+            .rev()
+            .map(|i| status.remove(i))
+            .collect();
+
+        let mut involved: Vec<usize> = through.iter().chain(starting.iter()).copied().collect();
+        involved.sort_unstable();
+        involved.dedup();
+        if let Some(intersection) = intersection_at(&segments, &involved, p) {
+            result.push(intersection);
+        }
+
+        let mut continuing: Vec<usize> = involved
+            .into_iter()
+            .filter(|s| segments[*s].right != p)
+            .collect();
+        for s in &through {
+            active[*s] = false;
+        }
+        for s in &continuing {
+            active[*s] = true;
+        }
+        // right of the point the segments are ordered by their direction
+        continuing.sort_by(|a, b| segments[*a].compare(&segments[*b]));
+        let position = status.partition_point(|s| segments[*s].below(p));
+        let count = continuing.len();
+        status.splice(position..position, continuing);
+
+        let mut check = |a: usize, b: usize, queue: &mut BinaryHeap<Event<S>>| {
+            let (sa, sb) = (segments[a], segments[b]);
+            // touching and overlapping segments meet at end points, which are events anyway
+            let ends = [sa.left, sa.right, sb.left, sb.right];
+            if let Some(q) = get_line_intersection(sa.left, sa.right, sb.left, sb.right)
+                .filter(|q| !ends.contains(q))
+            {
+                // a crossing rounded to before the sweep line is handled next
+                if seen.insert([a.min(b), a.max(b)]) {
+                    queue.push(Event {
+                        point: q,
+                        start: None,
+                        end: None,
+                        crossing: Some([a, b]),
+                    });
+                }
+            }
+        };
+        if count == 0 {
+            if position > 0 && position < status.len() {
+                check(status[position - 1], status[position], &mut queue);
+            }
+        } else {
+            if position > 0 {
+                check(status[position - 1], status[position], &mut queue);
+            }
+            let last = position + count - 1;
+            if last + 1 < status.len() {
+                check(status[last], status[last + 1], &mut queue);
+            }
+        }
+    }
+    return result;
+}
+
+/// the position of a segment on the sweep line, searching outwards from `position`
+fn find_near(status: &[usize], position: usize, segment: usize) -> Option<usize> {
+    for distance in 0..=status.len() {
+        if let Some(i) = position.checked_sub(distance + 1) {
+            if status[i] == segment {
+                return Some(i);
+            }
+        }
+        if status.get(position + distance) == Some(&segment) {
+            return Some(position + distance);
+        }
+    }
+    return None;
+}
+
+/// The intersection at `p`, unless the only segments through it are two edges of one
+/// polygon meeting at their common corner.
+fn intersection_at<S: BaseFloat>(
+    segments: &[Segment<S>],
+    involved: &[usize],
+    p: Vertex<S>,
+) -> Option<Intersection<S>> {
+    if involved.len() < 2 {
+        return None;
+    }
+    let polygon = segments[involved[0]].polygon;
+    let corner = involved.len() == 2
+        && involved.iter().all(|s| {
+            let s = &segments[*s];
+            s.polygon == polygon && (s.left == p || s.right == p)
+        });
+    // edges leaving the corner in the same direction overlap, which has to be resolved
+    let other = |s: &Segment<S>| {
+        if s.left == p {
+            s.right
+        } else {
+            s.left
+        }
+    };
+    let (a, b) = (&segments[involved[0]], &segments[involved[1]]);
+    let overlapping = corner
+        && (a.left == p) == (b.left == p)
+        && orient2d(p, other(a), other(b)) == Orientation::Collinear;
+    if corner && !overlapping {
+        return None;
+    }
+    return Some(Intersection {
+        point: p,
+        edges: involved
+            .iter()
+            .map(|s| (segments[*s].polygon, segments[*s].edge))
+            .collect(),
+    });
+}
+
+/// the edges of the closed polygons without the ones without length
+fn segments<S: BaseFloat>(slice: &[Polygon<S>]) -> Vec<Segment<S>> {
+    let mut segments = Vec::new();
+    for (i, polygon) in slice.iter().enumerate().filter(|(_, p)| closed(p)) {
+        for (j, (a, b)) in polygon.edges().enumerate() {
+            let (left, right) = match before(a, b) {
+                Ordering::Less => (a, b),
+                Ordering::Greater => (b, a),
+                Ordering::Equal => continue,
+            };
+            segments.push(Segment {
+                left,
+                right,
+                polygon: i,
+                edge: j,
+            });
+        }
+    }
+    return segments;
+}
+
+fn closed<S: BaseFloat>(polygon: &Polygon<S>) -> bool {
+    return polygon.points.len() > 3 && polygon.points.first() == polygon.points.last();
+}
+
+/// Resolves crossings and overlaps into simple polygons which do not touch each other except
+/// at corners. Which areas are filled is decided by the fill rule, outlines are counter
+/// clockwise and holes clockwise, with the normals pointing out of the filled area.
+/// Open lines are kept as they are.
+pub fn clean<S: BaseFloat>(slice: &[Polygon<S>], rule: FillRule) -> Slice<S> {
+    return clean_by(slice, |winding| rule.is_filled(winding));
+}
+
+/// like `clean`, with the filled areas chosen by their winding number
+fn clean_by<S: BaseFloat>(slice: &[Polygon<S>], is_filled: impl Fn(i32) -> bool) -> Slice<S> {
+    let closed_polygons: Vec<Polygon<S>> = slice.iter().filter(|p| closed(p)).cloned().collect();
+    let mut result: Slice<S> = slice.iter().filter(|p| !closed(p)).cloned().collect();
+
+    // every edge is split at the intersections on it
+    let mut splits: HashMap<(usize, usize), Vec<Vertex<S>>> = HashMap::new();
+    for intersection in find_intersections(&closed_polygons) {
+        for edge in intersection.edges {
+            splits.entry(edge).or_default().push(intersection.point);
+        }
+    }
+    let bands = Bands::new(&closed_polygons);
+    let winding = |p: Vertex<S>| bands.winding(p);
+
+    // the pieces between filled and empty areas, with the filled area on their left
+    let mut pieces: Vec<(Vertex<S>, Vertex<S>, usize)> = Vec::new();
+    let mut unique: HashSet<[Bits; 4]> = HashSet::new();
+    for (i, polygon) in closed_polygons.iter().enumerate() {
+        for (j, (a, b)) in polygon.edges().enumerate() {
+            let mut points = vec![a, b];
+            if let Some(split) = splits.get(&(i, j)) {
+                points.extend(split.iter().filter(|p| **p != a && **p != b));
+            }
+            let d = b - a;
+            points.sort_by(|p, q| {
+                let (tp, tq) = ((p - a).dot(d), (q - a).dot(d));
+                tp.partial_cmp(&tq).unwrap_or(Ordering::Equal)
+            });
+            points.dedup();
+            for w in points.windows(2) {
+                let (start, end) = (w[0], w[1]);
+                let direction = end - start;
+                let length = direction.magnitude();
+                if length == S::zero() {
+                    continue;
+                }
+                let middle = start + direction / scalar(2.);
+                let side = Vector2::new(-direction.y, direction.x) / length
+                    * (length * scalar(1e-3)).max(scalar(1e-5)).min(scalar(1e-3));
+                let left = is_filled(winding(middle + side));
+                let right = is_filled(winding(middle - side));
+                let piece = match (left, right) {
+                    (true, false) => (start, end, polygon.material),
+                    (false, true) => (end, start, polygon.material),
+                    _ => continue,
+                };
+                let key = [piece.0.x, piece.0.y, piece.1.x, piece.1.y].map(bits);
+                if unique.insert(key) {
+                    pieces.push(piece);
+                }
+            }
+        }
+    }
+
+    // the pieces are linked into loops, at a corner with several ways to continue the
+    // loop turns left as far as possible, so each loop encloses a single area
+    let key = |p: Vertex<S>| [bits(p.x), bits(p.y)];
+    let mut outgoing: HashMap<[Bits; 2], Vec<usize>> = HashMap::new();
+    for (i, (start, _, _)) in pieces.iter().enumerate() {
+        outgoing.entry(key(*start)).or_default().push(i);
+    }
+    let mut used = vec![false; pieces.len()];
+    for first in 0..pieces.len() {
+        if used[first] {
+            continue;
+        }
+        used[first] = true;
+        let (start, mut end, material) = pieces[first];
+        let mut points = vec![start, end];
+        let mut direction = end - start;
+        while end != start {
+            let next = outgoing.get(&key(end)).and_then(|candidates| {
+                candidates
+                    .iter()
+                    .copied()
+                    .filter(|c| !used[*c])
+                    .max_by(|a, b| {
+                        let (ta, tb) = (
+                            turn(direction, pieces[*a].1 - pieces[*a].0),
+                            turn(direction, pieces[*b].1 - pieces[*b].0),
+                        );
+                        ta.partial_cmp(&tb).unwrap_or(Ordering::Equal)
+                    })
+            });
+            let next = match next {
+                Some(next) => next,
+                // an open chain is left over from inconsistent input
+                None => break,
+            };
+            used[next] = true;
+            direction = pieces[next].1 - pieces[next].0;
+            end = pieces[next].1;
+            points.push(end);
+        }
+        if end == start && points.len() > 3 {
+            let normals = points
+                .windows(2)
+                .map(|w| {
+                    let d = (w[1] - w[0]).normalize();
+                    Vector2::new(d.y, -d.x)
+                })
+                .collect();
+            result.push(Polygon {
+                points,
+                normals,
+                material,
+            });
+        }
+    }
+    return result;
+}
+
+/// The edges of the polygons sorted into horizontal bands, so the winding number of a point
+/// only needs the edges of its band.
+struct Bands<S> {
+    min: S,
+    height: S,
+    edges: Vec<Vec<(Vertex<S>, Vertex<S>)>>,
+}
+
+impl<S: BaseFloat> Bands<S> {
+    fn new(polygons: &[Polygon<S>]) -> Bands<S> {
+        let edges: Vec<(Vertex<S>, Vertex<S>)> = polygons.iter().flat_map(|p| p.edges()).collect();
+        let (min, max) = edges
+            .iter()
+            .fold((S::infinity(), S::neg_infinity()), |m, (a, b)| {
+                (m.0.min(a.y).min(b.y), m.1.max(a.y).max(b.y))
+            });
+        let count = ((edges.len() as f64).sqrt() as usize).max(1);
+        let mut bands = Bands {
+            min,
+            height: (max - min) / scalar(count as f64),
+            edges: vec![Vec::new(); count],
+        };
+        for (a, b) in edges {
+            let (first, last) = (bands.band(a.y.min(b.y)), bands.band(a.y.max(b.y)));
+            for band in &mut bands.edges[first..=last] {
+                band.push((a, b));
+            }
+        }
+        return bands;
+    }
+
+    fn band(&self, y: S) -> usize {
+        // all edges are horizontal or there are none
+        if self.height.is_nan() || self.height <= S::zero() {
+            return 0;
+        }
+        let band = ((y - self.min) / self.height)
+            .floor()
+            .to_usize()
+            .unwrap_or(0);
+        return band.min(self.edges.len() - 1);
+    }
+
+    /// the winding number of the point, like summing up `scanline_winding` left of it
+    fn winding(&self, p: Vertex<S>) -> i32 {
+        return self.edges[self.band(p.y)]
+            .iter()
+            .filter(|(a, b)| (a.y <= p.y) != (b.y <= p.y))
+            .filter(|(a, b)| a.x + (p.y - a.y) / (b.y - a.y) * (b.x - a.x) < p.x)
+            .map(|(a, b)| if a.y > b.y { 1 } else { -1 })
+            .sum();
+    }
+}
+
+/// The area covered by either of the slices, which are filled with the even-odd rule.
+/// The result is cleaned like by `clean`.
+pub fn union<S: BaseFloat>(a: &[Polygon<S>], b: &[Polygon<S>]) -> Slice<S> {
+    let mut polygons = clean(a, FillRule::EvenOdd);
+    polygons.extend(clean(b, FillRule::EvenOdd));
+    return clean(&polygons, FillRule::Positive);
+}
+
+/// The area of `a` which is not covered by `b`, both are filled with the even-odd rule.
+/// The result is cleaned like by `clean`.
+pub fn difference<S: BaseFloat>(a: &[Polygon<S>], b: &[Polygon<S>]) -> Slice<S> {
+    let mut polygons = clean(a, FillRule::EvenOdd);
+    // reversed, the area of `b` winds clockwise and cancels the area of `a`
+    polygons.extend(
+        clean(b, FillRule::EvenOdd)
+            .into_iter()
+            .filter(closed)
+            .map(|p| Polygon {
+                points: p.points.into_iter().rev().collect(),
+                normals: p.normals.into_iter().rev().collect(),
+                material: p.material,
+            }),
+    );
+    return clean(&polygons, FillRule::Positive);
+}
+
+/// The area covered by both slices, which are filled with the even-odd rule.
+/// The result is cleaned like by `clean`.
+pub fn intersection<S: BaseFloat>(a: &[Polygon<S>], b: &[Polygon<S>]) -> Slice<S> {
+    let mut polygons = clean(a, FillRule::EvenOdd);
+    polygons.extend(clean(b, FillRule::EvenOdd));
+    // both wind once around their areas, so the common area is wound around twice
+    return clean_by(&polygons, |winding| winding >= 2);
+}
+
+/// the angle from one direction to the next, positive for left turns, turning back is last
+fn turn<S: BaseFloat>(from: Vector2<S>, to: Vector2<S>) -> S {
+    let angle = from.perp_dot(to).atan2(from.dot(to));
+    let pi: S = scalar(std::f64::consts::PI);
+    if angle >= pi - scalar(1e-6) {
+        return -pi;
+    }
+    return angle;
+}
+
+/// the exact bits of a coordinate, to use points as keys
+type Bits = (u64, i16, i8);
+
+fn bits<S: BaseFloat>(c: S) -> Bits {
+    return c.integer_decode();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rectangle(x0: f32, y0: f32, x1: f32, y1: f32) -> Polygon {
+        return Polygon::rectangle(Point2::new(x0, y0), Point2::new(x1, y1));
+    }
+
+    fn points<S: BaseFloat>(intersections: &[Intersection<S>]) -> Vec<Vertex<S>> {
+        let mut points: Vec<Vertex<S>> = intersections.iter().map(|i| i.point).collect();
+        points.sort_by(|a, b| before(*a, *b));
+        return points;
+    }
+
+    #[test]
+    fn crossing_squares() {
+        let slice = [rectangle(0., 0., 2., 2.), rectangle(1., 1., 3., 3.)];
+        assert_eq!(
+            points(&find_intersections(&slice)),
+            vec![Point2::new(1., 2.), Point2::new(2., 1.)]
+        );
+        let union = clean(&slice, FillRule::NonZero);
+        assert_eq!(union.len(), 1);
+        assert_eq!(union[0].area(), 7.);
+    }
+
+    #[test]
+    fn figure_eight_crosses_itself() {
+        let eight = Polygon {
+            points: vec![
+                Point2::new(0., 0.),
+                Point2::new(2., 2.),
+                Point2::new(2., 0.),
+                Point2::new(0., 2.),
+                Point2::new(0., 0.),
+            ],
+            normals: vec![Vector2::new(0., -1.); 4],
+            material: 0,
+        };
+        let found = find_intersections(&[eight, rectangle(3., 0., 4., 1.)]);
+        assert_eq!(points(&found), vec![Point2::new(1., 1.)]);
+        assert!(found[0].is_self_intersection());
+        assert_eq!(found[0].edges.len(), 2);
+
+        let crossing = [rectangle(0., 0., 2., 2.), rectangle(1., 1., 3., 3.)];
+        assert!(find_intersections(&crossing)
+            .iter()
+            .all(|i| !i.is_self_intersection()));
+    }
+
+    #[test]
+    fn nearly_collinear_edges() {
+        // the second square is tilted by the smallest possible amount
+        let tilted = Polygon {
+            points: vec![
+                Point2::new(1., 0.),
+                Point2::new(3., f32::EPSILON),
+                Point2::new(3., 2.),
+                Point2::new(1., 2.),
+                Point2::new(1., 0.),
+            ],
+            normals: rectangle(0., 0., 1., 1.).normals,
+            material: 0,
+        };
+        let slice = [rectangle(0., 0., 2., 2.), tilted];
+        let found = points(&find_intersections(&slice));
+        assert!(found.contains(&Point2::new(1., 0.)));
+        assert!(found.contains(&Point2::new(2., 2.)));
+        let union = clean(&slice, FillRule::NonZero);
+        assert_eq!(union.len(), 1);
+        assert!((union[0].area() - 6.).abs() < 1e-5);
+    }
+
+    #[test]
+    fn nearly_collinear_edges_in_double_precision() {
+        let square = |x0: f64, x1: f64, tilt: f64| Polygon {
+            points: vec![
+                Point2::new(x0, 0.),
+                Point2::new(x1, tilt),
+                Point2::new(x1, 2.),
+                Point2::new(x0, 2.),
+                Point2::new(x0, 0.),
+            ],
+            normals: Polygon::rectangle(Point2::new(0., 0.), Point2::new(1., 1.)).normals,
+            material: 0,
+        };
+        // far below the precision of f32, the tilt still separates the edges
+        let slice = [square(0., 2., 0.), square(1., 3., f64::EPSILON)];
+        let found = points(&find_intersections(&slice));
+        assert!(found.contains(&Point2::new(1., 0.)));
+        assert!(found.contains(&Point2::new(2., 2.)));
+        let union = clean(&slice, FillRule::NonZero);
+        assert_eq!(union.len(), 1);
+        assert!((union[0].area() - 6.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn overlap_at_shared_corner() {
+        // a spike back along the first edge, both edges share the corner at (2, 0)
+        let spike = Polygon {
+            points: vec![
+                Point2::new(0., 0.),
+                Point2::new(2., 0.),
+                Point2::new(1., 0.),
+                Point2::new(1., 1.),
+                Point2::new(0., 1.),
+                Point2::new(0., 0.),
+            ],
+            normals: vec![Vector2::new(0., -1.); 5],
+            material: 0,
+        };
+        let slice = [spike];
+        assert_eq!(
+            points(&find_intersections(&slice)),
+            vec![Point2::new(1., 0.), Point2::new(2., 0.)]
+        );
+        let cleaned = clean(&slice, FillRule::NonZero);
+        assert_eq!(cleaned.len(), 1);
+        assert_eq!(cleaned[0].area(), 1.);
+    }
+
+    #[test]
+    fn squares_with_common_corner_and_edges() {
+        let slice = [rectangle(0., 0., 2., 2.), rectangle(0., 0., 1., 1.)];
+        assert_eq!(
+            points(&find_intersections(&slice)),
+            vec![
+                Point2::new(0., 0.),
+                Point2::new(0., 1.),
+                Point2::new(1., 0.)
+            ]
+        );
+        assert_eq!(clean(&slice, FillRule::EvenOdd)[0].area(), 3.);
+    }
+
+    #[test]
+    fn booleans() {
+        let (a, b) = ([rectangle(0., 0., 4., 4.)], [rectangle(1., 1., 3., 5.)]);
+        let area = |s: &[Polygon]| s.iter().map(|p| p.area()).sum::<f32>();
+        assert_eq!(area(&union(&a, &b)), 18.);
+        assert_eq!(area(&difference(&a, &b)), 10.);
+        assert_eq!(area(&intersection(&a, &b)), 6.);
+        // a hole cut completely inside stays a hole
+        let ring = difference(&a, &[rectangle(1., 1., 3., 3.)]);
+        assert_eq!(ring.len(), 2);
+        assert_eq!(area(&ring), 12.);
+    }
+}
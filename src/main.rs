@@ -1,415 +1,496 @@
+#![allow(clippy::needless_return)]
+
+mod adhesion;
+mod arcs;
+mod comb;
+mod dxf;
+mod fixed;
+mod gcode;
+mod hollow;
+mod interchange;
+mod intersect;
 mod model;
 mod polygons;
+mod predicates;
+mod profile;
+mod raster;
+mod reconstruct;
+mod resin;
+mod seam;
+mod sheets;
+mod simplify;
+mod support;
+mod svg;
+mod toolpath;
+mod tools;
+mod travel;
+mod tree_support;
+mod viewer;
 
-use cgmath::*;
-use model::Model;
-use polygons::{AbstractPolygon, IndexPolygon, Normal, Polygon, Vertex};
-use std::fs::File;
-use std::{fmt::Debug, io::prelude::*};
+use adhesion::{add_raft, brim, skirt, RaftSettings};
+use cgmath::Point2;
+use dxf::{write_dxf, write_layer_files, DxfSettings};
+use fixed::{to_units, FixedLayer};
+use gcode::{write_gcode, GcodeSettings};
+use hollow::{drain, hollow, DrainHole, HollowSettings};
+use interchange::{Encoding, SlicedModel};
+use intersect::{clean, find_intersections};
+use model::{Layer, Model, Slice};
+use polygons::FillRule;
+use profile::{Adhesion, Profile, SupportKind};
+use raster::{write_image, write_layers, write_zip, RasterSettings};
+use reconstruct::reconstruct;
+use resin::{ExposureSettings, ResinFile};
+use seam::{place_seams, SeamPosition};
+use sheets::{nest, slice_sheets, write_sheets, SheetSettings};
+use simplify::simplify_layers;
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path as FilePath, PathBuf};
+use std::process;
+use support::{generate_supports, SupportSettings};
+use toolpath::{layer_toolpaths, Path, PathKind, ToolpathSettings};
+use tools::{plan_tools, PrimeTower};
+use travel::optimize_layers;
+use tree_support::{generate_tree_supports, TreeSupportSettings};
+use viewer::{write_viewer, ViewerSettings};
 
-fn connect_lines(lines: &Vec<Polygon>) -> Vec<Polygon> {
-    let mut vertices: Vec<Vertex> = Vec::new();
-    // convert points into list of indices => group similar points
-    let mut polygons: Vec<IndexPolygon> = lines
-        .iter()
-        // convert vectors to indices
-        .map(|p| p.to_indices(&mut vertices))
-        .collect::<Vec<IndexPolygon>>();
+const USAGE: &str = "\
+usage: disco <model> [-p <profile>]... [-s <key>=<value>]... [-f <format>] [-o <output>]
 
-    // remove duplicates
-    polygons.dedup_by(|p1, p2| p1.points == p2.points);
+<model> is a Wavefront OBJ file, layers sliced before (.json or .cbor)
+or a resin print (.dsco), which can only be written as png images
 
-    // combine lines into closed polygons
-    // this is done by iteratively joining lines together until no new lines can be joined
-    let mut new = true;
-    while new {
-        new = false;
-        let mut i = 0;
-        while i < polygons.len() {
-            let mut j = i + 1;
-            while j < polygons.len() {
-                let l1 = polygons[i].clone();
-                let l2 = polygons[j].clone();
-                match l1.join(l2) {
-                    Some(result) => {
-                        polygons.remove(j);
-                        polygons[i] = result;
-                        new = true;
-                    }
-                    None => {
-                        j += 1;
-                    }
-                }
-            }
-            i += 1;
-        }
-    }
-    // convert the indices pack to actual vertices
-    let line_vertices: Vec<Polygon> = polygons
-        .iter()
-        .filter(|l| l.points.len() > 2)
-        //.map(|l|l.fuse_normals()) // TODO
-        .map(|l| l.to_polygon(vertices.clone()))
-        .collect();
-    return line_vertices;
-}
+-p  profile file, later files override earlier ones
+-s  overrides a single setting, e.g. -s print.layer_height=0.1
+-f  output format, taken from the extension of the output by default
+      gcode     g-code and an html preview next to it
+      dxf       all slices in one drawing
+      dxf-dir   one drawing per slice in a directory
+      png       one image per layer in a directory
+      zip       one image per layer in a zip archive
+      dsco      resin print
+      json      slices in the interchange format, as json
+      cbor      slices in the interchange format, as cbor
+      obj       mesh rebuilt from the slices, as Wavefront OBJ
+      stl       mesh rebuilt from the slices, as STL
+      sheets    one svg file per sheet in a directory, with the layers nested on them
+-o  output file or directory, `sliced.<format>` by default";
 
-fn main() {
-    let (p1, n1) = [
-        ([0., 0.], [-1., 0.]),
-        ([0., 20.], [0., 1.]),
-        // ([10., 20.], [1., 0.]),
-        // ([10., 10.], [0., 1.]),
-        // ([20., 10.], [-1., 0.]),
-        // ([20., 20.], [0., 1.]),
-        ([30., 20.], [1., 0.]),
-        ([30., 0.], [0., -1.]),
-        ([0., 0.], [-1., 0.]),
-    ]
-    .iter()
-    .map(|([x, y], [nx, ny])| (Point2::new(*x, *y), Vector2::new(*nx, *ny)))
-    .unzip();
+/// number of corners of the drain hole of hollow resin prints
+const DRAIN_SEGMENTS: usize = 32;
 
-    let mut v1 = Polygon {
-        points: p1,
-        normals: n1,
-    };
+/// size of the preview image of resin prints
+const PREVIEW_SIZE: (u32, u32) = (240, 320);
 
-    let (p2, n2) = vec![
-        ([-10., 15.], [-1., 0.]),
-        ([-10., 30.], [0., 1.]),
-        ([40., 30.], [1., 0.]),
-        ([40., 15.], [0., -1.]),
-        ([-10., 15.], [-1., 0.]),
-    ]
-    .iter()
-    .map(|([x, y], [nx, ny])| (Point2::new(*x, *y), Vector2::new(*nx, *ny)))
-    .unzip();
-
-    let mut v2 = Polygon {
-        points: p2,
-        normals: n2,
-    };
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Gcode,
+    Dxf,
+    DxfDir,
+    Png,
+    Zip,
+    Resin,
+    Json,
+    Cbor,
+    Obj,
+    Stl,
+    Sheets,
+}
 
-    let mut p1: usize = 0;
-    let mut p2: usize = 1;
-    while p1 < v1.len() {
-        let mut p3: usize = 0;
-        let mut p4: usize = 1;
-        while p3 < v2.len() {
-            if p3 < v2.len() && p4 < v2.len() {
-                match polygons::get_line_intersection(
-                    v1.get_point(p1),
-                    v1.get_point(p2),
-                    v2.get_point(p3),
-                    v2.get_point(p4),
-                ) {
-                    Some(intersection) => {
-                        v1.insert_point(p1 + 1, intersection, v1.get_normal(p1));
-                        v2.insert_point(p3 + 1, intersection, v2.get_normal(p3));
+impl Format {
+    const ALL: [Format; 11] = [
+        Format::Gcode,
+        Format::Dxf,
+        Format::DxfDir,
+        Format::Png,
+        Format::Zip,
+        Format::Resin,
+        Format::Json,
+        Format::Cbor,
+        Format::Obj,
+        Format::Stl,
+        Format::Sheets,
+    ];
 
-                        // inserted new, hence skip
-                        p1 += 1;
-                        p2 += 1;
-                        p3 += 1;
-                        p4 += 1;
-                    }
-                    None => (),
-                }
-            }
-            p3 += 1;
-            p4 = if p4 == v2.len() - 1 { 0 } else { p4 + 1 };
-        }
-        p1 += 1;
-        p2 = if p2 == v1.len() - 1 { 0 } else { p1 + 1 };
+    /// the name used on the command line, which is also the extension of the files
+    fn name(&self) -> &'static str {
+        return match self {
+            Format::Gcode => "gcode",
+            Format::Dxf => "dxf",
+            Format::DxfDir => "dxf-dir",
+            Format::Png => "png",
+            Format::Zip => "zip",
+            Format::Resin => "dsco",
+            Format::Json => "json",
+            Format::Cbor => "cbor",
+            Format::Obj => "obj",
+            Format::Stl => "stl",
+            Format::Sheets => "sheets",
+        };
     }
 
-    // we are assuming that the length of v1 and v2 is larger then 3
-    let multi_graph = build_multi_graph(&v1, &v2);
-
-    let mut union_staff: Vec<(Point2<f32>, Vector2<f32>)> =
-        vec![(Point2::new(0., 0.), Vector2::new(-1., 0.))];
-    for i in 0..8 {
-        //while union_staff.len() == 1 || union_staff.last() != union_staff.first() {
-        let l = union_staff.len();
-        let (point, _) = union_staff.last().unwrap();
-        let pos = v1
-            .iter()
-            .chain(v2.iter())
-            .position(|(p, _): (&Vertex, &Normal)| *p == *point)
-            .unwrap();
+    fn from_name(name: &str) -> Option<Format> {
+        return Format::ALL.iter().copied().find(|f| f.name() == name);
+    }
 
-        let incoming_vertex = if l == 1 {
-            union_staff[l - 1].0
-        } else {
-            union_staff[l - 2].0
-        };
+    /// formats written as a directory of files
+    fn is_directory(&self) -> bool {
+        return matches!(self, Format::DxfDir | Format::Png | Format::Sheets);
+    }
+}
 
-        let next: (Vertex, Normal) = if multi_graph[pos].len() == 1 {
-            // return successor point
-            multi_graph[pos][0]
-        } else {
-            *multi_graph[pos]
-                .iter()
-                .min_by_key(|(p, n)| {
-                    let d_in =
-                        Vector2::new(point.x - incoming_vertex.x, point.y - incoming_vertex.y)
-                            .normalize();
-                    let d_out = Vector2::new(p.x - point.x, p.y - point.y).normalize();
+/// the parsed command line
+#[derive(Debug)]
+struct Options {
+    model: PathBuf,
+    profiles: Vec<PathBuf>,
+    /// dotted keys and toml values
+    overrides: Vec<(String, String)>,
+    format: Format,
+    output: PathBuf,
+}
 
-                    println!(
-                        "({:?}) {:?} {:?} => {:?}",
-                        p,
-                        d_in,
-                        d_out,
-                        3.14 - (d_in.x * d_out.y - d_out.x * d_in.y)
-                            .atan2(d_in.x * d_out.x + d_in.y * d_out.y)
-                    );
+impl Options {
+    fn parse(args: &[String]) -> Result<Options, String> {
+        let mut model = None;
+        let mut profiles = Vec::new();
+        let mut overrides = Vec::new();
+        let mut format = None;
+        let mut output: Option<PathBuf> = None;
+        let mut i = 0;
+        while i < args.len() {
+            let arg = args[i].as_str();
+            if !arg.starts_with('-') {
+                if model.is_some() {
+                    return Err(format!("unexpected argument `{}`", arg));
+                }
+                model = Some(PathBuf::from(arg));
+                i += 1;
+                continue;
+            }
+            let value = match args.get(i + 1) {
+                Some(value) => value.as_str(),
+                None => return Err(format!("`{}` needs a value", arg)),
+            };
+            match arg {
+                "-p" => profiles.push(PathBuf::from(value)),
+                "-s" => match value.split_once('=') {
+                    Some((key, value)) => overrides.push((key.to_string(), value.to_string())),
+                    None => return Err(format!("expected `<key>=<value>`, got `{}`", value)),
+                },
+                "-f" => match Format::from_name(value) {
+                    Some(f) => format = Some(f),
+                    None => return Err(format!("unknown format `{}`", value)),
+                },
+                "-o" => output = Some(PathBuf::from(value)),
+                _ => return Err(format!("unknown option `{}`", arg)),
+            }
+            i += 2;
+        }
 
-                    return (3.14
-                        - (d_in.x * d_out.y - d_out.x * d_in.y)
-                            .atan2(d_in.x * d_out.x + d_in.y * d_out.y)
-                            * 1000.) as i64;
-                })
-                .unwrap()
+        let model = model.ok_or("no model given")?;
+        let format = match (format, &output) {
+            (Some(format), _) => format,
+            (None, Some(output)) => {
+                let extension = output.extension().and_then(|e| e.to_str()).unwrap_or("");
+                Format::from_name(extension)
+                    .ok_or(format!("unknown format of `{}`", output.display()))?
+            }
+            (None, None) => Format::Gcode,
         };
-        if multi_graph[pos].len() != 1 {
-            println!("{:?}\n", multi_graph[pos]);
-        }
-        union_staff[l - 1].1.x = next.1.x;
-        union_staff[l - 1].1.y = next.1.y;
-        union_staff.push(next);
+        let output = output.unwrap_or_else(|| match format.is_directory() {
+            true => PathBuf::from("sliced"),
+            false => PathBuf::from(format!("sliced.{}", format.name())),
+        });
+        return Ok(Options {
+            model,
+            profiles,
+            overrides,
+            format,
+            output,
+        });
     }
-
-    //println!("{:?}", union_staff);
-    let mut layers: Vec<String> = Vec::new();
-
-    // draw union
-    layers.push(format!(
-        "<g >{}</g>",
-        union_staff
-            .iter()
-            .zip(union_staff.iter().skip(1))
-            .map(|((p1, n1), (p2, n2))| to_line2((*p1, *n1), (*p2, *n2), "orange"))
-            .collect::<Vec<String>>()
-            .join("\n")
-    ));
-
-    //draw connections
-    layers.push(format!(
-        "<g opacity='0.1' >{}</g>",
-        multi_graph
-            .iter()
-            .zip(v1.iter().chain(v2.iter()))
-            .flat_map(|(n, (p1, n1))| n.iter().map(move |x| to_line2(*x, (*p1, *n1), "green")))
-            .collect::<Vec<String>>()
-            .join("\n")
-    ));
-
-    // draw first polygon
-    // layers.push(
-    //     format!(
-    //         "<g >{}</g>",
-    //         v1.iter().zip(v1.iter().skip(1))
-    //         .map(|((p1,n1),(p2,n2))| to_line2((*p1,*n1),(*p2,*n2),"blue"))
-    //         .collect::<Vec<String>>()
-    //         .join("\n")
-    //     )
-    // );
-    // // draw second polygon
-    // layers.push(
-    //     format!(
-    //         "<g >{}</g>",
-    //         v2.iter().zip(v2.iter().skip(1))
-    //         .map(|((p1,n1),(p2,n2))| to_line2((*p1,*n1),(*p2,*n2),"black"))
-    //         .collect::<Vec<String>>()
-    //         .join("\n")
-    //     )
-    // );
-
-    // layers.push(format!(
-    //     "<g >{}</g>",
-    //     [v1, v2]
-    //         .iter()
-    //         .map(|x| to_polygon(x))
-    //         .collect::<Vec<String>>()
-    //         .join("\n")
-    // ));
-    // layers.push(format!(
-    //     "<g >{}</g>",
-    //     intersections
-    //         .iter()
-    //         .map(|i| format!("<circle cx='{}' cy='{}' r='1' fill='red' />", i.x, i.y))
-    //         .collect::<Vec<String>>()
-    //         .join("/n")
-    // ));
-    let mut file = File::create("sliced.html").unwrap();
-    file.write_all(
-        format!(
-            "
-            <!DOCTYPE html>
-            <html>
-                <body>
-                    <svg viewBox='-50 -50 100 100' height='800' width='800'>
-                        {}
-                    </svg>
-                </body>
-            </html>
-        ",
-            layers.join("\n")
-        )
-        .as_bytes(),
-    )
-    .unwrap();
 }
 
-fn build_multi_graph<T>(v1: &AbstractPolygon<T>, v2: &AbstractPolygon<T>) -> Vec<Vec<(T, Normal)>>
-where
-    T: std::fmt::Debug + PartialEq + Copy + Debug,
-{
-    fn next_prev<T>(i: usize, polygon: &AbstractPolygon<T>) -> Vec<(T, Normal)>
-    where
-        T: PartialEq + Copy,
-    {
-        let i_prev = if i == 0 {
-            // since the first and last point are the same we add the second last one
-            polygon.len() - 2
-        } else {
-            i - 1
-        };
-
-        let i_next = if i == polygon.len() - 1 { 1 } else { i + 1 };
-        let p_point = polygon.get_pair(i_prev);
-        let n_point = polygon.get_pair(i_next);
-        vec![p_point, (n_point.0, polygon.normals[i])]
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("{}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
     };
+    if let Err(err) = run(&options) {
+        eprintln!("error: {}", err);
+        process::exit(1);
+    }
+}
 
-    return [(v1, v2), (v2, v1)]
+fn run(options: &Options) -> Result<(), Box<dyn Error>> {
+    let overrides: Vec<(&str, &str)> = options
+        .overrides
         .iter()
-        .flat_map(
-            |(v, v_other): &(&AbstractPolygon<T>, &AbstractPolygon<T>)| {
-                v.iter().enumerate().map(move |(i, (p1, n1))| {
-                    // intersection at this point with other polygon
-                    let intersection = v_other.iter().position(|(p2, _)| *p2 == *p1);
+        .map(|(key, value)| (key.as_str(), value.as_str()))
+        .collect();
+    let profile = Profile::load_all(&options.profiles, &overrides)?;
+    let output = options.output.as_path();
 
-                    let mut next_other = match intersection {
-                        Some(j) => next_prev(j, *v_other),
-                        None => vec![],
-                    };
-                    let this = next_prev(i, *v);
-                    // skip first so you cant go back
-                    next_other.extend(this.iter().skip(1));
-                    return next_other;
-                })
-            },
+    let extension = options.model.extension().and_then(|e| e.to_str());
+    if extension == Some(Format::Resin.name()) {
+        if options.format != Format::Png {
+            return Err("resin prints can only be written as png images".into());
+        }
+        return Ok(write_resin_images(&options.model, output)?);
+    }
+    // sliced layers are rebuilt to a mesh, which supports need to find the overhangs
+    let (model, layers): (Model, Vec<Layer>) = match extension {
+        Some("json") | Some("cbor") => {
+            let layers = SlicedModel::load(&options.model)?.to_layers();
+            (reconstruct(&layers, profile.export.reconstruction), layers)
+        }
+        _ => {
+            let model = Model::load(&options.model.to_string_lossy())
+                .map_err(|err| format!("cannot load {}: {}", options.model.display(), err))?;
+            let layers = model.layers(profile.print.layer_height, profile.print.weld_tolerance);
+            (model, layers)
+        }
+    };
+    match options.format {
+        Format::Gcode => main_slice(&model, layers, &profile, output)?,
+        Format::Dxf => {
+            let mut file = BufWriter::new(File::create(output)?);
+            write_dxf(&mut file, &layers, &DxfSettings::from_profile(&profile))?;
+            file.flush()?;
+        }
+        Format::DxfDir => write_layer_files(&layers, &DxfSettings::from_profile(&profile), output)?,
+        Format::Png => write_layers(
+            &resin_layers(&model, layers, &profile),
+            &RasterSettings::from_profile(&profile),
+            output,
+        )?,
+        Format::Zip => write_zip(
+            &resin_layers(&model, layers, &profile),
+            &RasterSettings::from_profile(&profile),
+            output,
+        )?,
+        Format::Resin => ResinFile::from_layers(
+            &resin_layers(&model, layers, &profile),
+            &RasterSettings::from_profile(&profile),
+            &ExposureSettings::from_profile(&profile),
+            PREVIEW_SIZE,
         )
-        .collect();
+        .save(output)?,
+        Format::Json | Format::Cbor => {
+            // the format given on the command line wins over the extension of the output
+            let encoding = match options.format {
+                Format::Cbor => Encoding::Cbor,
+                _ => Encoding::Json,
+            };
+            SlicedModel::from_layers(&layers).save(output, encoding)?;
+        }
+        Format::Obj => reconstruct(&layers, profile.export.reconstruction)
+            .save_obj(&output.to_string_lossy())?,
+        Format::Stl => reconstruct(&layers, profile.export.reconstruction)
+            .save_stl(&output.to_string_lossy(), profile.export.stl_format)?,
+        Format::Sheets => {
+            // the model is sliced again at the thickness of the sheets
+            let settings = SheetSettings::from_profile(&profile);
+            let layers = slice_sheets(&model, &settings, profile.print.weld_tolerance);
+            write_sheets(&nest(&layers, &settings)?, &settings, output)?;
+        }
+    }
+    return Ok(());
 }
 
-fn main_slice() {
-    let model = Model::load("teapot.obj");
-    let mut layers: Vec<String> = Vec::new();
-    for z in 0..100 {
-        if let Some(outline) = model.slice(z as f32 - 40.) {
-            let polygons = connect_lines(&outline);
-            layers.push(format!(
-                "<g id='slice_{}'>{}</g>",
-                z,
-                polygons
-                    .iter()
-                    .map(|x| to_polygon(&x.points))
-                    .chain(
-                        polygons
-                            .iter()
-                            .map(|poly| poly
-                                .points
-                                .iter()
-                                .zip(poly.points.iter().skip(1))
-                                .zip(poly.normals.iter())
-                                .map(|((p1, p2), v)| to_line(
-                                    Point2::new(p1.x + p2.x, p1.y + p2.y) * 0.5,
-                                    *v * 3.
-                                ))
-                                .collect::<Vec<String>>())
-                            .flatten()
-                    )
-                    .collect::<Vec<String>>()
-                    .join("\n")
+fn main_slice(
+    model: &Model,
+    mut layers: Vec<Layer>,
+    profile: &Profile,
+    output: &FilePath,
+) -> io::Result<()> {
+    // contours crossing themselves come from meshes which are not closed or intersect
+    // themselves, the toolpaths of these layers may have gaps or overlaps
+    let broken = layers
+        .iter()
+        .filter(|l| {
+            find_intersections(&l.polygons)
+                .iter()
+                .any(|i| i.is_self_intersection())
+        })
+        .count();
+    if broken > 0 {
+        eprintln!(
+            "warning: the contours of {} layers cross themselves",
+            broken
+        );
+    }
+    // the supports are kept apart from the layers, so travel moves are not combed through them
+    let mut supports = support_slices(model, &layers, profile);
+    if profile.adhesion.kind == Adhesion::Raft {
+        let raft = add_raft(&layers, &RaftSettings::from_profile(profile));
+        supports.splice(0..0, vec![vec![]; raft.len() - layers.len()]);
+        layers = raft;
+    }
+    let settings = ToolpathSettings {
+        line_width: profile.printer.nozzle_diameter,
+        walls: profile.print.walls,
+        infill: profile.print.infill,
+    };
+    let mut toolpaths: Vec<Vec<Path>> = if profile.print.fixed_point {
+        // the layers stay on the grid, only the output is converted back to floats
+        let mut fixed: Vec<FixedLayer> = layers
+            .iter()
+            .map(|l| l.to_fixed().clean(FillRule::EvenOdd))
+            .collect();
+        if profile.print.resolution > 0. {
+            fixed::simplify_layers(
+                &mut fixed,
+                to_units(profile.print.resolution as f64),
+                profile.print.simplification,
+            );
+        }
+        fixed::place_seams(&mut fixed, SeamPosition::from_profile(profile));
+        layers = fixed.iter().map(|l| l.to_layer()).collect();
+        fixed
+            .iter()
+            .enumerate()
+            .map(|(i, layer)| layer.toolpaths(i, &settings))
+            .collect()
+    } else {
+        if profile.print.resolution > 0. {
+            simplify_layers(
+                &mut layers,
+                profile.print.resolution,
+                profile.print.simplification,
+            );
+        }
+        place_seams(&mut layers, SeamPosition::from_profile(profile));
+        layers
+            .iter()
+            .enumerate()
+            .map(|(i, layer)| layer_toolpaths(layer, i, &settings))
+            .collect()
+    };
+    for (i, (paths, support)) in toolpaths.iter_mut().zip(&supports).enumerate() {
+        let layer = Layer {
+            height: layers[i].height,
+            thickness: layers[i].thickness,
+            polygons: support.clone(),
+        };
+        paths.extend(layer_toolpaths(&layer, i, &settings));
+    }
+    // skirt and brim are printed first, the travel moves to them are added by the optimisation
+    if let (Some(first), Some(paths)) = (layers.first(), toolpaths.first_mut()) {
+        let adhesion = &profile.adhesion;
+        let loops = match adhesion.kind {
+            Adhesion::Skirt => skirt(
+                first,
+                adhesion.skirt_distance,
+                adhesion.loops,
+                settings.line_width,
+            ),
+            Adhesion::Brim => brim(first, adhesion.loops, settings.line_width),
+            Adhesion::None | Adhesion::Raft => vec![],
+        };
+        let material = paths.first().map(|p| p.material).unwrap_or(0);
+        let loops: Vec<Path> = loops
+            .into_iter()
+            .map(|p| Path {
+                kind: PathKind::Perimeter,
+                points: p.points,
+                material,
+            })
+            .collect();
+        paths.splice(0..0, loops);
+    }
+    let (toolpaths, travel) = optimize_layers(&toolpaths, true);
+    println!(
+        "travel: {:.0} mm before, {:.0} mm after optimisation",
+        travel.before, travel.after
+    );
+    let mut gcode = GcodeSettings::from_profile(profile);
+    // several materials are printed with several tools, which prime on a tower
+    let toolpaths = if model.materials().len() > 1 {
+        let tools = &profile.tools;
+        if tools.tower_size > 0. {
+            gcode.tools.prime_tower = Some(PrimeTower::beside(
+                &layers,
+                tools.tower_size,
+                tools.tower_distance,
             ));
         }
+        plan_tools(&mut layers, &toolpaths, &gcode.tools, settings.line_width)
+    } else {
+        toolpaths
+    };
+    let mut file = BufWriter::new(File::create(output)?);
+    write_gcode(&mut file, &layers, &toolpaths, &gcode)?;
+    file.flush()?;
+    let viewer = ViewerSettings {
+        line_width: settings.line_width,
+        ..ViewerSettings::default()
+    };
+    for (layer, support) in layers.iter_mut().zip(supports) {
+        layer.polygons.extend(support);
     }
-    let mut file = File::create("sliced.html").unwrap();
-    file.write_all(
-        format!(
-            "
-    <!DOCTYPE html>
-    <html>
-        <body>
-            <svg viewBox='-100 -100 200 200' height='500' width='500'>
-                {}
-            </svg>
-            <input type='range' min='0' max='99' value='0' class='slider' id='range'>
-            <script>
-            var slider = document.getElementById('range');
-            let slices = document.querySelectorAll('[id^=\"slice_\"]');
-            let min = parseInt(slices[0].id.substr(6));
-            slider.min = min;
-            slider.max = min+slices.length;
-            slider.oninput = function() {{
-                for(var i=min;i<(min+slices.length);i++){{
-                    var elem = document.getElementById('slice_'+i);
-                    if(!elem)
-                        break;
-                    elem.style.visibility = i== slider.value ? 'visible' : 'hidden';
-                }}
-            }}
-            </script>
-        </body>
-    </html>
-
-    ",
-            layers.join("\n")
-        )
-        .as_bytes(),
-    )
-    .unwrap();
+    let mut file = BufWriter::new(File::create(output.with_extension("html"))?);
+    write_viewer(&mut file, &layers, &toolpaths, model.materials(), &viewer)?;
+    return file.flush();
 }
 
-fn to_polygon(points: &Vec<Vertex>) -> String {
-    let formatted: Vec<String> = points.iter().map(|p| format!("{},{}", p.x, p.y)).collect();
-    return format!(
-        "<polygon points='{}' style='fill:none;stroke:purple;stroke-width:0.1'/>",
-        formatted.join(" ")
-    );
+/// the supports chosen in the profile for every layer
+fn support_slices(model: &Model, layers: &[Layer], profile: &Profile) -> Vec<Slice> {
+    return match profile.support.kind {
+        SupportKind::None => vec![vec![]; layers.len()],
+        SupportKind::Lines | SupportKind::Grid => {
+            generate_supports(model, layers, &SupportSettings::from_profile(profile))
+        }
+        SupportKind::Tree => {
+            let supports =
+                generate_tree_supports(model, layers, &TreeSupportSettings::from_profile(profile));
+            // branches may overlap, they are merged so they do not cancel out
+            supports
+                .iter()
+                .map(|support| clean(support, FillRule::NonZero))
+                .collect()
+        }
+    };
 }
 
-fn to_line(p: Vertex, v: Normal) -> String {
-    return format!(
-        "<line x1='{}' y1='{}' x2='{}' y2='{}' stroke='red' stroke-width='0.1' />",
-        p.x,
-        p.y,
-        p.x + v.x,
-        p.y + v.y
-    );
+/// Hollows the layers for printing with resin and adds the supports. A drain hole is cut
+/// through the bottom wall below the center of the model.
+fn resin_layers(model: &Model, mut layers: Vec<Layer>, profile: &Profile) -> Vec<Layer> {
+    let resin = &profile.resin;
+    if resin.hollow_wall > 0. {
+        layers = hollow(&layers, &HollowSettings::from_profile(profile));
+        if resin.drain_diameter > 0. {
+            let (min, max) = model.bounds();
+            let hole = DrainHole {
+                position: Point2::new((min.x + max.x) / 2., (min.z + max.z) / 2.),
+                diameter: resin.drain_diameter,
+                bottom: min.y,
+                // through the bottom wall into the cavity
+                depth: 2. * resin.hollow_wall,
+            };
+            layers = drain(&layers, &[hole], DRAIN_SEGMENTS);
+        }
+    }
+    let supports = support_slices(model, &layers, profile);
+    for (layer, support) in layers.iter_mut().zip(supports) {
+        layer.polygons.extend(support);
+    }
+    return layers;
 }
 
-fn to_line2((p1, n1): (Vertex, Normal), (p2, _): (Vertex, Normal), color: &str) -> String {
-    let center = Point2::new((p1.x + p2.x) / 2., (p1.y + p2.y) / 2.);
-    return format!(
-        "<line x1='{}' y1='{}' x2='{}' y2='{}' stroke='{color}' stroke-width='0.1' />
-        <line x1='{}' y1='{}' x2='{}' y2='{}' stroke='red' stroke-width='0.1' />"
-        //<circle cx='{}' cy='{}' r='1' fill='red' />
-        ,//",
-        p1.x,
-        p1.y,
-        p2.x,
-        p2.y,
-        center.x,
-        center.y,
-        center.x + n1.x,
-        center.y + n1.y,
-        // (p1.x + 3. * p2.x) / 4.,
-        // (p1.y + 3. * p2.y) / 4.,
-        color = color,
-    );
+/// writes the layers of a resin print as png images into the directory
+fn write_resin_images(file: &FilePath, dir: &FilePath) -> io::Result<()> {
+    let resin = ResinFile::load(file)?;
+    fs::create_dir_all(dir)?;
+    for i in 0..resin.layers.len() {
+        write_image(resin.layer_image(i)?, i, dir)?;
+    }
+    return Ok(());
 }
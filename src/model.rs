@@ -1,46 +1,150 @@
-use crate::polygons::Polygon;
+use crate::polygons::{connect_lines, scalar, Polygon};
 use cgmath::*;
-
-use std::cmp::Ordering;
+use serde::Deserialize;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 use std::path::Path;
-use tobj::{load_obj, Model as TobjModel};
+use tobj::{load_obj, LoadError};
+
+pub type Slice<S = f32> = Vec<Polygon<S>>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StlFormat {
+    Ascii,
+    Binary,
+}
+
+/// a material of the model, used to print parts of a model with different extruders
+#[derive(Debug, Clone, PartialEq)]
+pub struct Material {
+    pub name: String,
+    /// diffuse color (`Kd` in mtl files), each channel between 0 and 1
+    pub color: [f32; 3],
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        return Material {
+            name: "default".to_string(),
+            color: [0.5, 0.5, 0.5],
+        };
+    }
+}
 
-pub type Slice = Vec<Polygon>;
+impl Material {
+    /// the color as css hex string, e.g. `#21bb4c`
+    pub fn hex_color(&self) -> String {
+        let [r, g, b] = self.color.map(|c| (c.clamp(0., 1.) * 255.).round() as u8);
+        return format!("#{:02x}{:02x}{:02x}", r, g, b);
+    }
+}
 
+/// the closed polygons of a slice together with the height it was taken at
+#[derive(Debug, Clone)]
+pub struct Layer<S = f32> {
+    pub height: S,
+    /// distance between the bottom and the top of the layer, the slice is taken in its middle
+    pub thickness: S,
+    pub polygons: Slice<S>,
+}
+
+/// A triangle mesh. The coordinates are `f32` by default, `f64` keeps parts far away from
+/// the origin precise.
 #[derive(Debug)]
-pub struct Model {
+pub struct Model<S = f32> {
     // all points in model
-    vertices: Vec<Point3<f32>>,
+    vertices: Vec<Point3<S>>,
     // normal for every face
-    normals: Vec<Vector3<f32>>,
+    normals: Vec<Vector3<S>>,
     // the model's faces
     faces: Vec<[u32; 3]>,
+    // index into materials for every face
+    face_materials: Vec<usize>,
+    // there is at least one material
+    materials: Vec<Material>,
 }
 
-impl Model {
-    // loads model from file
-    pub fn load(file: &str) -> Model {
-        let obj_data = load_obj(&Path::new(&file));
-        assert!(obj_data.is_ok());
+impl<S: BaseFloat> Model<S> {
+    // loads model from file, all objects of the file are merged into one model
+    pub fn load(file: &str) -> Result<Model<S>, LoadError> {
+        let (models, obj_materials) = load_obj(Path::new(&file))?;
+        let mut materials: Vec<Material> = obj_materials
+            .iter()
+            .map(|m| Material {
+                name: m.name.clone(),
+                color: m.diffuse,
+            })
+            .collect();
+        // faces without a material get a default one
+        let default = materials.len();
+        if materials.is_empty() || models.iter().any(|m| m.mesh.material_id.is_none()) {
+            materials.push(Material::default());
+        }
 
-        let (models, _) = obj_data.unwrap();
-        let m: &TobjModel = &models[0];
+        let mut vertices: Vec<Point3<S>> = Vec::new();
+        let mut indices: Vec<[u32; 3]> = Vec::new();
+        let mut face_materials: Vec<usize> = Vec::new();
+        for m in &models {
+            let offset = vertices.len() as u32;
+            vertices.extend(
+                m.mesh
+                    .positions
+                    .chunks(3)
+                    .map(|v| Point3::new(v[0], v[1], v[2]).cast().unwrap()),
+            );
+            let faces = m
+                .mesh
+                .indices
+                .chunks(3)
+                .map(|f| [f[0] + offset, f[1] + offset, f[2] + offset]);
+            let before = indices.len();
+            indices.extend(faces);
+            let material = m.mesh.material_id.unwrap_or(default);
+            face_materials.extend(std::iter::repeat_n(material, indices.len() - before));
+        }
+        return Ok(Model::new(vertices, indices).with_materials(materials, face_materials));
+    }
 
-        let vertices: Vec<Point3<f32>> = m
-            .mesh
-            .positions
-            .chunks(3)
-            .map(|v| Point3::new(v[0], v[1], v[2]))
-            .collect();
-        let indices: Vec<[u32; 3]> = m
-            .mesh
-            .indices
-            .chunks(3)
-            .map(|f| [f[0], f[1], f[2]])
-            .collect();
+    /// assigns a material to every face, `face_materials` holds an index into `materials`
+    pub fn with_materials(
+        mut self,
+        materials: Vec<Material>,
+        face_materials: Vec<usize>,
+    ) -> Model<S> {
+        assert_eq!(face_materials.len(), self.faces.len());
+        assert!(face_materials.iter().all(|m| *m < materials.len()));
+        self.materials = materials;
+        self.face_materials = face_materials;
+        return self;
+    }
+
+    pub fn materials(&self) -> &[Material] {
+        return &self.materials;
+    }
+
+    /// creates a model from its vertices and the indices of the corners of each triangle.
+    /// The faces have to be ordered consistently, either all clockwise or all counter clockwise
+    pub fn new(vertices: Vec<Point3<S>>, indices: Vec<[u32; 3]>) -> Model<S> {
+        // the signed volume tells us if the faces are ordered clockwise or counter clockwise,
+        // it is used to let all normals point outwards
+        let volume: S = indices.iter().fold(S::zero(), |volume, [i1, i2, i3]| {
+            let (v1, v2, v3) = (
+                vertices[*i1 as usize].to_vec(),
+                vertices[*i2 as usize].to_vec(),
+                vertices[*i3 as usize].to_vec(),
+            );
+            return volume + v1.dot(v2.cross(v3));
+        });
+        let orientation = if volume > S::zero() {
+            -S::one()
+        } else {
+            S::one()
+        };
 
         // calculate normal for each face
-        let normals: Vec<Vector3<f32>> = indices
+        let normals: Vec<Vector3<S>> = indices
             .iter()
             .map(|[i1, i2, i3]| {
                 let (v1, v2, v3) = (
@@ -48,39 +152,101 @@ impl Model {
                     vertices[*i2 as usize],
                     vertices[*i3 as usize],
                 );
-                let normal: Vector3<f32> = (v1 - v2).cross(v3 - v1).normalize().into();
+                let normal: Vector3<S> = (v1 - v2).cross(v3 - v1).normalize() * orientation;
 
                 return normal;
             })
             .collect();
 
         return Model {
-            vertices: vertices,
-            normals: normals,
+            vertices,
+            normals,
+            face_materials: vec![0; indices.len()],
             faces: indices,
+            materials: vec![Material::default()],
         };
     }
+
+    /// the faces with their corners ordered counter clockwise when looking against the normal,
+    /// which is the order most programs expect
+    fn oriented_faces(&self) -> impl Iterator<Item = ([u32; 3], Vector3<S>)> + '_ {
+        return self.faces.iter().zip(&self.normals).map(move |(face, n)| {
+            let [a, b, c] = face.map(|i| self.vertices[i as usize]);
+            if (b - a).cross(c - a).dot(*n) < S::zero() {
+                return ([face[0], face[2], face[1]], *n);
+            }
+            return (*face, *n);
+        });
+    }
+
+    /// smallest and largest corner of the model's bounding box
+    pub fn bounds(&self) -> (Point3<S>, Point3<S>) {
+        let first = self.vertices[0];
+        return self.vertices.iter().fold((first, first), |(min, max), v| {
+            (
+                Point3::new(min.x.min(v.x), min.y.min(v.y), min.z.min(v.z)),
+                Point3::new(max.x.max(v.x), max.y.max(v.y), max.z.max(v.z)),
+            )
+        });
+    }
+
+    /// iterates over the corners and the normal of every face
+    pub fn triangles(&self) -> impl Iterator<Item = ([Point3<S>; 3], Vector3<S>)> + '_ {
+        return self
+            .faces
+            .iter()
+            .zip(&self.normals)
+            .map(move |([i1, i2, i3], n)| {
+                (
+                    [
+                        self.vertices[*i1 as usize],
+                        self.vertices[*i2 as usize],
+                        self.vertices[*i3 as usize],
+                    ],
+                    *n,
+                )
+            });
+    }
+
+    /// slices the whole model into layers of closed polygons.
+    /// every layer is cut in the middle of its height range
+    pub fn layers(&self, layer_height: S, tolerance: S) -> Vec<Layer<S>> {
+        let (min, max) = self.bounds();
+        let count = ((max.y - min.y) / layer_height).ceil().to_usize().unwrap();
+        return (0..count)
+            .filter_map(|i| {
+                let height = min.y + (S::from(i).unwrap() + scalar(0.5)) * layer_height;
+                self.slice(height).map(|outline| Layer {
+                    height,
+                    thickness: layer_height,
+                    polygons: connect_lines(&outline, tolerance),
+                })
+            })
+            .collect();
+    }
+
     /// creates a slice of a model at a given height (y)
-    pub fn slice(&self, y: f32) -> Option<Slice> {
+    pub fn slice(&self, y: S) -> Option<Slice<S>> {
         let outline = self
             .faces
             .iter()
             .zip(&self.normals)
-            .filter_map(|([i1, i2, i3], normal)| {
+            .zip(&self.face_materials)
+            .filter_map(|(([i1, i2, i3], normal), material)| {
                 let v1 = self.vertices[*i1 as usize];
                 let v2 = self.vertices[*i2 as usize];
                 let v3 = self.vertices[*i3 as usize];
 
                 // the 3 lines of a triangle represented as start point and vector pointing to the end
-                let (mut points, normals): (Vec<Point2<f32>>, Vec<Vector2<f32>>) =
+                let (mut points, normals): (Vec<Point2<S>>, Vec<Vector2<S>>) =
                     [(v1, v2 - v1), (v2, v3 - v2), (v3, v1 - v3)]
                         .iter()
                         .filter_map(|(p, d)| {
-                            if d.y == 0. {
+                            if d.y == S::zero() {
                                 return None;
                             } else {
-                                let t: f32 = (y - p.y) / (d.y);
-                                if t >= 0. && t <= 1. {
+                                let t: S = (y - p.y) / (d.y);
+                                if t >= S::zero() && t <= S::one() {
                                     // the intersection is within the start and end of the line
                                     let intsec = p + d * t;
                                     return Some((
@@ -95,7 +261,7 @@ impl Model {
                         })
                         .unzip();
 
-                if points.len() > 0 {
+                if !points.is_empty() {
                     if points.len() == 3 {
                         // this is a special case, we have the same point two times
                         if points[0] == points[1] {
@@ -111,18 +277,207 @@ impl Model {
                     }
                     // Only return a polygon if the triangle intersects the plane
                     return Some(Polygon {
-                        points: points,
+                        points,
                         normals: normals[..1].into(), // only take first normal
+                        material: *material,
                     });
                 } else {
                     return None;
                 }
             })
-            .collect::<Vec<Polygon>>();
-        if outline.len() > 0 {
+            .collect::<Vec<Polygon<S>>>();
+        if !outline.is_empty() {
             return Some(outline);
         } else {
             return None;
         }
     }
+
+    /// writes the model as Wavefront OBJ with one normal per face
+    pub fn save_obj(&self, file: &str) -> io::Result<()>
+    where
+        S: Display,
+    {
+        let mut writer = BufWriter::new(File::create(file)?);
+        self.write_obj(&mut writer)?;
+        return writer.flush();
+    }
+
+    pub fn write_obj<W: Write>(&self, w: &mut W) -> io::Result<()>
+    where
+        S: Display,
+    {
+        for v in &self.vertices {
+            writeln!(w, "v {} {} {}", v.x, v.y, v.z)?;
+        }
+        for n in &self.normals {
+            writeln!(w, "vn {} {} {}", n.x, n.y, n.z)?;
+        }
+        // indices in obj files start at 1
+        for (i, ([a, b, c], _)) in self.oriented_faces().enumerate() {
+            let n = i + 1;
+            writeln!(w, "f {}//{} {}//{} {}//{}", a + 1, n, b + 1, n, c + 1, n)?;
+        }
+        return Ok(());
+    }
+
+    pub fn save_stl(&self, file: &str, format: StlFormat) -> io::Result<()>
+    where
+        S: Display,
+    {
+        let mut writer = BufWriter::new(File::create(file)?);
+        self.write_stl(&mut writer, format)?;
+        return writer.flush();
+    }
+
+    pub fn write_stl<W: Write>(&self, w: &mut W, format: StlFormat) -> io::Result<()>
+    where
+        S: Display,
+    {
+        let triangles = self
+            .oriented_faces()
+            .map(|(face, n)| (face.map(|i| self.vertices[i as usize]), n));
+        match format {
+            StlFormat::Ascii => {
+                writeln!(w, "solid disco")?;
+                for (corners, n) in triangles {
+                    writeln!(w, "facet normal {} {} {}", n.x, n.y, n.z)?;
+                    writeln!(w, "outer loop")?;
+                    for v in corners.iter() {
+                        writeln!(w, "vertex {} {} {}", v.x, v.y, v.z)?;
+                    }
+                    writeln!(w, "endloop")?;
+                    writeln!(w, "endfacet")?;
+                }
+                writeln!(w, "endsolid disco")?;
+            }
+            StlFormat::Binary => {
+                // 80 byte header, which must not start with "solid"
+                let mut header = [0u8; 80];
+                header[..5].copy_from_slice(b"disco");
+                w.write_all(&header)?;
+                w.write_all(&(self.faces.len() as u32).to_le_bytes())?;
+                for (corners, n) in triangles {
+                    // binary files always store single precision
+                    for value in [n.x, n.y, n.z].iter() {
+                        w.write_all(&value.to_f32().unwrap().to_le_bytes())?;
+                    }
+                    for v in corners.iter() {
+                        for value in [v.x, v.y, v.z].iter() {
+                            w.write_all(&value.to_f32().unwrap().to_le_bytes())?;
+                        }
+                    }
+                    // attribute byte count, unused
+                    w.write_all(&[0, 0])?;
+                }
+            }
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+
+    /// Builds a model of axis aligned boxes, each given by its smallest and largest corner.
+    /// The boxes are closed meshes of their own, touching boxes are not joined.
+    pub fn boxes(boxes: &[([f32; 3], [f32; 3])]) -> Model {
+        // the corners of each side ordered counter clockwise when looking from outside,
+        // bit 0 of an index selects the largest x, bit 1 y and bit 2 z
+        const SIDES: [[u32; 4]; 6] = [
+            [0, 4, 6, 2],
+            [1, 3, 7, 5],
+            [0, 1, 5, 4],
+            [2, 6, 7, 3],
+            [0, 2, 3, 1],
+            [4, 5, 7, 6],
+        ];
+        let mut vertices = Vec::new();
+        let mut indices = Vec::new();
+        for (min, max) in boxes {
+            let offset = vertices.len() as u32;
+            vertices.extend((0..8).map(|i| {
+                let pick = |bit: usize, axis: usize| match i & (1 << bit) {
+                    0 => min[axis],
+                    _ => max[axis],
+                };
+                Point3::new(pick(0, 0), pick(1, 1), pick(2, 2))
+            }));
+            for [a, b, c, d] in SIDES.iter() {
+                indices.push([a + offset, b + offset, c + offset]);
+                indices.push([a + offset, c + offset, d + offset]);
+            }
+        }
+        return Model::new(vertices, indices);
+    }
+
+    #[test]
+    fn slice_a_box() {
+        let model = boxes(&[([0., 0., 0.], [4., 1., 2.])]);
+        let layers = model.layers(0.25, 0.01);
+        assert_eq!(layers.len(), 4);
+        for (i, layer) in layers.iter().enumerate() {
+            assert!((layer.height - (0.125 + 0.25 * i as f32)).abs() < 1e-6);
+            assert_eq!(layer.polygons.len(), 1);
+            assert!((layer.polygons[0].area().abs() - 8.).abs() < 1e-4);
+        }
+        // the faces point outwards
+        let bottom = model.triangles().filter(|(_, n)| n.y < -0.99).count();
+        assert_eq!(bottom, 2);
+    }
+
+    #[test]
+    fn obj_files_load_back() {
+        let model = boxes(&[([0., 0., 0.], [4., 1., 2.]), ([5., 0., 0.], [6., 3., 1.])]);
+        let path = std::env::temp_dir().join(format!("disco-{}.obj", std::process::id()));
+        model.save_obj(&path.to_string_lossy()).unwrap();
+        let loaded: Model = Model::load(&path.to_string_lossy()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.bounds(), model.bounds());
+        let triangles: Vec<_> = model.triangles().collect();
+        let loaded: Vec<_> = loaded.triangles().collect();
+        assert_eq!(loaded, triangles);
+    }
+
+    #[test]
+    fn missing_files_are_reported() {
+        let path = std::env::temp_dir().join("disco-missing.obj");
+        assert!(Model::<f32>::load(&path.to_string_lossy()).is_err());
+    }
+
+    #[test]
+    fn stl_has_one_facet_per_face() {
+        let model = boxes(&[([0., 0., 0.], [4., 1., 2.])]);
+
+        let mut binary = Vec::new();
+        model.write_stl(&mut binary, StlFormat::Binary).unwrap();
+        assert_eq!(binary.len(), 84 + 50 * 12);
+        assert_eq!(&binary[80..84], &12u32.to_le_bytes());
+        // the first corner of the first facet follows its normal
+        let value = |offset: usize| {
+            let bytes = [
+                binary[offset],
+                binary[offset + 1],
+                binary[offset + 2],
+                binary[offset + 3],
+            ];
+            f32::from_le_bytes(bytes)
+        };
+        let (corners, n) = model.triangles().next().unwrap();
+        assert_eq!([value(84), value(88), value(92)], [n.x, n.y, n.z]);
+        assert_eq!(
+            [value(96), value(100), value(104)],
+            [corners[0].x, corners[0].y, corners[0].z]
+        );
+
+        let mut ascii = Vec::new();
+        model.write_stl(&mut ascii, StlFormat::Ascii).unwrap();
+        let ascii = String::from_utf8(ascii).unwrap();
+        assert!(ascii.starts_with("solid disco\n"));
+        assert!(ascii.ends_with("endsolid disco\n"));
+        assert_eq!(ascii.matches("facet normal").count(), 12);
+        assert_eq!(ascii.matches("vertex").count(), 36);
+    }
 }
@@ -1,47 +1,229 @@
+use crate::predicates::{
+    incircle, intersect_segments, orient2d, Circle, Orientation, SegmentIntersection,
+};
 use cgmath::*;
+use serde::Deserialize;
+use std::cmp::Ordering;
 
-pub type Vertex = Point2<f32>;
-pub type Normal = Vector2<f32>;
+/// the coordinates are `f32` unless a pipeline asks for more precision
+pub type Vertex<S = f32> = Point2<S>;
+pub type Normal<S = f32> = Vector2<S>;
+
+/// a constant in the scalar type of the coordinates
+pub fn scalar<S: BaseFloat>(value: f64) -> S {
+    return S::from(value).unwrap();
+}
 
 #[derive(Debug, Clone, PartialEq)]
-pub struct AbstractPolygon<T> where T:PartialEq+Copy{
+pub struct AbstractPolygon<T, S = f32> {
     /// list of n points
     pub points: Vec<T>,
     /// list of n normals, one for each line
-    pub normals: Vec<Normal>,
+    pub normals: Vec<Normal<S>>,
+    /// index of the material the polygon is made of, see `Model::materials`
+    pub material: usize,
 }
 
-impl<T> AbstractPolygon<T>  where T:PartialEq+Copy{
+// element accessors, kept for users of the polygon type even though the slicer itself
+// works on `points` and `normals` directly
+#[allow(dead_code)]
+impl<T, S> AbstractPolygon<T, S>
+where
+    T: PartialEq + Copy,
+    S: BaseFloat,
+{
     pub fn len(&self) -> usize {
         return self.points.len();
     }
 
-    pub fn get_point(&self,i: usize) -> T{
+    pub fn get_point(&self, i: usize) -> T {
         return self.points[i];
     }
-    
-    pub fn get_normal(&self,i: usize) -> Normal{
-        return  self.normals[i];
+
+    pub fn get_normal(&self, i: usize) -> Normal<S> {
+        return self.normals[i];
     }
 
-    pub fn get_pair(&self,i: usize) -> (T,Normal){
-        return  (self.points[i],self.normals[i]);
+    pub fn get_pair(&self, i: usize) -> (T, Normal<S>) {
+        return (self.points[i], self.normals[i]);
     }
 
-    pub fn insert_point(&mut self,i: usize,point: T, normal: Normal){
+    pub fn insert_point(&mut self, i: usize, point: T, normal: Normal<S>) {
         self.points.insert(i, point);
         self.normals.insert(i, normal);
     }
 
-    pub fn iter(&self) -> std::iter::Zip<std::slice::Iter<'_, T>, std::slice::Iter<'_, Normal>>{
-        return self.points.iter().zip(self.normals.iter())
+    pub fn iter(&self) -> std::iter::Zip<std::slice::Iter<'_, T>, std::slice::Iter<'_, Normal<S>>> {
+        return self.points.iter().zip(self.normals.iter());
+    }
+}
+
+impl<T, S> AbstractPolygon<T, S>
+where
+    T: PartialEq + Copy,
+    S: BaseFloat,
+{
+    /// iterates over the lines between consecutive points
+    pub fn edges(&self) -> impl Iterator<Item = (T, T)> + '_ {
+        return self.points.windows(2).map(|w| (w[0], w[1]));
     }
 }
 
-pub type Polygon = AbstractPolygon<Vertex>;
+pub type Polygon<S = f32> = AbstractPolygon<Vertex<S>, S>;
+
+impl<S: BaseFloat> Polygon<S> {
+    /// closed axis aligned rectangle with outward facing normals
+    pub fn rectangle(min: Vertex<S>, max: Vertex<S>) -> Polygon<S> {
+        return Polygon {
+            points: vec![
+                min,
+                Point2::new(max.x, min.y),
+                max,
+                Point2::new(min.x, max.y),
+                min,
+            ],
+            normals: vec![
+                Vector2::new(S::zero(), -S::one()),
+                Vector2::unit_x(),
+                Vector2::unit_y(),
+                Vector2::new(-S::one(), S::zero()),
+            ],
+            material: 0,
+        };
+    }
+
+    /// closed regular polygon approximating a circle, normals point outwards
+    pub fn circle(center: Vertex<S>, radius: S, segments: usize) -> Polygon<S> {
+        let step = Rad::<S>::full_turn().0 / S::from(segments).unwrap();
+        let angle = |i: usize| S::from(i).unwrap() * step;
+        // the last point repeats the first exactly, a full turn does not round back to it
+        let point = |i: usize| {
+            let a = angle(i % segments);
+            return center + Vector2::new(a.cos(), a.sin()) * radius;
+        };
+        return Polygon {
+            points: (0..=segments).map(point).collect(),
+            normals: (0..segments)
+                .map(|i| {
+                    let a = angle(i) + step / scalar(2.);
+                    Vector2::new(a.cos(), a.sin())
+                })
+                .collect(),
+            material: 0,
+        };
+    }
 
-impl Polygon {
-    pub fn to_indices(&self, vertices: &mut Vec<Vertex>) -> IndexPolygon {
+    /// checks if the point lies inside of the closed polygon
+    pub fn contains(&self, point: Vertex<S>) -> bool {
+        return inside(std::slice::from_ref(self), point);
+    }
+
+    /// shortest distance between the point and the polygon's outline
+    pub fn distance(&self, point: Vertex<S>) -> S {
+        return self
+            .edges()
+            .map(|(a, b)| {
+                let d = b - a;
+                let t = if d.magnitude2() > S::zero() {
+                    ((point - a).dot(d) / d.magnitude2())
+                        .max(S::zero())
+                        .min(S::one())
+                } else {
+                    S::zero()
+                };
+                (a + d * t - point).magnitude()
+            })
+            .fold(S::infinity(), S::min);
+    }
+
+    /// signed area, positive if the points are ordered counter clockwise
+    pub fn area(&self) -> S {
+        return self
+            .edges()
+            .fold(S::zero(), |sum, (a, b)| sum + a.x * b.y - b.x * a.y)
+            / scalar(2.);
+    }
+
+    /// Moves every line of the closed polygon by `distance` away from the area it encloses,
+    /// negative distances shrink the polygon. Corners are mitered, sharp corners are beveled.
+    /// The normals keep the side they were pointing to.
+    /// Returns `None` if the polygon vanishes or turns inside out.
+    pub fn offset(&self, distance: S) -> Option<Polygon<S>> {
+        let area = self.area();
+        if area == S::zero() {
+            return None;
+        }
+        // direction of the outside, relative to the walking direction
+        let outside = if area > S::zero() {
+            S::one()
+        } else {
+            -S::one()
+        };
+
+        // drop lines without length
+        let edges: Vec<(Vertex<S>, Vertex<S>, Normal<S>)> = self
+            .edges()
+            .zip(self.normals.iter())
+            .filter(|((a, b), _)| a != b)
+            .map(|((a, b), n)| (a, b, *n))
+            .collect();
+        if edges.len() < 3 {
+            return None;
+        }
+
+        let perpendicular = |(a, b, _): &(Vertex<S>, Vertex<S>, Normal<S>)| {
+            let d = (*b - *a).normalize();
+            return Vector2::new(d.y, -d.x) * outside;
+        };
+
+        let mut points: Vec<Vertex<S>> = Vec::new();
+        let mut normals: Vec<Normal<S>> = Vec::new();
+        for i in 0..edges.len() {
+            let previous = &edges[(i + edges.len() - 1) % edges.len()];
+            let current = &edges[i];
+            let (n1, n2) = (perpendicular(previous), perpendicular(current));
+            let corner = current.0;
+            let cos = n1.dot(n2);
+            // the normal of the new line keeps the orientation of the original one
+            let normal = if n2.dot(current.2) < S::zero() {
+                -n2
+            } else {
+                n2
+            };
+            // miters longer than twice the distance are cut off
+            if cos > scalar(-0.5) {
+                let miter = (n1 + n2) / (S::one() + cos);
+                points.push(corner + miter * distance);
+                normals.push(normal);
+            } else {
+                let bevel = if n1.dot(previous.2) < S::zero() {
+                    -n1
+                } else {
+                    n1
+                };
+                points.push(corner + n1 * distance);
+                normals.push(bevel.lerp(normal, scalar(0.5)).normalize());
+                points.push(corner + n2 * distance);
+                normals.push(normal);
+            }
+        }
+        points.push(points[0]);
+
+        let result = Polygon {
+            points,
+            normals,
+            material: self.material,
+        };
+        let new_area = result.area();
+        if new_area * area <= S::zero() || (distance < S::zero() && new_area.abs() > area.abs()) {
+            return None;
+        }
+        return Some(result);
+    }
+
+    /// points within `tolerance` of an existing vertex are mapped onto it, equal points are
+    /// always merged
+    pub fn to_indices(&self, vertices: &mut Vec<Vertex<S>>, tolerance: S) -> IndexPolygon<S> {
         let indices = self
             .points
             .iter()
@@ -49,7 +231,7 @@ impl Polygon {
                 let hit = vertices
                     .iter()
                     .enumerate()
-                    .filter(|(_, x)| (**x - p).magnitude() < 0.01) // smaler than 0.1 mm
+                    .filter(|(_, x)| **x == *p || (**x - p).magnitude2() <= tolerance * tolerance)
                     .map(|(i, _)| i)
                     .next();
                 match hit {
@@ -66,65 +248,110 @@ impl Polygon {
         return IndexPolygon {
             points: indices,
             normals: self.normals.clone(),
+            material: self.material,
         };
     }
+}
+
+/// Joins the line segments of a slice into closed polygons. Lines of different materials
+/// are joined as well, each polygon gets the material covering most of its length.
+pub fn connect_lines<S: BaseFloat>(lines: &[Polygon<S>], tolerance: S) -> Vec<Polygon<S>> {
+    let mut vertices: Vec<Vertex<S>> = Vec::new();
+    // convert points into list of indices => group similar points
+    // every line keeps the length of its edges of each material
+    let mut polygons: Vec<(IndexPolygon<S>, MaterialLengths<S>)> = lines
+        .iter()
+        // convert vectors to indices
+        .map(|p| (p.to_indices(&mut vertices, tolerance), material_lengths(p)))
+        .collect();
+
+    // remove duplicates
+    polygons.dedup_by(|(p1, _), (p2, _)| p1.points == p2.points);
 
-    pub fn union(&self, other: Polygon) -> Vec<Polygon> {
-        let mut v1: Polygon = self.clone();
-        let mut v2: Polygon = other.clone();
-
-        let mut p1: usize = 0;
-        let mut p2: usize = 1;
-        while p1 < v1.len() {
-            let mut p3: usize = 0;
-            let mut p4: usize = 1;
-            while p3 < v2.len() {
-                if p3 < v2.len() && p4 < v2.len() {
-                    match get_line_intersection(
-                        v1.points[p1],
-                        v1.points[p2],
-                        v2.points[p3],
-                        v2.points[p4],
-                    ) {
-                        Some(i) => {
-                            //TODO insert does not work, solve it another way
-                            v1.points.insert(p1 + 1, i);
-                            v2.points.insert(p3 + 1, i);
-
-                            // inserted new, hence skip
-                            p1 += 1;
-                            p2 += 1;
-                            p3 += 1;
-                            p4 += 1;
-                        }
-                        None => (),
+    // combine lines into closed polygons
+    // this is done by iteratively joining lines together until no new lines can be joined
+    let mut new = true;
+    while new {
+        new = false;
+        let mut i = 0;
+        while i < polygons.len() {
+            let mut j = i + 1;
+            while j < polygons.len() {
+                let l1 = polygons[i].0.clone();
+                let l2 = polygons[j].0.clone();
+                match l1.join(l2) {
+                    Some(result) => {
+                        let (_, lengths) = polygons.remove(j);
+                        polygons[i].0 = result;
+                        polygons[i].1.extend(lengths);
+                        new = true;
+                    }
+                    None => {
+                        j += 1;
                     }
                 }
-                p3 += 1;
-                p4 = if p4 == v2.len() - 1 { 0 } else { p4 + 1 };
             }
-            p1 += 1;
-            p2 = if p2 == v1.len() - 1 { 0 } else { p1 + 1 };
+            i += 1;
+        }
+    }
+    // convert the indices pack to actual vertices
+    let line_vertices: Vec<Polygon<S>> = polygons
+        .iter()
+        .filter(|(l, _)| l.points.len() > 2)
+        //.map(|l|l.fuse_normals()) // TODO
+        .map(|(l, lengths)| Polygon {
+            material: majority(lengths),
+            ..l.to_polygon(vertices.clone())
+        })
+        .collect();
+    return line_vertices;
+}
+
+/// the material and length of every edge
+type MaterialLengths<S> = Vec<(usize, S)>;
+
+fn material_lengths<S: BaseFloat>(line: &Polygon<S>) -> MaterialLengths<S> {
+    return line
+        .edges()
+        .map(|(a, b)| (line.material, (b - a).magnitude()))
+        .collect();
+}
+
+/// the material with the largest total length, the lower one on a tie
+fn majority<S: BaseFloat>(lengths: &[(usize, S)]) -> usize {
+    let mut totals: Vec<(usize, S)> = Vec::new();
+    for (material, length) in lengths {
+        match totals.iter_mut().find(|(m, _)| m == material) {
+            Some((_, total)) => *total += *length,
+            None => totals.push((*material, *length)),
         }
-        return vec![v1, v2];
     }
+    totals.sort_by_key(|(m, _)| *m);
+    // `max_by` keeps the last of equal elements
+    return totals
+        .iter()
+        .rev()
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal))
+        .map_or(0, |(m, _)| *m);
 }
 
-pub type IndexPolygon = AbstractPolygon<usize>;
+pub type IndexPolygon<S = f32> = AbstractPolygon<usize, S>;
 
-impl IndexPolygon {
-    pub fn to_polygon(&self, vertices: Vec<Vertex>) -> Polygon {
+impl<S: BaseFloat> IndexPolygon<S> {
+    pub fn to_polygon(&self, vertices: Vec<Vertex<S>>) -> Polygon<S> {
         return Polygon {
             points: self
                 .points
                 .iter()
                 .map(|x| vertices[*x])
-                .collect::<Vec<Vertex>>(),
+                .collect::<Vec<Vertex<S>>>(),
             normals: self.normals.clone(),
+            material: self.material,
         };
     }
 
-    pub fn join(&self, l2: IndexPolygon) -> Option<IndexPolygon> {
+    /// joins two lines sharing an end point, the result keeps the material of `self`
+    pub fn join(&self, l2: IndexPolygon<S>) -> Option<IndexPolygon<S>> {
         if self.points[0] == l2.points[0] {
             // first points of each lines are the same
             return Some(IndexPolygon {
@@ -134,15 +361,16 @@ impl IndexPolygon {
                     .skip(1)
                     .rev()
                     .chain(l2.points.iter())
-                    .map(|x| *x)
+                    .copied()
                     .collect(),
                 normals: self
                     .normals
                     .iter()
                     .rev()
                     .chain(l2.normals.iter())
-                    .map(|x| *x)
+                    .copied()
                     .collect(),
+                material: self.material,
             });
         } else if self.points[self.points.len() - 1] == l2.points[l2.points.len() - 1] {
             // last points of each lines are the same
@@ -151,14 +379,15 @@ impl IndexPolygon {
                     .points
                     .iter()
                     .chain(l2.points.iter().rev().skip(1))
-                    .map(|x| *x)
+                    .copied()
                     .collect(),
                 normals: self
                     .normals
                     .iter()
                     .chain(l2.normals.iter().rev())
-                    .map(|x| *x)
+                    .copied()
                     .collect(),
+                material: self.material,
             });
         } else if self.points[self.points.len() - 1] == l2.points[0] {
             // last point of first line and the first of the second one are the same
@@ -167,14 +396,15 @@ impl IndexPolygon {
                     .points
                     .iter()
                     .chain(l2.points.iter().skip(1))
-                    .map(|x| *x)
+                    .copied()
                     .collect(),
                 normals: self
                     .normals
                     .iter()
                     .chain(l2.normals.iter())
-                    .map(|x| *x)
+                    .copied()
                     .collect(),
+                material: self.material,
             });
         } else if self.points[0] == l2.points[l2.points.len() - 1] {
             // first point of first line and the last of the second one are the same
@@ -183,14 +413,15 @@ impl IndexPolygon {
                     .points
                     .iter()
                     .chain(self.points.iter().skip(1))
-                    .map(|x| *x)
+                    .copied()
                     .collect(),
                 normals: l2
                     .normals
                     .iter()
                     .chain(self.normals.iter())
-                    .map(|x| *x)
+                    .copied()
                     .collect(),
+                material: self.material,
             });
         } else {
             return None;
@@ -198,24 +429,375 @@ impl IndexPolygon {
     }
 }
 
-pub fn get_line_intersection(
-    p1: Vertex,
-    p2: Vertex,
-    p3: Vertex,
-    p4: Vertex,
-) -> Option<Vertex> {
-    // source: https://stackoverflow.com/a/1968345
-    let s1 = p2 - p1;
-    let s2 = p4 - p3;
+/// The point where the segments meet. Collinear segments which overlap do not meet in a
+/// single point and return `None` like segments which are apart, `intersect_segments`
+/// tells them apart.
+pub fn get_line_intersection<S: BaseFloat>(
+    p1: Vertex<S>,
+    p2: Vertex<S>,
+    p3: Vertex<S>,
+    p4: Vertex<S>,
+) -> Option<Vertex<S>> {
+    return match intersect_segments(p1, p2, p3, p4) {
+        SegmentIntersection::Proper(p) | SegmentIntersection::Touching(p) => Some(p),
+        SegmentIntersection::None | SegmentIntersection::Overlap(..) => None,
+    };
+}
+
+/// decides which parts of overlapping polygons are filled
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FillRule {
+    /// a point is inside if a ray from it crosses an odd number of lines
+    EvenOdd,
+    /// a point is inside if the polygons wind around it at least once
+    NonZero,
+    /// a point is inside if the polygons wind around it counter clockwise more often than
+    /// clockwise, which turns clockwise polygons into holes
+    Positive,
+}
+
+impl FillRule {
+    /// checks if an area with the given winding number is filled
+    pub fn is_filled(&self, winding: i32) -> bool {
+        return match self {
+            FillRule::EvenOdd => winding % 2 != 0,
+            FillRule::NonZero => winding != 0,
+            FillRule::Positive => winding > 0,
+        };
+    }
+}
+
+/// Like `scanline` but additionally returns the direction of each line, +1 for lines going
+/// down and -1 for lines going up. Summing them from the left gives the winding number,
+/// which is positive inside of counter clockwise polygons.
+pub fn scanline_winding<S: BaseFloat>(polygons: &[Polygon<S>], y: S) -> Vec<(S, i32)> {
+    let mut crossings: Vec<(S, i32)> = polygons
+        .iter()
+        .flat_map(|p| p.edges())
+        .filter(|(a, b)| (a.y <= y) != (b.y <= y))
+        .map(|(a, b)| {
+            let x = a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x);
+            (x, if a.y > b.y { 1 } else { -1 })
+        })
+        .collect();
+    crossings.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+    return crossings;
+}
+
+/// x coordinates (sorted) where the horizontal line at `y` crosses the polygons
+pub fn scanline<S: BaseFloat>(polygons: &[Polygon<S>], y: S) -> Vec<S> {
+    let mut crossings: Vec<S> = polygons
+        .iter()
+        .flat_map(|p| p.edges())
+        // half open check, so a line through a vertex is only counted once
+        .filter(|(a, b)| (a.y <= y) != (b.y <= y))
+        .map(|(a, b)| a.x + (y - a.y) / (b.y - a.y) * (b.x - a.x))
+        .collect();
+    crossings.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    return crossings;
+}
+
+/// checks if a point is inside of the area described by the polygons (even-odd rule)
+pub fn inside<S: BaseFloat>(polygons: &[Polygon<S>], point: Vertex<S>) -> bool {
+    return scanline(polygons, point.y)
+        .iter()
+        .filter(|x| **x < point.x)
+        .count()
+        % 2
+        == 1;
+}
+
+/// marks the polygons that are holes, which are the ones inside of an odd number of others
+pub fn holes<S: BaseFloat>(polygons: &[Polygon<S>]) -> Vec<bool> {
+    return polygons
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let containing = polygons
+                .iter()
+                .enumerate()
+                .filter(|(j, other)| *j != i && other.contains(p.points[0]))
+                .count();
+            containing % 2 == 1
+        })
+        .collect();
+}
+
+/// Offsets the area described by the polygons, positive distances grow the area.
+/// Outlines move outwards while holes shrink and vice versa.
+pub fn offset_area<S: BaseFloat>(polygons: &[Polygon<S>], distance: S) -> Vec<Polygon<S>> {
+    return polygons
+        .iter()
+        .zip(holes(polygons))
+        .filter_map(|(p, hole)| p.offset(if hole { -distance } else { distance }))
+        .collect();
+}
+
+/// smallest convex polygon containing all points, ordered counter clockwise
+pub fn convex_hull<S: BaseFloat>(points: &[Vertex<S>]) -> Polygon<S> {
+    let mut sorted = points.to_vec();
+    sorted.sort_by(|a, b| (a.x, a.y).partial_cmp(&(b.x, b.y)).unwrap());
+    sorted.dedup();
+
+    // monotone chain, builds the lower and upper half of the hull
+    let mut hull: Vec<Vertex<S>> = Vec::new();
+    for pass in 0..2 {
+        let start = hull.len();
+        for p in sorted.iter() {
+            while hull.len() >= start + 2
+                && orient2d(hull[hull.len() - 2], hull[hull.len() - 1], *p)
+                    != Orientation::CounterClockwise
+            {
+                hull.pop();
+            }
+            hull.push(*p);
+        }
+        hull.pop();
+        if pass == 0 {
+            sorted.reverse();
+        }
+    }
+    if let Some(first) = hull.first() {
+        hull.push(*first);
+    }
+
+    let normals = hull
+        .windows(2)
+        .map(|w| {
+            let d = (w[1] - w[0]).normalize();
+            Vector2::new(d.y, -d.x)
+        })
+        .collect();
+    return Polygon {
+        points: hull,
+        normals,
+        material: 0,
+    };
+}
+
+/// Groups the polygons into connected areas. Each group starts with an outline followed by
+/// the holes directly inside of it, holes of islands within a hole belong to the island.
+pub fn islands<S: BaseFloat>(polygons: &[Polygon<S>]) -> Vec<Vec<Polygon<S>>> {
+    let is_hole = holes(polygons);
+    // the smallest outline containing a hole is the one the hole belongs to
+    let owner = |hole: &Polygon<S>| {
+        return polygons
+            .iter()
+            .enumerate()
+            .filter(|(j, p)| !is_hole[*j] && p.contains(hole.points[0]))
+            .min_by(|(_, a), (_, b)| a.area().abs().partial_cmp(&b.area().abs()).unwrap())
+            .map(|(j, _)| j);
+    };
+    let mut groups: Vec<(usize, Vec<Polygon<S>>)> = polygons
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| !is_hole[*i])
+        .map(|(i, p)| (i, vec![p.clone()]))
+        .collect();
+    for (i, hole) in polygons.iter().enumerate().filter(|(i, _)| is_hole[*i]) {
+        if let Some(j) = owner(hole) {
+            if let Some((_, group)) = groups.iter_mut().find(|(k, _)| *k == j) {
+                group.push(polygons[i].clone());
+            }
+        }
+    }
+    return groups.into_iter().map(|(_, group)| group).collect();
+}
 
-    let d = -s2.x * s1.y + s1.x * s2.y;
+/// Splits an island (an outline followed by its holes, see `islands`) into triangles.
+/// The corners of the triangles are ordered counter clockwise.
+///
+/// Every hole is connected to the outline by a bridge, which turns the island into a single
+/// polygon that is then cut into triangles by ear clipping.
+pub fn triangulate<S: BaseFloat>(island: &[Polygon<S>]) -> Vec<[Vertex<S>; 3]> {
+    // open rings without repeated points, the outline counter clockwise and holes clockwise
+    let ring = |p: &Polygon<S>, ccw: bool| {
+        let mut points = p.points.clone();
+        points.dedup();
+        if points.len() > 1 && points.first() == points.last() {
+            points.pop();
+        }
+        if (p.area() > S::zero()) != ccw {
+            points.reverse();
+        }
+        points
+    };
+    if island.is_empty() {
+        return vec![];
+    }
+    let mut outline = ring(&island[0], true);
+    let mut holes: Vec<Vec<Vertex<S>>> = island[1..]
+        .iter()
+        .map(|p| ring(p, false))
+        .filter(|h| h.len() > 2)
+        .collect();
+    // holes furthest to the right first, so their bridges do not cross the later ones
+    let max_x = |h: &Vec<Vertex<S>>| h.iter().map(|p| p.x).fold(S::neg_infinity(), S::max);
+    holes.sort_by(|a, b| max_x(b).partial_cmp(&max_x(a)).unwrap());
+
+    for (i, hole) in holes.iter().enumerate() {
+        let m = (0..hole.len())
+            .max_by(|a, b| hole[*a].x.partial_cmp(&hole[*b].x).unwrap())
+            .unwrap();
+        // the closest point of the outline that can be reached without crossing any line
+        let blocked = |p: Vertex<S>| {
+            let rings = std::iter::once(&outline).chain(holes[i..].iter());
+            rings.flat_map(|r| ring_edges(r)).any(|(a, b)| {
+                a != p && b != p && a != hole[m] && b != hole[m] && segments_cross(hole[m], p, a, b)
+            })
+        };
+        let bridge = (0..outline.len())
+            .filter(|j| !blocked(outline[*j]))
+            .min_by(|a, b| {
+                let da = (outline[*a] - hole[m]).magnitude2();
+                let db = (outline[*b] - hole[m]).magnitude2();
+                da.partial_cmp(&db).unwrap()
+            });
+        if let Some(j) = bridge {
+            // walk to the hole, around it and back on the same bridge
+            let mut joined: Vec<Vertex<S>> = outline[..=j].to_vec();
+            joined.extend(hole[m..].iter().chain(hole[..=m].iter()));
+            joined.extend(outline[j..].iter());
+            outline = joined;
+        }
+    }
+    return ear_clipping(outline);
+}
+
+fn ring_edges<S: BaseFloat>(
+    ring: &[Vertex<S>],
+) -> impl Iterator<Item = (Vertex<S>, Vertex<S>)> + '_ {
+    return (0..ring.len()).map(move |i| (ring[i], ring[(i + 1) % ring.len()]));
+}
+
+/// checks if the segments cross each other in a single point that is not one of their ends
+pub fn segments_cross<S: BaseFloat>(
+    p1: Vertex<S>,
+    p2: Vertex<S>,
+    p3: Vertex<S>,
+    p4: Vertex<S>,
+) -> bool {
+    return matches!(
+        intersect_segments(p1, p2, p3, p4),
+        SegmentIntersection::Proper(_)
+    );
+}
+
+/// triangulates a simple polygon whose points are ordered counter clockwise
+fn ear_clipping<S: BaseFloat>(mut points: Vec<Vertex<S>>) -> Vec<[Vertex<S>; 3]> {
+    let left = |a: Vertex<S>, b: Vertex<S>, c: Vertex<S>| {
+        orient2d(a, b, c) == Orientation::CounterClockwise
+    };
+    let right =
+        |a: Vertex<S>, b: Vertex<S>, c: Vertex<S>| orient2d(a, b, c) == Orientation::Clockwise;
+    let mut triangles = Vec::new();
+    while points.len() > 3 {
+        let n = points.len();
+        let is_ear = |i: usize| {
+            let (a, b, c) = (points[(i + n - 1) % n], points[i], points[(i + 1) % n]);
+            if !left(a, b, c) {
+                return false;
+            }
+            // no other point may lie within the ear, points of a bridge appear twice
+            return points.iter().all(|p| {
+                *p == a
+                    || *p == b
+                    || *p == c
+                    || right(a, b, *p)
+                    || right(b, c, *p)
+                    || right(c, a, *p)
+            });
+        };
+        // an ear whose circle holds neither of the neighbouring points is not a sliver,
+        // the neighbours can be cut off later with better shaped triangles
+        let round = |i: usize| {
+            let (a, b, c) = (points[(i + n - 1) % n], points[i], points[(i + 1) % n]);
+            let (before, after) = (points[(i + n - 2) % n], points[(i + 2) % n]);
+            return incircle(a, b, c, before) != Circle::Inside
+                && incircle(a, b, c, after) != Circle::Inside;
+        };
+        let ear = (0..n)
+            .find(|i| round(*i) && is_ear(*i))
+            .or_else(|| (0..n).find(|i| is_ear(*i)));
+        // a degenerate polygon might not have an ear, the flattest corner is cut instead
+        let ear = ear.unwrap_or_else(|| {
+            (0..n)
+                .max_by(|i, j| {
+                    let corner = |k: usize| {
+                        let (a, b, c) = (points[(k + n - 1) % n], points[k], points[(k + 1) % n]);
+                        (b - a).perp_dot(c - a)
+                    };
+                    corner(*i).partial_cmp(&corner(*j)).unwrap()
+                })
+                .unwrap()
+        });
+        let (a, b, c) = (
+            points[(ear + n - 1) % n],
+            points[ear],
+            points[(ear + 1) % n],
+        );
+        if left(a, b, c) {
+            triangles.push([a, b, c]);
+        }
+        points.remove(ear);
+    }
+    if points.len() == 3 && left(points[0], points[1], points[2]) {
+        triangles.push([points[0], points[1], points[2]]);
+    }
+    return triangles;
+}
 
-    let s = (-s1.y * (p1.x - p3.x) + s1.x * (p1.y - p3.y)) / d;
-    let t = (s2.x * (p1.y - p3.y) - s2.y * (p1.x - p3.x)) / d;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    if s >= 0. && s <= 1. && t >= 0. && t <= 1. {
-        return Some(p1 + (p2 - p1) * t);
+    /// the smallest angle of the triangle (degrees)
+    fn smallest_angle([a, b, c]: [Vertex; 3]) -> f32 {
+        let angle = |p: Vertex, q: Vertex, r: Vertex| Deg::from((q - p).angle(r - p)).0;
+        return angle(a, b, c).min(angle(b, c, a)).min(angle(c, a, b));
     }
 
-    return None;
+    #[test]
+    fn triangulation_avoids_slivers() {
+        // an ellipse, points on a circle would all be on the circle of every ear
+        let mut ellipse = Polygon::circle(Point2::new(0., 0.), 10., 32);
+        for p in ellipse.points.iter_mut() {
+            p.y *= 0.5;
+        }
+        let triangles = triangulate(&[ellipse.clone()]);
+        assert_eq!(triangles.len(), 30);
+        let area: f32 = triangles
+            .iter()
+            .map(|[a, b, c]| (b - a).perp_dot(c - a) / 2.)
+            .sum();
+        assert!((area - ellipse.area()).abs() < 1e-3);
+        let smallest = triangles
+            .iter()
+            .copied()
+            .map(smallest_angle)
+            .fold(90., f32::min);
+        // cutting the ears in order leaves a fan with angles below 3 degrees
+        assert!(smallest > 10., "{}", smallest);
+    }
+
+    #[test]
+    fn line_intersections() {
+        let p = |x: f32, y: f32| Point2::new(x, y);
+        let crossing = get_line_intersection(p(0., 0.), p(2., 2.), p(0., 2.), p(2., 0.));
+        assert_eq!(crossing, Some(p(1., 1.)));
+        assert_eq!(
+            get_line_intersection(p(0., 0.), p(2., 0.), p(1., 0.), p(1., 1.)),
+            Some(p(1., 0.))
+        );
+        // parallel and collinear segments have no single point in common
+        assert_eq!(
+            get_line_intersection(p(0., 0.), p(2., 0.), p(0., 1.), p(2., 1.)),
+            None
+        );
+        assert_eq!(
+            get_line_intersection(p(0., 0.), p(2., 0.), p(1., 0.), p(3., 0.)),
+            None
+        );
+    }
 }
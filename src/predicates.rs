@@ -0,0 +1,452 @@
+//! Geometric predicates that always give the right answer.
+//!
+//! Rounding errors can make floating point tests like "is this point left of that line"
+//! return the wrong sign for nearly collinear points, which leads to crossing polygons or
+//! endless loops. The predicates are first evaluated in double precision together with a
+//! bound of the rounding error. Only if the result is too close to zero to be sure, they
+//! are evaluated exactly with floating point expansions, sums of doubles which do not
+//! overlap (Shewchuk, "Adaptive Precision Floating-Point Arithmetic and Fast Robust
+//! Geometric Predicates").
+
+use crate::polygons::Vertex;
+use cgmath::*;
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Orientation {
+    CounterClockwise,
+    Clockwise,
+    Collinear,
+}
+
+/// the position of a point relative to the circle through three other points
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Circle {
+    Inside,
+    Outside,
+    On,
+}
+
+/// how two segments meet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SegmentIntersection<T = Vertex> {
+    None,
+    /// the segments cross in a point which is not an end point of either of them
+    Proper(T),
+    /// an end point of one segment lies on the other segment
+    Touching(T),
+    /// the segments are collinear and share the part between the two points
+    Overlap(T, T),
+}
+
+const EPSILON: f64 = f64::EPSILON / 2.;
+const ORIENT_BOUND: f64 = (3. + 16. * EPSILON) * EPSILON;
+const INCIRCLE_BOUND: f64 = (10. + 96. * EPSILON) * EPSILON;
+
+/// the side of the line from `a` to `b` that `c` is on, counter clockwise means left
+pub fn orient2d<S: BaseFloat>(a: Vertex<S>, b: Vertex<S>, c: Vertex<S>) -> Orientation {
+    return orientation(orient2d_sign(double(a), double(b), double(c)));
+}
+
+fn orientation(sign: Ordering) -> Orientation {
+    return match sign {
+        Ordering::Greater => Orientation::CounterClockwise,
+        Ordering::Less => Orientation::Clockwise,
+        Ordering::Equal => Orientation::Collinear,
+    };
+}
+
+/// the point in double precision, exact for `f32` and `f64` coordinates
+fn double<S: BaseFloat>(p: Vertex<S>) -> Vertex<f64> {
+    return Point2::new(p.x.to_f64().unwrap(), p.y.to_f64().unwrap());
+}
+
+/// the sign of twice the signed area of the triangle
+fn orient2d_sign(a: Vertex<f64>, b: Vertex<f64>, c: Vertex<f64>) -> Ordering {
+    let (ax, ay, bx, by, cx, cy) = (a.x, a.y, b.x, b.y, c.x, c.y);
+    let left = (ax - cx) * (by - cy);
+    let right = (ay - cy) * (bx - cx);
+    let det = left - right;
+    let bound = ORIENT_BOUND * (left.abs() + right.abs());
+    if det > bound || -det > bound {
+        return det.partial_cmp(&0.).unwrap();
+    }
+    // every product is split into its rounded value and the rounding error
+    let terms = [
+        two_product(ax, by),
+        two_product(-ax, cy),
+        two_product(-ay, bx),
+        two_product(ay, cx),
+        two_product(bx, cy),
+        two_product(-by, cx),
+    ];
+    let mut sum: Vec<f64> = Vec::new();
+    for (product, error) in terms.iter() {
+        sum = grow(&grow(&sum, *error), *product);
+    }
+    return sign(&sum);
+}
+
+/// Where `d` lies relative to the circle through `a`, `b` and `c`,
+/// which have to be ordered counter clockwise.
+pub fn incircle<S: BaseFloat>(a: Vertex<S>, b: Vertex<S>, c: Vertex<S>, d: Vertex<S>) -> Circle {
+    let (a, b, c, d) = (double(a), double(b), double(c), double(d));
+    let (adx, ady) = (a.x - d.x, a.y - d.y);
+    let (bdx, bdy) = (b.x - d.x, b.y - d.y);
+    let (cdx, cdy) = (c.x - d.x, c.y - d.y);
+    let alift = adx * adx + ady * ady;
+    let blift = bdx * bdx + bdy * bdy;
+    let clift = cdx * cdx + cdy * cdy;
+    let (bc, ca, ab) = (
+        bdx * cdy - cdx * bdy,
+        cdx * ady - adx * cdy,
+        adx * bdy - bdx * ady,
+    );
+    let det = alift * bc + blift * ca + clift * ab;
+    let permanent = ((bdx * cdy).abs() + (cdx * bdy).abs()) * alift
+        + ((cdx * ady).abs() + (adx * cdy).abs()) * blift
+        + ((adx * bdy).abs() + (bdx * ady).abs()) * clift;
+    let bound = INCIRCLE_BOUND * permanent;
+    let sign = if det > bound || -det > bound {
+        det.partial_cmp(&0.).unwrap()
+    } else {
+        incircle_exact(a, b, c, d)
+    };
+    return match sign {
+        Ordering::Greater => Circle::Inside,
+        Ordering::Less => Circle::Outside,
+        Ordering::Equal => Circle::On,
+    };
+}
+
+fn incircle_exact(a: Vertex<f64>, b: Vertex<f64>, c: Vertex<f64>, d: Vertex<f64>) -> Ordering {
+    // the differences are expansions, as they might not be exact in double precision
+    let (adx, ady) = (two_diff(a.x, d.x), two_diff(a.y, d.y));
+    let (bdx, bdy) = (two_diff(b.x, d.x), two_diff(b.y, d.y));
+    let (cdx, cdy) = (two_diff(c.x, d.x), two_diff(c.y, d.y));
+    let lift = |x: &[f64], y: &[f64]| sum(&product(x, x), &product(y, y));
+    let cross = |x1: &[f64], y1: &[f64], x2: &[f64], y2: &[f64]| {
+        sum(&product(x1, y2), &negate(&product(x2, y1)))
+    };
+    let det = sum(
+        &sum(
+            &product(&lift(&adx, &ady), &cross(&bdx, &bdy, &cdx, &cdy)),
+            &product(&lift(&bdx, &bdy), &cross(&cdx, &cdy, &adx, &ady)),
+        ),
+        &product(&lift(&cdx, &cdy), &cross(&adx, &ady, &bdx, &bdy)),
+    );
+    return sign(&det);
+}
+
+/// Classifies how the segments `p1`-`p2` and `p3`-`p4` meet. The decision is exact,
+/// only the position of a proper crossing is rounded.
+pub fn intersect_segments<S: BaseFloat>(
+    p1: Vertex<S>,
+    p2: Vertex<S>,
+    p3: Vertex<S>,
+    p4: Vertex<S>,
+) -> SegmentIntersection<Vertex<S>> {
+    return classify(
+        [p1, p2, p3, p4],
+        orient2d,
+        |p| (p.x, p.y),
+        || {
+            let (p1, p2, p3, p4) = (double(p1), double(p2), double(p3), double(p4));
+            // the signs differ, so the denominator is not zero
+            let area = |a: Vertex<f64>, b: Vertex<f64>, c: Vertex<f64>| {
+                (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+            };
+            let (d1, d2) = (area(p3, p4, p1), area(p3, p4, p2));
+            let t = (d1 / (d1 - d2)).clamp(0., 1.);
+            let along = |from: f64, to: f64| S::from(from + (to - from) * t).unwrap();
+            return Point2::new(along(p1.x, p2.x), along(p1.y, p2.y));
+        },
+    );
+}
+
+/// The classification of `intersect_segments` for any kind of coordinates. `orient` has to
+/// be exact, `coordinates` splits a point into x and y and `crossing` computes the point
+/// of a proper crossing.
+fn classify<T, C>(
+    [p1, p2, p3, p4]: [T; 4],
+    orient: impl Fn(T, T, T) -> Orientation,
+    coordinates: impl Fn(T) -> (C, C),
+    crossing: impl FnOnce() -> T,
+) -> SegmentIntersection<T>
+where
+    T: Copy,
+    C: PartialOrd,
+{
+    let (o1, o2) = (orient(p3, p4, p1), orient(p3, p4, p2));
+    let (o3, o4) = (orient(p1, p2, p3), orient(p1, p2, p4));
+    let collinear = Orientation::Collinear;
+
+    // all four are needed, every point is collinear with a segment of length zero
+    if o1 == collinear && o2 == collinear && o3 == collinear && o4 == collinear {
+        return overlap([p1, p2, p3, p4], coordinates);
+    }
+    if (o1 == o2 && o1 != collinear) || (o3 == o4 && o3 != collinear) {
+        return SegmentIntersection::None;
+    }
+    // an end point on the other segment, the other orientations already show that it is
+    // within the segment if it is on its line
+    if o1 == collinear {
+        return SegmentIntersection::Touching(p1);
+    }
+    if o2 == collinear {
+        return SegmentIntersection::Touching(p2);
+    }
+    if o3 == collinear {
+        return SegmentIntersection::Touching(p3);
+    }
+    if o4 == collinear {
+        return SegmentIntersection::Touching(p4);
+    }
+    return SegmentIntersection::Proper(crossing());
+}
+
+/// the common part of two collinear segments
+fn overlap<T, C>(
+    [p1, p2, p3, p4]: [T; 4],
+    coordinates: impl Fn(T) -> (C, C),
+) -> SegmentIntersection<T>
+where
+    T: Copy,
+    C: PartialOrd,
+{
+    // points on a line are ordered by their coordinates, x unless the line is vertical
+    let vertical = |a: T, b: T| coordinates(a).0 == coordinates(b).0;
+    let by_y = vertical(p1, p2) && vertical(p3, p4);
+    let key = |p: T| {
+        let (x, y) = coordinates(p);
+        if by_y {
+            (y, x)
+        } else {
+            (x, y)
+        }
+    };
+    let order = |a: T, b: T| {
+        if key(a) <= key(b) {
+            (a, b)
+        } else {
+            (b, a)
+        }
+    };
+    let ((a0, a1), (b0, b1)) = (order(p1, p2), order(p3, p4));
+    let start = if key(a0) >= key(b0) { a0 } else { b0 };
+    let end = if key(a1) <= key(b1) { a1 } else { b1 };
+    return match key(start).partial_cmp(&key(end)) {
+        Some(Ordering::Less) => SegmentIntersection::Overlap(start, end),
+        Some(Ordering::Equal) => SegmentIntersection::Touching(start),
+        _ => SegmentIntersection::None,
+    };
+}
+
+/// `a + b` as the rounded sum and its error
+fn two_sum(a: f64, b: f64) -> (f64, f64) {
+    let x = a + b;
+    let virtual_b = x - a;
+    let virtual_a = x - virtual_b;
+    return (x, (a - virtual_a) + (b - virtual_b));
+}
+
+/// `a - b` as an expansion
+fn two_diff(a: f64, b: f64) -> Vec<f64> {
+    let (x, y) = two_sum(a, -b);
+    return [y, x].iter().copied().filter(|c| *c != 0.).collect();
+}
+
+/// `a * b` as the rounded product and its error
+fn two_product(a: f64, b: f64) -> (f64, f64) {
+    let x = a * b;
+    return (x, a.mul_add(b, -x));
+}
+
+/// adds a double to an expansion, the components are ordered by increasing magnitude
+fn grow(e: &[f64], b: f64) -> Vec<f64> {
+    let mut result = Vec::with_capacity(e.len() + 1);
+    let mut q = b;
+    for component in e {
+        let (sum, error) = two_sum(q, *component);
+        if error != 0. {
+            result.push(error);
+        }
+        q = sum;
+    }
+    if q != 0. {
+        result.push(q);
+    }
+    return result;
+}
+
+fn sum(e: &[f64], f: &[f64]) -> Vec<f64> {
+    return f
+        .iter()
+        .fold(e.to_vec(), |s, component| grow(&s, *component));
+}
+
+fn scale(e: &[f64], b: f64) -> Vec<f64> {
+    let mut result = Vec::new();
+    for component in e {
+        let (product, error) = two_product(*component, b);
+        result = grow(&grow(&result, error), product);
+    }
+    return result;
+}
+
+fn product(e: &[f64], f: &[f64]) -> Vec<f64> {
+    return f
+        .iter()
+        .fold(Vec::new(), |s, component| sum(&s, &scale(e, *component)));
+}
+
+fn negate(e: &[f64]) -> Vec<f64> {
+    return e.iter().map(|c| -c).collect();
+}
+
+/// the sign of an expansion is the sign of its largest component
+fn sign(e: &[f64]) -> Ordering {
+    return match e.last() {
+        Some(largest) => largest.partial_cmp(&0.).unwrap(),
+        None => Ordering::Equal,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// the next larger float, for positive numbers
+    fn next_up(x: f32) -> f32 {
+        return f32::from_bits(x.to_bits() + 1);
+    }
+
+    #[test]
+    fn orient2d_nearly_collinear() {
+        let (a, b) = (Point2::new(0.5, 0.5), Point2::new(12., 12.));
+        // a determinant of zero is always decided by the exact expansion
+        assert_eq!(
+            orient2d(a, b, Point2::new(24., 24.)),
+            Orientation::Collinear
+        );
+        assert_eq!(
+            orient2d(a, b, Point2::new(24., next_up(24.))),
+            Orientation::CounterClockwise
+        );
+        assert_eq!(
+            orient2d(a, b, Point2::new(next_up(24.), 24.)),
+            Orientation::Clockwise
+        );
+        // far from the origin the differences of the coordinates are not exact in f32
+        let (a, b) = (
+            Point2::new(1e6, 1e6),
+            Point2::new(1e6 + 0.0625, 1e6 + 0.0625),
+        );
+        let c = Point2::new(-3e6, -3e6);
+        assert_eq!(orient2d(a, b, c), Orientation::Collinear);
+        // the spacing of floats around 3e6 is 0.25
+        let raised = Point2::new(c.x, c.y + 0.25);
+        assert_eq!(orient2d(a, b, raised), Orientation::CounterClockwise);
+        assert_eq!(orient2d(b, a, raised), Orientation::Clockwise);
+    }
+
+    #[test]
+    fn orient2d_nearly_collinear_doubles() {
+        // the products of the coordinates need more than double precision
+        let x: f64 = 134_217_729.;
+        let (a, b) = (Point2::new(x, x), Point2::new(3. * x, 3. * x));
+        assert_eq!(
+            orient2d(a, b, Point2::new(2. * x, 2. * x)),
+            Orientation::Collinear
+        );
+        let raised = Point2::new(2. * x, 2. * x + 2. * x * f64::EPSILON);
+        assert_eq!(orient2d(a, b, raised), Orientation::CounterClockwise);
+        assert_eq!(orient2d(b, a, raised), Orientation::Clockwise);
+        // neither 0.1 nor 0.3 is exact, so their products are rounded
+        let small = Point2::new(0.1, 0.1);
+        let shifted = Point2::new(0.3, 0.3 + f64::EPSILON);
+        assert_eq!(
+            orient2d(Point2::new(0., 0.), small, shifted),
+            Orientation::CounterClockwise
+        );
+    }
+
+    #[test]
+    fn expansions_are_exact() {
+        let e = grow(&grow(&grow(&[], 1e16), 1.), -1e16);
+        assert_eq!(e, vec![1.]);
+        let e = sum(&[1e-20, 1.], &negate(&[1.]));
+        assert_eq!(sign(&e), Ordering::Greater);
+        // (2^27 + 1)² does not fit into a double
+        let x = 134_217_729.;
+        let square = product(&[x], &[x]);
+        assert_eq!(sign(&grow(&square, -(x * x))), Ordering::Greater);
+        assert_eq!(sign(&[]), Ordering::Equal);
+    }
+
+    #[test]
+    fn incircle_exact_path() {
+        let (a, b, c): (Vertex, Vertex, Vertex) = (
+            Point2::new(1., 0.),
+            Point2::new(0., 1.),
+            Point2::new(-1., 0.),
+        );
+        let exact = |d: Vertex| incircle_exact(double(a), double(b), double(c), double(d));
+        assert_eq!(incircle(a, b, c, Point2::new(0., -1.)), Circle::On);
+        assert_eq!(exact(Point2::new(0., -1.)), Ordering::Equal);
+        let inside = Point2::new(0., -1. + f32::EPSILON);
+        assert_eq!(incircle(a, b, c, inside), Circle::Inside);
+        assert_eq!(exact(inside), Ordering::Greater);
+        let outside = Point2::new(0., -1. - f32::EPSILON);
+        assert_eq!(incircle(a, b, c, outside), Circle::Outside);
+        assert_eq!(exact(outside), Ordering::Less);
+        // in double precision the point moves by a much smaller amount
+        let (a, b, c) = (double(a), double(b), double(c));
+        assert_eq!(
+            incircle(a, b, c, Point2::new(0., -1. + f64::EPSILON)),
+            Circle::Inside
+        );
+        assert_eq!(
+            incircle(a, b, c, Point2::new(0., -1. - f64::EPSILON)),
+            Circle::Outside
+        );
+    }
+
+    #[test]
+    fn intersect_segments_nearly_collinear() {
+        let (p1, p2) = (Point2::new(0., 0.), Point2::new(3., 3.));
+        let on = Point2::new(1.5, 1.5);
+        assert_eq!(
+            intersect_segments(p1, p2, on, Point2::new(3., 0.)),
+            SegmentIntersection::Touching(on)
+        );
+        // just above the segment, so the other one crosses it right next to its end
+        let above = Point2::new(1.5, next_up(1.5));
+        match intersect_segments(p1, p2, above, Point2::new(3., 0.)) {
+            SegmentIntersection::Proper(q) => assert!((q - on).magnitude() < 1e-6),
+            other => panic!("expected a proper crossing, got {:?}", other),
+        }
+        assert_eq!(
+            intersect_segments(p1, p2, above, Point2::new(3., next_up(3.))),
+            SegmentIntersection::None
+        );
+        assert_eq!(
+            intersect_segments(p1, p2, Point2::new(1., 1.), Point2::new(4., 4.)),
+            SegmentIntersection::Overlap(Point2::new(1., 1.), Point2::new(3., 3.))
+        );
+        assert_eq!(
+            intersect_segments(p1, p2, p2, Point2::new(4., 4.)),
+            SegmentIntersection::Touching(p2)
+        );
+        // a segment without length is collinear with every line
+        let point = Point2::new(4., 4.);
+        assert_eq!(
+            intersect_segments(p1, p2, point, point),
+            SegmentIntersection::None
+        );
+        assert_eq!(
+            intersect_segments(p1, p2, on, on),
+            SegmentIntersection::Touching(on)
+        );
+    }
+}
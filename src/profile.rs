@@ -0,0 +1,616 @@
+use crate::dxf::Units;
+use crate::model::StlFormat;
+use crate::polygons::FillRule;
+use crate::reconstruct::Method;
+use crate::simplify::Simplification;
+use serde::Deserialize;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use toml::value::{Table, Value};
+
+/// key used inside a profile file to name the file(s) it is based on
+const INHERITS_KEY: &str = "inherits";
+
+#[derive(Debug)]
+pub enum ProfileError {
+    /// the file could not be read
+    Io(PathBuf, std::io::Error),
+    /// the file is not valid toml or does not match the profile layout
+    Parse(String),
+    /// a value is out of range, `key` is the dotted path to the value
+    Invalid { key: String, reason: String },
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProfileError::Io(path, err) => write!(f, "{}: {}", path.display(), err),
+            ProfileError::Parse(msg) => write!(f, "{}", msg),
+            ProfileError::Invalid { key, reason } => write!(f, "invalid `{}`: {}", key, reason),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kinematics {
+    Cartesian,
+    CoreXY,
+    Delta,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrinterProfile {
+    pub name: String,
+    /// size of the bed in x and z direction (mm)
+    pub bed_size: [f32; 2],
+    /// maximal printable height (mm)
+    pub max_height: f32,
+    pub nozzle_diameter: f32,
+    pub kinematics: Kinematics,
+    /// g-code emitted before the first layer
+    pub start_gcode: String,
+    /// g-code emitted after the last layer
+    pub end_gcode: String,
+}
+
+impl Default for PrinterProfile {
+    fn default() -> Self {
+        return PrinterProfile {
+            name: "generic".to_string(),
+            bed_size: [200., 200.],
+            max_height: 200.,
+            nozzle_diameter: 0.4,
+            kinematics: Kinematics::Cartesian,
+            start_gcode: "G28 ; home all axes\nG90\nM83".to_string(),
+            end_gcode: "M104 S0\nM140 S0\nG28 X0\nM84".to_string(),
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaterialProfile {
+    pub name: String,
+    /// nozzle temperature (°C)
+    pub temperature: f32,
+    /// bed temperature (°C)
+    pub bed_temperature: f32,
+    /// g/cm³
+    pub density: f32,
+    pub filament_diameter: f32,
+    /// retraction length (mm)
+    pub retraction: f32,
+    /// retraction speed (mm/s)
+    pub retraction_speed: f32,
+}
+
+impl Default for MaterialProfile {
+    fn default() -> Self {
+        return MaterialProfile {
+            name: "PLA".to_string(),
+            temperature: 205.,
+            bed_temperature: 60.,
+            density: 1.24,
+            filament_diameter: 1.75,
+            retraction: 1.,
+            retraction_speed: 35.,
+        };
+    }
+}
+
+/// where closed loops start
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Seam {
+    /// lined up with the seams of the layer below
+    Aligned,
+    /// close to the end of the previous loop
+    Nearest,
+    /// spread over the surface, repeatable with the same `seam_seed`
+    Random,
+    /// at the back of the printer
+    Rearmost,
+    /// in the sharpest concave corner
+    Sharpest,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PrintProfile {
+    pub layer_height: f32,
+    /// thickness of the layer on the bed, which is the base of the raft if there is one
+    pub first_layer_height: f32,
+    /// number of perimeters
+    pub walls: u32,
+    /// infill density between 0 and 1
+    pub infill: f32,
+    /// distance below which two points of a slice are considered the same
+    pub weld_tolerance: f32,
+    /// largest deviation allowed when simplifying the slices, 0 keeps every point
+    pub resolution: f32,
+    pub simplification: Simplification,
+    /// processes the layers on a grid of nanometres, so equal points compare equal and
+    /// the toolpaths do not depend on rounding
+    pub fixed_point: bool,
+    pub seam: Seam,
+    pub seam_seed: u64,
+    /// routes travel moves inside of the layer, where they need no retraction
+    pub combing: bool,
+    /// height the nozzle is lifted during travel moves, 0 for none
+    pub z_hop: f32,
+    /// lifts the nozzle for every travel move, not only for those which retract
+    pub z_hop_always: bool,
+    /// largest deviation of arcs replacing extruded runs, 0 prints lines only for firmware
+    /// without arc support
+    pub arc_tolerance: f32,
+}
+
+impl Default for PrintProfile {
+    fn default() -> Self {
+        return PrintProfile {
+            layer_height: 0.2,
+            first_layer_height: 0.3,
+            walls: 2,
+            infill: 0.2,
+            weld_tolerance: 0.01,
+            resolution: 0.02,
+            simplification: Simplification::DouglasPeucker,
+            fixed_point: false,
+            seam: Seam::Aligned,
+            seam_seed: 0,
+            combing: true,
+            z_hop: 0.,
+            z_hop_always: false,
+            arc_tolerance: 0.01,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SupportKind {
+    None,
+    /// columns below the overhangs connected by lines in x direction
+    Lines,
+    /// columns below the overhangs connected by lines in x and z direction
+    Grid,
+    /// branches growing down from the overhangs
+    Tree,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupportProfile {
+    pub kind: SupportKind,
+    /// overhangs steeper than this angle (degrees, measured from the vertical) are supported
+    pub angle: f32,
+    /// distance between the support lines, or between the contact points of tree supports
+    pub spacing: f32,
+    /// horizontal distance between support and model
+    pub xy_gap: f32,
+    /// vertical distance between support and model
+    pub z_gap: f32,
+}
+
+impl Default for SupportProfile {
+    fn default() -> Self {
+        return SupportProfile {
+            kind: SupportKind::None,
+            angle: 45.,
+            spacing: 2.,
+            xy_gap: 0.7,
+            z_gap: 0.2,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Adhesion {
+    None,
+    /// loops around the first layer, which prime the nozzle
+    Skirt,
+    /// loops attached to the first layer
+    Brim,
+    /// a few layers below the model
+    Raft,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdhesionProfile {
+    pub kind: Adhesion,
+    /// number of skirt or brim loops
+    pub loops: usize,
+    /// distance between the skirt and the model
+    pub skirt_distance: f32,
+    /// how far the raft reaches beyond the model
+    pub raft_margin: f32,
+    /// vertical distance between the raft and the model
+    pub raft_gap: f32,
+}
+
+impl Default for AdhesionProfile {
+    fn default() -> Self {
+        return AdhesionProfile {
+            kind: Adhesion::None,
+            loops: 3,
+            skirt_distance: 5.,
+            raft_margin: 3.,
+            raft_gap: 0.1,
+        };
+    }
+}
+
+/// settings for printers with several extruders
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ToolProfile {
+    /// tool used for each material, materials without an entry use the tool with their index
+    pub tools: Vec<usize>,
+    /// g-code emitted before a tool change, `{from}` and `{to}` are replaced by the tools
+    pub change_before: String,
+    /// g-code emitted after a tool change
+    pub change_after: String,
+    /// length the filament of the old tool is pulled back before the change
+    pub retraction: f32,
+    /// length of filament pushed out of the new tool after the change
+    pub purge: f32,
+    /// length of a side of the prime tower, 0 for none
+    pub tower_size: f32,
+    /// distance between the prime tower and the model
+    pub tower_distance: f32,
+}
+
+impl Default for ToolProfile {
+    fn default() -> Self {
+        return ToolProfile {
+            tools: vec![],
+            change_before: "G91\nG1 Z1 F600\nG90".to_string(),
+            change_after: "G91\nG1 Z-1 F600\nG90".to_string(),
+            retraction: 4.,
+            purge: 6.,
+            tower_size: 10.,
+            tower_distance: 5.,
+        };
+    }
+}
+
+/// settings for resin printers, which expose whole layers at once
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ResinProfile {
+    /// width and height of the layer images in pixels
+    pub resolution: [u32; 2],
+    /// size of a pixel (mm)
+    pub pixel_size: f32,
+    /// samples per pixel in y direction, 1 turns anti-aliasing off
+    pub antialias: u32,
+    /// only black and white pixels instead of gray values
+    pub monochrome: bool,
+    pub fill_rule: FillRule,
+    /// exposure time of a normal layer (s)
+    pub exposure: f32,
+    /// exposure time of the first layers (s)
+    pub bottom_exposure: f32,
+    pub bottom_layers: u32,
+    /// time between two exposures (s)
+    pub light_off_delay: f32,
+    /// wall thickness of a hollowed model, 0 keeps the model solid
+    pub hollow_wall: f32,
+    /// distance between the pillars of the lattice inside of a hollow model, 0 for none
+    pub lattice_spacing: f32,
+    pub lattice_thickness: f32,
+    /// diameter of the drain hole in the bottom of a hollow model, 0 for none
+    pub drain_diameter: f32,
+}
+
+impl Default for ResinProfile {
+    fn default() -> Self {
+        return ResinProfile {
+            resolution: [1440, 2560],
+            pixel_size: 0.047,
+            antialias: 4,
+            monochrome: false,
+            fill_rule: FillRule::EvenOdd,
+            exposure: 2.5,
+            bottom_exposure: 30.,
+            bottom_layers: 4,
+            light_off_delay: 1.,
+            hollow_wall: 0.,
+            lattice_spacing: 0.,
+            lattice_thickness: 1.,
+            drain_diameter: 0.,
+        };
+    }
+}
+
+/// settings for cutting the layers out of sheets of material
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SheetProfile {
+    /// width and height of a sheet (mm)
+    pub size: [f32; 2],
+    /// thickness of the material, the model is sliced at this distance
+    pub thickness: f32,
+    /// free space along the edges of a sheet
+    pub margin: f32,
+    /// distance between two pieces
+    pub spacing: f32,
+    /// diameter of the holes for alignment pins, 0 for none
+    pub registration_diameter: f32,
+    /// distance between the alignment pins
+    pub registration_spacing: f32,
+    /// font size of the layer numbers
+    pub label_size: f32,
+}
+
+impl Default for SheetProfile {
+    fn default() -> Self {
+        return SheetProfile {
+            size: [600., 400.],
+            thickness: 3.,
+            margin: 5.,
+            spacing: 3.,
+            registration_diameter: 3.,
+            registration_spacing: 20.,
+            label_size: 4.,
+        };
+    }
+}
+
+/// settings of the files written for other programs
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExportProfile {
+    pub dxf_units: Units,
+    /// places each slice of a dxf drawing at its height
+    pub dxf_elevation: bool,
+    /// how meshes are rebuilt from the slices
+    pub reconstruction: Method,
+    pub stl_format: StlFormat,
+}
+
+impl Default for ExportProfile {
+    fn default() -> Self {
+        return ExportProfile {
+            dxf_units: Units::Millimeters,
+            dxf_elevation: false,
+            reconstruction: Method::Loft,
+            stl_format: StlFormat::Binary,
+        };
+    }
+}
+
+/// All settings needed to slice and print a model.
+/// Every section is optional in the files, missing values are taken from the defaults.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub printer: PrinterProfile,
+    pub material: MaterialProfile,
+    pub print: PrintProfile,
+    pub support: SupportProfile,
+    pub adhesion: AdhesionProfile,
+    pub tools: ToolProfile,
+    pub resin: ResinProfile,
+    pub sheets: SheetProfile,
+    pub export: ExportProfile,
+}
+
+impl Profile {
+    /// loads and merges the given files, later files override earlier ones.
+    /// `overrides` are pairs of dotted keys and toml values, e.g. `("print.layer_height", "0.1")`,
+    /// which are applied last
+    pub fn load_all<P: AsRef<Path>>(
+        files: &[P],
+        overrides: &[(&str, &str)],
+    ) -> Result<Profile, ProfileError> {
+        let mut table = Table::new();
+        for file in files {
+            let mut chain = Vec::new();
+            merge(&mut table, read_table(file.as_ref(), &mut chain)?);
+        }
+        for (key, value) in overrides {
+            set_key(&mut table, key, value)?;
+        }
+
+        let profile: Profile = Value::Table(table)
+            .try_into()
+            .map_err(|e| ProfileError::Parse(e.to_string()))?;
+        profile.validate()?;
+        return Ok(profile);
+    }
+
+    pub fn validate(&self) -> Result<(), ProfileError> {
+        let printer = &self.printer;
+        let material = &self.material;
+        let print = &self.print;
+        let support = &self.support;
+        let adhesion = &self.adhesion;
+        let tools = &self.tools;
+        let resin = &self.resin;
+        let sheets = &self.sheets;
+        positive(
+            "printer.bed_size",
+            printer.bed_size[0].min(printer.bed_size[1]),
+        )?;
+        positive("printer.max_height", printer.max_height)?;
+        positive("printer.nozzle_diameter", printer.nozzle_diameter)?;
+        positive("material.density", material.density)?;
+        positive("material.filament_diameter", material.filament_diameter)?;
+        not_negative("material.temperature", material.temperature)?;
+        not_negative("material.bed_temperature", material.bed_temperature)?;
+        not_negative("material.retraction", material.retraction)?;
+        not_negative("material.retraction_speed", material.retraction_speed)?;
+        positive("print.layer_height", print.layer_height)?;
+        positive("print.first_layer_height", print.first_layer_height)?;
+        positive("print.weld_tolerance", print.weld_tolerance)?;
+        not_negative("print.resolution", print.resolution)?;
+        not_negative("print.z_hop", print.z_hop)?;
+        not_negative("print.arc_tolerance", print.arc_tolerance)?;
+        positive("support.angle", support.angle)?;
+        positive("support.spacing", support.spacing)?;
+        not_negative("support.xy_gap", support.xy_gap)?;
+        not_negative("support.z_gap", support.z_gap)?;
+        not_negative("adhesion.skirt_distance", adhesion.skirt_distance)?;
+        not_negative("adhesion.raft_margin", adhesion.raft_margin)?;
+        not_negative("adhesion.raft_gap", adhesion.raft_gap)?;
+        not_negative("tools.retraction", tools.retraction)?;
+        not_negative("tools.purge", tools.purge)?;
+        not_negative("tools.tower_size", tools.tower_size)?;
+        not_negative("tools.tower_distance", tools.tower_distance)?;
+        positive(
+            "resin.resolution",
+            resin.resolution[0].min(resin.resolution[1]) as f32,
+        )?;
+        positive("resin.pixel_size", resin.pixel_size)?;
+        positive("resin.antialias", resin.antialias as f32)?;
+        not_negative("resin.exposure", resin.exposure)?;
+        not_negative("resin.bottom_exposure", resin.bottom_exposure)?;
+        not_negative("resin.light_off_delay", resin.light_off_delay)?;
+        not_negative("resin.hollow_wall", resin.hollow_wall)?;
+        not_negative("resin.lattice_spacing", resin.lattice_spacing)?;
+        positive("resin.lattice_thickness", resin.lattice_thickness)?;
+        not_negative("resin.drain_diameter", resin.drain_diameter)?;
+        positive("sheets.size", sheets.size[0].min(sheets.size[1]))?;
+        positive("sheets.thickness", sheets.thickness)?;
+        not_negative("sheets.margin", sheets.margin)?;
+        not_negative("sheets.spacing", sheets.spacing)?;
+        not_negative("sheets.registration_diameter", sheets.registration_diameter)?;
+        positive("sheets.registration_spacing", sheets.registration_spacing)?;
+        positive("sheets.label_size", sheets.label_size)?;
+        if print.layer_height > printer.nozzle_diameter {
+            return Err(ProfileError::Invalid {
+                key: "print.layer_height".to_string(),
+                reason: format!(
+                    "{} is larger than the nozzle diameter ({})",
+                    print.layer_height, printer.nozzle_diameter
+                ),
+            });
+        }
+        if !(0. ..=1.).contains(&print.infill) {
+            return Err(ProfileError::Invalid {
+                key: "print.infill".to_string(),
+                reason: format!("{} is not between 0 and 1", print.infill),
+            });
+        }
+        return Ok(());
+    }
+}
+
+fn positive(key: &str, value: f32) -> Result<(), ProfileError> {
+    if value > 0. {
+        return Ok(());
+    }
+    return Err(ProfileError::Invalid {
+        key: key.to_string(),
+        reason: format!("{} must be larger than zero", value),
+    });
+}
+
+fn not_negative(key: &str, value: f32) -> Result<(), ProfileError> {
+    if value >= 0. {
+        return Ok(());
+    }
+    return Err(ProfileError::Invalid {
+        key: key.to_string(),
+        reason: format!("{} must not be negative", value),
+    });
+}
+
+/// reads a toml file and resolves its `inherits` entry.
+/// `chain` holds the files currently being read to detect cycles
+fn read_table(file: &Path, chain: &mut Vec<PathBuf>) -> Result<Table, ProfileError> {
+    let path = fs::canonicalize(file).map_err(|e| ProfileError::Io(file.to_path_buf(), e))?;
+    if chain.contains(&path) {
+        return Err(ProfileError::Parse(format!(
+            "{}: profile inherits from itself",
+            path.display()
+        )));
+    }
+    let content = fs::read_to_string(&path).map_err(|e| ProfileError::Io(path.clone(), e))?;
+    let mut table: Table = toml::from_str(&content)
+        .map_err(|e| ProfileError::Parse(format!("{}: {}", path.display(), e)))?;
+
+    let parents: Vec<String> = match table.remove(INHERITS_KEY) {
+        None => vec![],
+        Some(Value::String(parent)) => vec![parent],
+        Some(Value::Array(parents)) => parents
+            .into_iter()
+            .map(|p| match p {
+                Value::String(s) => Ok(s),
+                _ => Err(invalid_inherits(&path)),
+            })
+            .collect::<Result<Vec<String>, ProfileError>>()?,
+        Some(_) => return Err(invalid_inherits(&path)),
+    };
+
+    // parents are resolved relative to the file that names them
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    chain.push(path.clone());
+    let mut base = Table::new();
+    for parent in parents {
+        merge(&mut base, read_table(&dir.join(parent), chain)?);
+    }
+    chain.pop();
+
+    merge(&mut base, table);
+    return Ok(base);
+}
+
+fn invalid_inherits(path: &Path) -> ProfileError {
+    return ProfileError::Invalid {
+        key: INHERITS_KEY.to_string(),
+        reason: format!(
+            "{}: expected a file name or a list of file names",
+            path.display()
+        ),
+    };
+}
+
+/// recursively merges `other` into `base`, values in `other` win
+fn merge(base: &mut Table, other: Table) {
+    for (key, value) in other {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(b)), Value::Table(o)) => merge(b, o),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// sets a dotted key like `print.layer_height` to a value given in toml syntax
+fn set_key(table: &mut Table, key: &str, value: &str) -> Result<(), ProfileError> {
+    // parse the value as part of a document to make use of the toml parser
+    let value = match toml::from_str::<Table>(&format!("value = {}", value)) {
+        Ok(mut t) => t.remove("value").unwrap(),
+        // allow strings without quotes
+        Err(_) => Value::String(value.to_string()),
+    };
+
+    let parts: Vec<&str> = key.split('.').collect();
+    let mut current = table;
+    for part in &parts[..parts.len() - 1] {
+        let entry = current
+            .entry(part.to_string())
+            .or_insert_with(|| Value::Table(Table::new()));
+        current = match entry {
+            Value::Table(t) => t,
+            _ => {
+                return Err(ProfileError::Invalid {
+                    key: key.to_string(),
+                    reason: format!("`{}` is not a section", part),
+                })
+            }
+        };
+    }
+    current.insert(parts[parts.len() - 1].to_string(), value);
+    return Ok(());
+}
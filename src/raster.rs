@@ -0,0 +1,287 @@
+use crate::model::Layer;
+use crate::polygons::{scanline_winding, FillRule, Polygon};
+use crate::profile::Profile;
+use image::{GrayImage, Luma};
+use png::{BitDepth, ColorType, Encoder};
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::Path;
+use zip::write::FileOptions;
+use zip::ZipWriter;
+
+#[derive(Debug, Clone)]
+pub struct RasterSettings {
+    /// width and height of the image in pixels
+    pub resolution: (u32, u32),
+    /// size of a pixel in mm
+    pub pixel_size: f32,
+    /// number of samples per pixel in y direction, 1 turns anti-aliasing off.
+    /// In x direction the exact coverage is used when anti-aliasing is on
+    pub antialias: u32,
+    pub fill_rule: FillRule,
+    /// only write black and white pixels instead of 8 bit gray values
+    pub monochrome: bool,
+}
+
+impl Default for RasterSettings {
+    fn default() -> Self {
+        return RasterSettings {
+            resolution: (1440, 2560),
+            pixel_size: 0.047,
+            antialias: 4,
+            fill_rule: FillRule::EvenOdd,
+            monochrome: false,
+        };
+    }
+}
+
+impl RasterSettings {
+    pub fn from_profile(profile: &Profile) -> RasterSettings {
+        let resin = &profile.resin;
+        return RasterSettings {
+            resolution: (resin.resolution[0], resin.resolution[1]),
+            pixel_size: resin.pixel_size,
+            antialias: resin.antialias,
+            fill_rule: resin.fill_rule,
+            monochrome: resin.monochrome,
+        };
+    }
+
+    /// checks if the images only contain black and white pixels
+    fn is_binary(&self) -> bool {
+        return self.monochrome || self.antialias <= 1;
+    }
+}
+
+/// Fills the area of the polygons into an image.
+/// The origin of the polygons is in the center of the image, one pixel is `pixel_size` mm wide.
+/// Rows go in the direction of y, so the image shows the slice like the svg drawings and the
+/// viewer do: x to the right and y downwards.
+pub fn rasterize(polygons: &[Polygon], settings: &RasterSettings) -> GrayImage {
+    let (width, height) = settings.resolution;
+    let mut image = GrayImage::new(width, height);
+    let samples = settings.antialias.max(1);
+
+    // converts between pixel and model coordinates
+    let to_pixel = |x: f32| x / settings.pixel_size + width as f32 / 2.;
+    let to_model = |y: f32| (y - height as f32 / 2.) * settings.pixel_size;
+
+    let mut coverage = vec![0f32; width as usize];
+    for row in 0..height {
+        coverage.iter_mut().for_each(|c| *c = 0.);
+        for sample in 0..samples {
+            let y = to_model(row as f32 + (sample as f32 + 0.5) / samples as f32);
+            for (start, end) in spans(polygons, y, settings.fill_rule) {
+                let (start, end) = (to_pixel(start), to_pixel(end));
+                if samples == 1 {
+                    // a pixel is set if its center is covered
+                    let first = (start - 0.5).ceil().max(0.) as usize;
+                    let last = ((end - 0.5).floor() + 1.).clamp(0., width as f32) as usize;
+                    for c in coverage.iter_mut().take(last).skip(first) {
+                        *c = 1.;
+                    }
+                } else {
+                    add_coverage(&mut coverage, start, end, 1. / samples as f32);
+                }
+            }
+        }
+        for (x, c) in coverage.iter().enumerate() {
+            let value = if settings.monochrome {
+                if *c >= 0.5 {
+                    255
+                } else {
+                    0
+                }
+            } else {
+                (c.min(1.) * 255.).round() as u8
+            };
+            image.put_pixel(x as u32, row, Luma([value]));
+        }
+    }
+    return image;
+}
+
+/// filled x ranges of the horizontal line at `y`
+fn spans(polygons: &[Polygon], y: f32, rule: FillRule) -> Vec<(f32, f32)> {
+    let mut spans = Vec::new();
+    let mut winding = 0;
+    let mut start = 0.;
+    for (x, direction) in scanline_winding(polygons, y) {
+        let was_filled = rule.is_filled(winding);
+        winding += direction;
+        let filled = rule.is_filled(winding);
+        if !was_filled && filled {
+            start = x;
+        } else if was_filled && !filled {
+            spans.push((start, x));
+        }
+    }
+    return spans;
+}
+
+/// adds the part of every pixel covered by the range (in pixels) weighted by `weight`
+fn add_coverage(coverage: &mut [f32], start: f32, end: f32, weight: f32) {
+    let width = coverage.len() as f32;
+    let (start, end) = (start.clamp(0., width), end.clamp(0., width));
+    if start >= end {
+        return;
+    }
+    let (first, last) = (
+        start.floor() as usize,
+        (end.ceil() as usize).min(coverage.len()),
+    );
+    for (i, c) in coverage.iter_mut().enumerate().take(last).skip(first) {
+        let pixel_start = (i as f32).max(start);
+        let pixel_end = (i as f32 + 1.).min(end);
+        *c += (pixel_end - pixel_start) * weight;
+    }
+}
+
+/// Encodes the image as png. Binary images are written with one bit per pixel,
+/// which keeps them small and tells the printer that there are no gray values.
+fn png(image: &GrayImage, binary: bool) -> io::Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    {
+        let mut encoder = Encoder::new(&mut buffer, image.width(), image.height());
+        encoder.set_color(ColorType::Grayscale);
+        if binary {
+            encoder.set_depth(BitDepth::One);
+            encoder
+                .write_header()?
+                .write_image_data(&pack_bits(image))?;
+        } else {
+            encoder.set_depth(BitDepth::Eight);
+            encoder.write_header()?.write_image_data(image)?;
+        }
+    }
+    return Ok(buffer);
+}
+
+/// packs the pixels into one bit each, every row starts at a new byte
+fn pack_bits(image: &GrayImage) -> Vec<u8> {
+    let row_bytes = (image.width() as usize).div_ceil(8);
+    let mut data = vec![0u8; row_bytes * image.height() as usize];
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if pixel[0] >= 128 {
+            data[y as usize * row_bytes + x as usize / 8] |= 0x80 >> (x % 8);
+        }
+    }
+    return data;
+}
+
+/// name of the image of a layer, e.g. `00042.png`
+fn file_name(index: usize) -> String {
+    return format!("{:05}.png", index);
+}
+
+/// writes one png image per layer into the directory, which is created if needed
+pub fn write_layers(layers: &[Layer], settings: &RasterSettings, dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for (i, layer) in layers.iter().enumerate() {
+        let image = rasterize(&layer.polygons, settings);
+        File::create(dir.join(file_name(i)))?.write_all(&png(&image, settings.is_binary())?)?;
+    }
+    return Ok(());
+}
+
+/// Writes an image into the directory, named after the index of its layer.
+/// The settings it was made with are unknown, so it is binary if it only has black and white.
+pub fn write_image(image: GrayImage, index: usize, dir: &Path) -> io::Result<()> {
+    let binary = image.pixels().all(|p| p[0] == 0 || p[0] == 255);
+    return File::create(dir.join(file_name(index)))?.write_all(&png(&image, binary)?);
+}
+
+/// writes one png image per layer into a zip archive
+pub fn write_zip(layers: &[Layer], settings: &RasterSettings, file: &Path) -> io::Result<()> {
+    let mut zip = ZipWriter::new(File::create(file)?);
+    // the images are already compressed
+    let options = FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+    for (i, layer) in layers.iter().enumerate() {
+        let image = rasterize(&layer.polygons, settings);
+        zip.start_file(file_name(i), options)?;
+        zip.write_all(&png(&image, settings.is_binary())?)?;
+    }
+    zip.finish()?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Point2;
+
+    /// 10 x 10 pixels of 1 mm
+    fn settings(antialias: u32) -> RasterSettings {
+        return RasterSettings {
+            resolution: (10, 10),
+            pixel_size: 1.,
+            antialias,
+            ..RasterSettings::default()
+        };
+    }
+
+    #[test]
+    fn holes_stay_empty() {
+        let polygons = vec![
+            Polygon::rectangle(Point2::new(-4., -4.), Point2::new(4., 4.)),
+            Polygon::rectangle(Point2::new(-2., -2.), Point2::new(2., 2.)),
+        ];
+        let image = rasterize(&polygons, &settings(1));
+        assert_eq!(image.get_pixel(0, 5)[0], 0);
+        assert_eq!(image.get_pixel(2, 5)[0], 255);
+        assert_eq!(image.get_pixel(5, 5)[0], 0);
+    }
+
+    #[test]
+    fn rows_go_in_the_direction_of_y() {
+        // a square in the corner with the smallest x and y is drawn in the top left,
+        // like in the svg drawings
+        let polygons = vec![Polygon::rectangle(
+            Point2::new(-5., -5.),
+            Point2::new(-3., -3.),
+        )];
+        let image = rasterize(&polygons, &settings(1));
+        assert_eq!(image.get_pixel(0, 0)[0], 255);
+        assert_eq!(image.get_pixel(9, 9)[0], 0);
+        assert_eq!(image.get_pixel(0, 9)[0], 0);
+    }
+
+    #[test]
+    fn edges_are_anti_aliased() {
+        let polygons = vec![Polygon::rectangle(
+            Point2::new(-2.5, -5.),
+            Point2::new(2.5, 5.),
+        )];
+        let image = rasterize(&polygons, &settings(4));
+        assert_eq!(image.get_pixel(2, 5)[0], 128);
+        assert_eq!(image.get_pixel(5, 5)[0], 255);
+    }
+
+    #[test]
+    fn binary_images_use_one_bit_per_pixel() {
+        let polygons = vec![Polygon::rectangle(
+            Point2::new(-3., -3.),
+            Point2::new(3., 3.),
+        )];
+        for (settings, depth) in [
+            (settings(1), BitDepth::One),
+            (settings(4), BitDepth::Eight),
+            (
+                RasterSettings {
+                    monochrome: true,
+                    ..settings(4)
+                },
+                BitDepth::One,
+            ),
+        ] {
+            let image = rasterize(&polygons, &settings);
+            let data = png(&image, settings.is_binary()).unwrap();
+            let (_, reader) = png::Decoder::new(&data[..]).read_info().unwrap();
+            assert_eq!(reader.info().bit_depth, depth);
+
+            // the pixels survive the round trip
+            let decoded = image::load_from_memory(&data).unwrap().to_luma();
+            assert_eq!(decoded.into_raw(), image.into_raw());
+        }
+    }
+}
@@ -0,0 +1,300 @@
+use crate::model::{Layer, Model};
+use crate::polygons::{holes, islands, triangulate, Polygon, Vertex};
+use cgmath::*;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// how the volume between the slices is filled
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Method {
+    /// every layer becomes a prism as high as the layer, like stacked sheets
+    Extrude,
+    /// the contours of neighbouring layers are connected by a skin of triangles
+    Loft,
+}
+
+/// Builds a triangle mesh from the layers, which have to be sorted by height.
+/// Open lines left over from slicing are ignored.
+pub fn reconstruct(layers: &[Layer], method: Method) -> Model {
+    return match method {
+        Method::Extrude => extrude(layers),
+        Method::Loft => loft(layers),
+    };
+}
+
+/// collects the triangles of a mesh, points at the same position share one vertex
+#[derive(Default)]
+struct Mesh {
+    vertices: Vec<Point3<f32>>,
+    faces: Vec<[u32; 3]>,
+    indices: HashMap<[u32; 3], u32>,
+}
+
+impl Mesh {
+    /// index of a point of the slicing plane at the given height
+    fn vertex(&mut self, p: Vertex, height: f32) -> u32 {
+        let vertices = &mut self.vertices;
+        return *self
+            .indices
+            .entry([p.x.to_bits(), height.to_bits(), p.y.to_bits()])
+            .or_insert_with(|| {
+                vertices.push(Point3::new(p.x, height, p.y));
+                (vertices.len() - 1) as u32
+            });
+    }
+
+    fn triangle(&mut self, a: (Vertex, f32), b: (Vertex, f32), c: (Vertex, f32)) {
+        let face = [
+            self.vertex(a.0, a.1),
+            self.vertex(b.0, b.1),
+            self.vertex(c.0, c.1),
+        ];
+        // collapsed triangles add nothing but trouble
+        if face[0] != face[1] && face[1] != face[2] && face[2] != face[0] {
+            self.faces.push(face);
+        }
+    }
+
+    /// Closes an area at the given height. The triangles face upwards for a cap on top of
+    /// the material and downwards for a cap below it, `top` tells which one is needed.
+    fn cap(&mut self, island: &[Polygon], height: f32, top: bool) {
+        for [a, b, c] in triangulate(island) {
+            // triangulate returns counter clockwise triangles in the xz plane, which face down
+            if top {
+                self.triangle((a, height), (c, height), (b, height));
+            } else {
+                self.triangle((a, height), (b, height), (c, height));
+            }
+        }
+    }
+
+    fn into_model(self) -> Model {
+        return Model::new(self.vertices, self.faces);
+    }
+}
+
+/// Closed polygons as open rings without repeated points.
+/// Outlines are ordered counter clockwise and holes clockwise, so the material is always
+/// on the left side.
+fn rings(polygons: &[Polygon]) -> Vec<(Vec<Vertex>, bool, &Polygon)> {
+    return polygons
+        .iter()
+        .zip(holes(polygons))
+        .filter(|(p, _)| p.points.len() > 3 && p.points.first() == p.points.last())
+        .map(|(p, hole)| {
+            let mut points = p.points.clone();
+            points.dedup();
+            points.pop();
+            if (p.area() > 0.) == hole {
+                points.reverse();
+            }
+            (points, hole, p)
+        })
+        .filter(|(points, _, _)| points.len() > 2)
+        .collect();
+}
+
+fn extrude(layers: &[Layer]) -> Model {
+    let mut mesh = Mesh::default();
+    for layer in layers {
+        let half = layer.thickness / 2.;
+        let (bottom, top) = (layer.height - half, layer.height + half);
+        let rings = rings(&layer.polygons);
+        for (points, _, _) in &rings {
+            for i in 0..points.len() {
+                let (a, b) = (points[i], points[(i + 1) % points.len()]);
+                mesh.triangle((a, bottom), (a, top), (b, top));
+                mesh.triangle((a, bottom), (b, top), (b, bottom));
+            }
+        }
+        let closed: Vec<Polygon> = rings.iter().map(|(_, _, p)| (*p).clone()).collect();
+        for island in islands(&closed) {
+            mesh.cap(&island, top, true);
+            mesh.cap(&island, bottom, false);
+        }
+    }
+    return mesh.into_model();
+}
+
+fn loft(layers: &[Layer]) -> Model {
+    let mut mesh = Mesh::default();
+    let rings: Vec<Vec<(Vec<Vertex>, bool, &Polygon)>> =
+        layers.iter().map(|l| rings(&l.polygons)).collect();
+    // which rings are connected to the layer below and above
+    let mut below: Vec<Vec<bool>> = rings.iter().map(|r| vec![false; r.len()]).collect();
+    let mut above = below.clone();
+
+    for l in 1..layers.len() {
+        for (i, j) in matching(&rings[l - 1], &rings[l]) {
+            stitch(
+                &mut mesh,
+                &rings[l - 1][i].0,
+                layers[l - 1].height,
+                &rings[l][j].0,
+                layers[l].height,
+            );
+            above[l - 1][i] = true;
+            below[l][j] = true;
+        }
+    }
+
+    // close the rings which are not connected
+    for (l, layer) in layers.iter().enumerate() {
+        for (top, connected) in [(true, &above[l]), (false, &below[l])].iter() {
+            let open: Vec<Polygon> = rings[l]
+                .iter()
+                .zip(connected.iter())
+                .filter(|(_, c)| !**c)
+                .map(|((_, _, p), _)| (*p).clone())
+                .collect();
+            for island in islands(&open) {
+                // a cap over a hole faces the other way than a cap over an outline
+                let hole = rings[l]
+                    .iter()
+                    .find(|(_, _, p)| **p == island[0])
+                    .map(|(_, hole, _)| *hole)
+                    .unwrap_or(false);
+                mesh.cap(&island, layer.height, *top != hole);
+            }
+        }
+    }
+    return mesh.into_model();
+}
+
+/// Pairs the rings of two neighbouring layers. Rings are only paired with rings of the same
+/// kind (outline or hole) whose bounding box overlaps, closer rings are paired first.
+fn matching(
+    lower: &[(Vec<Vertex>, bool, &Polygon)],
+    upper: &[(Vec<Vertex>, bool, &Polygon)],
+) -> Vec<(usize, usize)> {
+    let bounds = |points: &[Vertex]| {
+        points.iter().fold((points[0], points[0]), |(min, max), p| {
+            (
+                Point2::new(min.x.min(p.x), min.y.min(p.y)),
+                Point2::new(max.x.max(p.x), max.y.max(p.y)),
+            )
+        })
+    };
+    let center = |points: &[Vertex]| {
+        let sum = points.iter().fold(Vector2::zero(), |s, p| s + p.to_vec());
+        Point2::from_vec(sum / points.len() as f32)
+    };
+    let mut candidates: Vec<(f32, usize, usize)> = Vec::new();
+    for (i, (a, hole_a, _)) in lower.iter().enumerate() {
+        for (j, (b, hole_b, _)) in upper.iter().enumerate() {
+            let ((min_a, max_a), (min_b, max_b)) = (bounds(a), bounds(b));
+            let overlap = min_a.x <= max_b.x
+                && min_b.x <= max_a.x
+                && min_a.y <= max_b.y
+                && min_b.y <= max_a.y;
+            if hole_a == hole_b && overlap {
+                candidates.push(((center(a) - center(b)).magnitude(), i, j));
+            }
+        }
+    }
+    candidates.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    let mut used_lower = vec![false; lower.len()];
+    let mut used_upper = vec![false; upper.len()];
+    let mut pairs = Vec::new();
+    for (_, i, j) in candidates {
+        if !used_lower[i] && !used_upper[j] {
+            used_lower[i] = true;
+            used_upper[j] = true;
+            pairs.push((i, j));
+        }
+    }
+    return pairs;
+}
+
+/// Connects two rings with triangles. Starting at the closest points, the ring whose next
+/// point gives the shorter diagonal is advanced.
+fn stitch(
+    mesh: &mut Mesh,
+    lower: &[Vertex],
+    lower_height: f32,
+    upper: &[Vertex],
+    upper_height: f32,
+) {
+    let (n, m) = (lower.len(), upper.len());
+    let start = (0..m)
+        .min_by(|a, b| {
+            let da = (upper[*a] - lower[0]).magnitude2();
+            let db = (upper[*b] - lower[0]).magnitude2();
+            da.partial_cmp(&db).unwrap()
+        })
+        .unwrap();
+    let a = |i: usize| (lower[i % n], lower_height);
+    let b = |j: usize| (upper[(start + j) % m], upper_height);
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        let advance_lower = if i == n {
+            false
+        } else if j == m {
+            true
+        } else {
+            (a(i + 1).0 - b(j).0).magnitude2() < (a(i).0 - b(j + 1).0).magnitude2()
+        };
+        if advance_lower {
+            mesh.triangle(a(i), b(j), a(i + 1));
+            i += 1;
+        } else {
+            mesh.triangle(a(i), b(j), b(j + 1));
+            j += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::tests::boxes;
+
+    /// volume enclosed by the mesh, which is only right for closed meshes
+    fn volume(model: &Model) -> f32 {
+        return model
+            .triangles()
+            .map(|([a, b, c], _)| a.to_vec().dot(b.to_vec().cross(c.to_vec())) / 6.)
+            .sum();
+    }
+
+    /// checks that every edge is shared by exactly two faces going opposite ways
+    fn is_closed(model: &Model) -> bool {
+        let key = |p: Point3<f32>| [p.x, p.y, p.z].map(f32::to_bits);
+        let mut edges: HashMap<([u32; 3], [u32; 3]), i32> = HashMap::new();
+        for (corners, _) in model.triangles() {
+            for i in 0..3 {
+                let (a, b) = (key(corners[i]), key(corners[(i + 1) % 3]));
+                *edges.entry((a.min(b), a.max(b))).or_default() += if a < b { 1 } else { -1 };
+            }
+        }
+        return edges.values().all(|count| *count == 0);
+    }
+
+    #[test]
+    fn extruded_layers_fill_their_thickness() {
+        // a frame, so the caps have a hole
+        let model = boxes(&[([0., 0., 0.], [10., 2., 10.])]);
+        let mut layers = model.layers(0.5, 0.01);
+        for layer in layers.iter_mut() {
+            layer
+                .polygons
+                .push(Polygon::rectangle(Point2::new(3., 3.), Point2::new(7., 7.)));
+        }
+        let mesh = reconstruct(&layers, Method::Extrude);
+        assert!((volume(&mesh) - 84. * 2.).abs() < 1e-2);
+    }
+
+    #[test]
+    fn lofted_layers_are_closed() {
+        let model = boxes(&[([0., 0., 0.], [10., 2., 6.])]);
+        let layers = model.layers(0.5, 0.01);
+        let mesh = reconstruct(&layers, Method::Loft);
+        assert!(is_closed(&mesh));
+        // from the lowest to the highest slice
+        assert!((volume(&mesh) - 60. * 1.5).abs() < 1e-2);
+        let (min, max) = mesh.bounds();
+        assert!((min.y - 0.25).abs() < 1e-5 && (max.y - 1.75).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,468 @@
+//! Container for resin printing jobs.
+//!
+//! All numbers are little endian, offsets are counted from the start of the file.
+//!
+//! ```text
+//! header
+//!   magic               4 bytes  "DSCO"
+//!   version             u32      currently 1
+//!   width, height       u32, u32 resolution in pixels
+//!   pixel size          f32      mm
+//!   layer height        f32      mm
+//!   exposure            f32      seconds per normal layer
+//!   bottom exposure     f32      seconds per bottom layer
+//!   bottom layers       u32      number of layers exposed with the bottom exposure
+//!   light off delay     f32      seconds between two exposures
+//!   preview offset      u32
+//!   layer table offset  u32
+//!   layer count         u32
+//! preview
+//!   width, height       u32, u32
+//!   data length         u32
+//!   data                rle encoded 8 bit gray image
+//! layer table, one entry per layer
+//!   height              f32      mm
+//!   exposure            f32      seconds
+//!   data offset         u32
+//!   data length         u32
+//! layer data            rle encoded 8 bit gray images
+//! ```
+//!
+//! Images are stored row by row. Each run of equal pixels is written as the gray value (one byte)
+//! followed by the run length as variable length integer: 7 bits per byte, least significant
+//! bits first, the highest bit is set if another byte follows.
+
+use crate::model::Layer;
+use crate::profile::Profile;
+use crate::raster::{rasterize, RasterSettings};
+use image::imageops::{resize, FilterType};
+use image::GrayImage;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"DSCO";
+const VERSION: u32 = 1;
+const HEADER_SIZE: u32 = 52;
+const LAYER_ENTRY_SIZE: u32 = 16;
+/// largest image accepted when reading, far more pixels than any printer has
+const MAX_PIXELS: u64 = 1 << 28;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExposureSettings {
+    /// exposure time of a normal layer (s)
+    pub exposure: f32,
+    /// exposure time of the first layers, which need to stick to the build plate (s)
+    pub bottom_exposure: f32,
+    pub bottom_layers: u32,
+    /// time between two exposures (s)
+    pub light_off_delay: f32,
+}
+
+impl Default for ExposureSettings {
+    fn default() -> Self {
+        return ExposureSettings {
+            exposure: 2.5,
+            bottom_exposure: 30.,
+            bottom_layers: 4,
+            light_off_delay: 1.,
+        };
+    }
+}
+
+impl ExposureSettings {
+    pub fn from_profile(profile: &Profile) -> ExposureSettings {
+        let resin = &profile.resin;
+        return ExposureSettings {
+            exposure: resin.exposure,
+            bottom_exposure: resin.bottom_exposure,
+            bottom_layers: resin.bottom_layers,
+            light_off_delay: resin.light_off_delay,
+        };
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResinLayer {
+    pub height: f32,
+    pub exposure: f32,
+    /// rle encoded image
+    data: Vec<u8>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResinFile {
+    pub width: u32,
+    pub height: u32,
+    pub pixel_size: f32,
+    pub layer_height: f32,
+    pub exposure: ExposureSettings,
+    pub preview: GrayImage,
+    pub layers: Vec<ResinLayer>,
+}
+
+impl ResinFile {
+    /// rasterizes the layers and creates a preview showing the model from above
+    pub fn from_layers(
+        layers: &[Layer],
+        raster: &RasterSettings,
+        exposure: &ExposureSettings,
+        preview_size: (u32, u32),
+    ) -> ResinFile {
+        let (width, height) = raster.resolution;
+        let mut top_view = GrayImage::new(width, height);
+        let resin_layers = layers
+            .iter()
+            .enumerate()
+            .map(|(i, layer)| {
+                let image = rasterize(&layer.polygons, raster);
+                for (top, pixel) in top_view.pixels_mut().zip(image.pixels()) {
+                    top.0[0] = top.0[0].max(pixel.0[0]);
+                }
+                ResinLayer {
+                    height: layer.height,
+                    exposure: if (i as u32) < exposure.bottom_layers {
+                        exposure.bottom_exposure
+                    } else {
+                        exposure.exposure
+                    },
+                    data: encode(&image),
+                }
+            })
+            .collect();
+        let layer_height = if layers.len() > 1 {
+            layers[1].height - layers[0].height
+        } else {
+            0.
+        };
+        return ResinFile {
+            width,
+            height,
+            pixel_size: raster.pixel_size,
+            layer_height,
+            exposure: exposure.clone(),
+            preview: resize(
+                &top_view,
+                preview_size.0,
+                preview_size.1,
+                FilterType::Triangle,
+            ),
+            layers: resin_layers,
+        };
+    }
+
+    /// decodes the image of a layer
+    pub fn layer_image(&self, index: usize) -> io::Result<GrayImage> {
+        return decode(&self.layers[index].data, self.width, self.height);
+    }
+
+    pub fn save(&self, file: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(file)?);
+        self.write(&mut writer)?;
+        return writer.flush();
+    }
+
+    pub fn load(file: &Path) -> io::Result<ResinFile> {
+        return ResinFile::read(&mut BufReader::new(File::open(file)?));
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let preview = encode(&self.preview);
+        let preview_offset = HEADER_SIZE;
+        let table_offset = preview_offset + 12 + preview.len() as u32;
+        let mut data_offset = table_offset + LAYER_ENTRY_SIZE * self.layers.len() as u32;
+
+        w.write_all(MAGIC)?;
+        write_u32(w, VERSION)?;
+        write_u32(w, self.width)?;
+        write_u32(w, self.height)?;
+        write_f32(w, self.pixel_size)?;
+        write_f32(w, self.layer_height)?;
+        write_f32(w, self.exposure.exposure)?;
+        write_f32(w, self.exposure.bottom_exposure)?;
+        write_u32(w, self.exposure.bottom_layers)?;
+        write_f32(w, self.exposure.light_off_delay)?;
+        write_u32(w, preview_offset)?;
+        write_u32(w, table_offset)?;
+        write_u32(w, self.layers.len() as u32)?;
+
+        write_u32(w, self.preview.width())?;
+        write_u32(w, self.preview.height())?;
+        write_u32(w, preview.len() as u32)?;
+        w.write_all(&preview)?;
+
+        for layer in &self.layers {
+            write_f32(w, layer.height)?;
+            write_f32(w, layer.exposure)?;
+            write_u32(w, data_offset)?;
+            write_u32(w, layer.data.len() as u32)?;
+            data_offset += layer.data.len() as u32;
+        }
+        for layer in &self.layers {
+            w.write_all(&layer.data)?;
+        }
+        return Ok(());
+    }
+
+    pub fn read<R: Read>(r: &mut R) -> io::Result<ResinFile> {
+        let mut content = Vec::new();
+        r.read_to_end(&mut content)?;
+        let mut header = Cursor::new(&content, 0);
+
+        if header.bytes(4)? != MAGIC {
+            return Err(invalid("not a resin file"));
+        }
+        let version = header.u32()?;
+        if version != VERSION {
+            return Err(invalid(&format!("unsupported version {}", version)));
+        }
+        let width = header.u32()?;
+        let height = header.u32()?;
+        let pixel_size = header.f32()?;
+        let layer_height = header.f32()?;
+        let exposure = ExposureSettings {
+            exposure: header.f32()?,
+            bottom_exposure: header.f32()?,
+            bottom_layers: header.u32()?,
+            light_off_delay: header.f32()?,
+        };
+        let preview_offset = header.u32()?;
+        let table_offset = header.u32()?;
+        let layer_count = header.u32()?;
+
+        let mut preview = Cursor::new(&content, preview_offset);
+        let (preview_width, preview_height) = (preview.u32()?, preview.u32()?);
+        let length = preview.u32()?;
+        let preview = decode(preview.bytes(length)?, preview_width, preview_height)?;
+
+        let mut table = Cursor::new(&content, table_offset);
+        let layers = (0..layer_count)
+            .map(|_| {
+                let height = table.f32()?;
+                let exposure = table.f32()?;
+                let offset = table.u32()?;
+                let length = table.u32()?;
+                return Ok(ResinLayer {
+                    height,
+                    exposure,
+                    data: Cursor::new(&content, offset).bytes(length)?.to_vec(),
+                });
+            })
+            .collect::<io::Result<Vec<ResinLayer>>>()?;
+
+        return Ok(ResinFile {
+            width,
+            height,
+            pixel_size,
+            layer_height,
+            exposure,
+            preview,
+            layers,
+        });
+    }
+}
+
+/// rle encodes a gray image, see the module documentation for the format
+pub fn encode(image: &GrayImage) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut pixels = image.pixels().map(|p| p.0[0]).peekable();
+    while let Some(value) = pixels.next() {
+        let mut length: u64 = 1;
+        while pixels.next_if_eq(&value).is_some() {
+            length += 1;
+        }
+        data.push(value);
+        loop {
+            let byte = (length & 0x7f) as u8;
+            length >>= 7;
+            if length == 0 {
+                data.push(byte);
+                break;
+            }
+            data.push(byte | 0x80);
+        }
+    }
+    return data;
+}
+
+/// Decodes an rle encoded image of the given size.
+/// The size comes from the file, so the pixels are only allocated as the runs are decoded.
+pub fn decode(data: &[u8], width: u32, height: u32) -> io::Result<GrayImage> {
+    let size = width as u64 * height as u64;
+    if size > MAX_PIXELS {
+        return Err(invalid(&format!(
+            "image of {}x{} pixels is too large",
+            width, height
+        )));
+    }
+    let mut pixels: Vec<u8> = Vec::new();
+    let mut bytes = data.iter();
+    while let Some(value) = bytes.next() {
+        let mut length: u64 = 0;
+        let mut shift = 0;
+        loop {
+            let byte = *bytes
+                .next()
+                .ok_or_else(|| invalid("run length is missing"))?;
+            length |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+            if shift > 63 {
+                return Err(invalid("run length is too long"));
+            }
+        }
+        if pixels.len() as u64 + length > size {
+            return Err(invalid("image data exceeds the image size"));
+        }
+        pixels.extend(std::iter::repeat_n(*value, length as usize));
+    }
+    return GrayImage::from_raw(width, height, pixels)
+        .ok_or_else(|| invalid("image data is shorter than the image"));
+}
+
+fn invalid(message: &str) -> io::Error {
+    return io::Error::new(io::ErrorKind::InvalidData, message);
+}
+
+fn write_u32<W: Write>(w: &mut W, value: u32) -> io::Result<()> {
+    return w.write_all(&value.to_le_bytes());
+}
+
+fn write_f32<W: Write>(w: &mut W, value: f32) -> io::Result<()> {
+    return w.write_all(&value.to_le_bytes());
+}
+
+/// reads values from a position within the file's content
+struct Cursor<'a> {
+    content: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(content: &'a [u8], offset: u32) -> Cursor<'a> {
+        return Cursor {
+            content,
+            position: offset as usize,
+        };
+    }
+
+    fn bytes(&mut self, length: u32) -> io::Result<&'a [u8]> {
+        let end = self.position + length as usize;
+        if end > self.content.len() {
+            return Err(invalid("unexpected end of file"));
+        }
+        let bytes = &self.content[self.position..end];
+        self.position = end;
+        return Ok(bytes);
+    }
+
+    fn u32(&mut self) -> io::Result<u32> {
+        let mut buffer = [0; 4];
+        buffer.copy_from_slice(self.bytes(4)?);
+        return Ok(u32::from_le_bytes(buffer));
+    }
+
+    fn f32(&mut self) -> io::Result<f32> {
+        return Ok(f32::from_bits(self.u32()?));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::polygons::Polygon;
+    use cgmath::Point2;
+    use image::Luma;
+
+    fn layers() -> Vec<Layer> {
+        return (0..6)
+            .map(|i| Layer {
+                height: 0.05 * (i + 1) as f32,
+                thickness: 0.05,
+                polygons: vec![Polygon::rectangle(
+                    Point2::new(-1. - i as f32 * 0.1, -1.),
+                    Point2::new(1., 0.5 + i as f32 * 0.1),
+                )],
+            })
+            .collect();
+    }
+
+    fn settings() -> RasterSettings {
+        return RasterSettings {
+            resolution: (64, 48),
+            pixel_size: 0.05,
+            ..RasterSettings::default()
+        };
+    }
+
+    #[test]
+    fn write_and_read() {
+        let exposure = ExposureSettings {
+            exposure: 3.,
+            bottom_exposure: 25.,
+            bottom_layers: 2,
+            light_off_delay: 0.5,
+        };
+        let file = ResinFile::from_layers(&layers(), &settings(), &exposure, (16, 12));
+        let mut bytes = Vec::new();
+        file.write(&mut bytes).unwrap();
+        assert_eq!(&bytes[..4], MAGIC);
+
+        let read = ResinFile::read(&mut bytes.as_slice()).unwrap();
+        assert_eq!((read.width, read.height), (64, 48));
+        assert_eq!(read.pixel_size, 0.05);
+        assert_eq!(read.layer_height, file.layer_height);
+        assert_eq!(read.exposure, exposure);
+        assert_eq!(read.preview.as_ref(), file.preview.as_ref());
+        assert_eq!(read.preview.dimensions(), (16, 12));
+        assert_eq!(read.layers, file.layers);
+        let exposures: Vec<f32> = read.layers.iter().map(|l| l.exposure).collect();
+        assert_eq!(exposures, vec![25., 25., 3., 3., 3., 3.]);
+        for (i, layer) in layers().iter().enumerate() {
+            let image = read.layer_image(i).unwrap();
+            assert_eq!(image.dimensions(), (64, 48));
+            assert_eq!(
+                image.as_ref(),
+                rasterize(&layer.polygons, &settings()).as_ref()
+            );
+        }
+    }
+
+    #[test]
+    fn run_lengths() {
+        // runs which need one, two and three bytes for their length
+        let mut image = GrayImage::new(200, 100);
+        for (i, pixel) in image.pixels_mut().enumerate() {
+            pixel.0[0] = match i {
+                0..=99 => 255,
+                100..=299 => 17,
+                _ if i < 19_000 => 0,
+                _ => 128,
+            };
+        }
+        let data = encode(&image);
+        assert_eq!(&data[..4], &[255, 100, 17, 0xc8]);
+        assert_eq!(decode(&data, 200, 100).unwrap().as_ref(), image.as_ref());
+    }
+
+    #[test]
+    fn invalid_data() {
+        let image = GrayImage::from_pixel(4, 4, Luma([9]));
+        let data = encode(&image);
+        assert!(decode(&data, 4, 3).is_err());
+        assert!(decode(&data, 5, 4).is_err());
+        assert!(decode(&data[..data.len() - 1], 4, 4).is_err());
+        // the size in the header is not trusted
+        assert!(decode(&data, u32::MAX, u32::MAX).is_err());
+        assert!(decode(&data, 1 << 14, 1 << 14).is_err());
+        assert!(ResinFile::read(&mut &b"DISCO"[..]).is_err());
+
+        let file = ResinFile::from_layers(&layers(), &settings(), &Default::default(), (8, 8));
+        let mut bytes = Vec::new();
+        file.write(&mut bytes).unwrap();
+        bytes.truncate(bytes.len() - 1);
+        assert!(ResinFile::read(&mut bytes.as_slice()).is_err());
+    }
+}
@@ -0,0 +1,263 @@
+//! Chooses where closed loops start, the seam.
+//!
+//! Every loop leaves a small blob or gap where it starts and ends. The seams are placed on
+//! the contours of the layers, the perimeters created from them start at the same corner.
+
+use crate::model::Layer;
+use crate::polygons::{scalar, Polygon, Vertex};
+use crate::profile::{Profile, Seam};
+use cgmath::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SeamPosition {
+    /// the corner closest to the seam of the layer below, which lines the seams up
+    Aligned,
+    /// the corner closest to the end of the previous loop, which keeps travel moves short
+    Nearest,
+    /// a random corner, which spreads the seams over the surface
+    Random { seed: u64 },
+    /// the corner at the back of the printer (largest z)
+    Rearmost,
+    /// the sharpest concave corner, where the seam is hard to see,
+    /// loops without concave corners use the rearmost one
+    Sharpest,
+}
+
+impl SeamPosition {
+    pub fn from_profile(profile: &Profile) -> SeamPosition {
+        let print = &profile.print;
+        return match print.seam {
+            Seam::Aligned => SeamPosition::Aligned,
+            Seam::Nearest => SeamPosition::Nearest,
+            Seam::Random => SeamPosition::Random {
+                seed: print.seam_seed,
+            },
+            Seam::Rearmost => SeamPosition::Rearmost,
+            Seam::Sharpest => SeamPosition::Sharpest,
+        };
+    }
+}
+
+/// Starts the closed polygons of all layers at their seam.
+/// The layers are processed from the bottom up, so `Aligned` follows the layers below.
+pub fn place_seams<S: BaseFloat>(layers: &mut [Layer<S>], position: SeamPosition) {
+    let mut below: Vec<Vertex<S>> = Vec::new();
+    for (l, layer) in layers.iter_mut().enumerate() {
+        let mut seams: Vec<Vertex<S>> = Vec::new();
+        for (i, polygon) in layer.polygons.iter_mut().enumerate() {
+            let closed =
+                polygon.points.len() > 3 && polygon.points.first() == polygon.points.last();
+            if !closed || polygon.normals.len() != polygon.points.len() - 1 {
+                continue;
+            }
+            let corner = match position {
+                SeamPosition::Aligned => match closest_seam(&below, polygon) {
+                    Some(seam) => closest(polygon, seam),
+                    None => rearmost(polygon),
+                },
+                SeamPosition::Nearest => match seams.last() {
+                    Some(previous) => closest(polygon, *previous),
+                    None => rearmost(polygon),
+                },
+                SeamPosition::Random { seed } => {
+                    (random(seed, l as u64, i as u64) % (polygon.points.len() as u64 - 1)) as usize
+                }
+                SeamPosition::Rearmost => rearmost(polygon),
+                SeamPosition::Sharpest => sharpest(polygon).unwrap_or_else(|| rearmost(polygon)),
+            };
+            rotate(polygon, corner);
+            seams.push(polygon.points[0]);
+        }
+        below = seams;
+    }
+}
+
+/// the seam of the layer below that is closest to the polygon, if there is one nearby
+fn closest_seam<S: BaseFloat>(seams: &[Vertex<S>], polygon: &Polygon<S>) -> Option<Vertex<S>> {
+    return seams
+        .iter()
+        .map(|s| (*s, polygon.distance(*s)))
+        .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+        .map(|(s, _)| s);
+}
+
+/// index of the corner closest to the point
+fn closest<S: BaseFloat>(polygon: &Polygon<S>, to: Vertex<S>) -> usize {
+    return best(polygon, |i, p| -(p[i] - to).magnitude2());
+}
+
+fn rearmost<S: BaseFloat>(polygon: &Polygon<S>) -> usize {
+    // ties are decided by the smaller x, so straight back edges still give a stable corner
+    return best(polygon, |i, p| (p[i].y, -p[i].x));
+}
+
+/// The concave corner with the largest change of direction. A corner is concave if the
+/// next edge turns towards the outside, which the normal of the previous edge points to.
+fn sharpest<S: BaseFloat>(polygon: &Polygon<S>) -> Option<usize> {
+    let points = &polygon.points;
+    let n = points.len() - 1;
+    let mut result: Option<(usize, S)> = None;
+    for i in 0..n {
+        let previous = (i + n - 1) % n;
+        let (e1, e2) = (points[i] - points[previous], points[i + 1] - points[i]);
+        if e1.magnitude2() == S::zero() || e2.magnitude2() == S::zero() {
+            continue;
+        }
+        if e2.dot(polygon.normals[previous]) <= S::zero() {
+            continue;
+        }
+        let turn = e1.angle(e2).0.abs();
+        // a small margin keeps the choice stable across layers with nearly equal corners
+        if result.is_none_or(|(_, best)| turn > best + scalar(1e-3)) {
+            result = Some((i, turn));
+        }
+    }
+    return result.map(|(i, _)| i);
+}
+
+/// index of the corner with the highest score, the repeated last point is ignored
+fn best<S, T, F>(polygon: &Polygon<S>, score: F) -> usize
+where
+    S: BaseFloat,
+    T: PartialOrd,
+    F: Fn(usize, &[Vertex<S>]) -> T,
+{
+    let points = &polygon.points;
+    return (0..points.len() - 1)
+        .max_by(|a, b| score(*a, points).partial_cmp(&score(*b, points)).unwrap())
+        .unwrap_or(0);
+}
+
+/// starts a closed polygon at the given corner
+fn rotate<S: BaseFloat>(polygon: &mut Polygon<S>, corner: usize) {
+    polygon.points.pop();
+    polygon.points.rotate_left(corner);
+    polygon.points.push(polygon.points[0]);
+    polygon.normals.rotate_left(corner);
+}
+
+/// a linear congruential generator, the same seed, layer and loop always give the same number
+fn random(seed: u64, layer: u64, index: u64) -> u64 {
+    let mut state = seed ^ layer.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ index.wrapping_mul(31);
+    for _ in 0..3 {
+        state = state
+            .wrapping_mul(6_364_136_223_846_793_005)
+            .wrapping_add(1_442_695_040_888_963_407);
+    }
+    return state >> 33;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a closed counter clockwise polygon with outward normals
+    fn polygon(corners: &[(f32, f32)]) -> Polygon {
+        let mut points: Vec<Vertex> = corners.iter().map(|(x, y)| Point2::new(*x, *y)).collect();
+        points.push(points[0]);
+        let normals = points
+            .windows(2)
+            .map(|e| Vector2::new(e[1].y - e[0].y, e[0].x - e[1].x).normalize())
+            .collect();
+        return Polygon {
+            points,
+            normals,
+            material: 0,
+        };
+    }
+
+    fn layer(polygons: Vec<Polygon>) -> Layer {
+        return Layer {
+            height: 0.1,
+            thickness: 0.2,
+            polygons,
+        };
+    }
+
+    fn seams(position: SeamPosition, polygons: Vec<Polygon>) -> Vec<Vertex> {
+        let mut layers = vec![layer(polygons)];
+        place_seams(&mut layers, position);
+        for polygon in &layers[0].polygons {
+            // the polygon is still closed and its normals follow its edges
+            assert_eq!(polygon.points.first(), polygon.points.last());
+            for ((a, b), n) in polygon.edges().zip(&polygon.normals) {
+                assert!((b - a).dot(*n).abs() < 1e-4);
+            }
+        }
+        return layers[0].polygons.iter().map(|p| p.points[0]).collect();
+    }
+
+    #[test]
+    fn seams_start_at_the_chosen_corner() {
+        let l = || {
+            polygon(&[
+                (0., 0.),
+                (10., 0.),
+                (10., 5.),
+                (5., 5.),
+                (5., 10.),
+                (0., 10.),
+            ])
+        };
+        let far = || polygon(&[(20., 0.), (30., 0.), (30., 10.), (20., 10.)]);
+        assert_eq!(
+            seams(SeamPosition::Rearmost, vec![l()]),
+            vec![Point2::new(0., 10.)]
+        );
+        assert_eq!(
+            seams(SeamPosition::Sharpest, vec![l()]),
+            vec![Point2::new(5., 5.)]
+        );
+        // the square has no concave corner
+        assert_eq!(
+            seams(SeamPosition::Sharpest, vec![far()]),
+            vec![Point2::new(20., 10.)]
+        );
+        // the second loop starts close to where the first one ended
+        assert_eq!(
+            seams(SeamPosition::Nearest, vec![l(), far()]),
+            vec![Point2::new(0., 10.), Point2::new(20., 10.)]
+        );
+        assert_eq!(
+            seams(SeamPosition::Nearest, vec![far(), l()]),
+            vec![Point2::new(20., 10.), Point2::new(10., 5.)]
+        );
+    }
+
+    #[test]
+    fn aligned_seams_follow_the_layer_below() {
+        // a square turned a little more on every layer
+        let mut layers: Vec<Layer> = (0..5)
+            .map(|i| {
+                let turn = Basis2::from_angle(Deg(5. * i as f32));
+                let corners: Vec<(f32, f32)> = [(-5., -5.), (5., -5.), (5., 5.), (-5., 5.)]
+                    .iter()
+                    .map(|(x, y)| turn.rotate_point(Point2::new(*x, *y)))
+                    .map(|p| (p.x, p.y))
+                    .collect();
+                layer(vec![polygon(&corners)])
+            })
+            .collect();
+        place_seams(&mut layers, SeamPosition::Aligned);
+        // the rearmost corner of the first layer is at the top left, the other layers keep
+        // the corner close to it even when another corner gets further back
+        for layer in &layers {
+            let seam = layer.polygons[0].points[0];
+            assert!(seam.x < 0. && seam.y > 0., "{:?}", seam);
+        }
+    }
+
+    #[test]
+    fn random_seams_depend_on_the_seed() {
+        let circles = || -> Vec<Polygon> {
+            return (0..8)
+                .map(|i| Polygon::circle(Point2::new(20. * i as f32, 0.), 5., 32))
+                .collect();
+        };
+        let a = seams(SeamPosition::Random { seed: 1 }, circles());
+        let b = seams(SeamPosition::Random { seed: 1 }, circles());
+        let c = seams(SeamPosition::Random { seed: 2 }, circles());
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+    }
+}
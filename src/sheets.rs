@@ -0,0 +1,425 @@
+use crate::model::{Layer, Model};
+use crate::polygons::{inside, islands, Polygon, Vertex};
+use crate::profile::Profile;
+use crate::svg::{self, Document, Element, Group, Style};
+use cgmath::*;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct SheetSettings {
+    /// width and height of a sheet (mm)
+    pub sheet_size: (f32, f32),
+    /// thickness of the material, the model is sliced at this distance
+    pub thickness: f32,
+    /// free space along the edges of a sheet
+    pub margin: f32,
+    /// distance between two pieces
+    pub spacing: f32,
+    /// holes for alignment pins, which line up across all layers
+    pub registration: Option<Registration>,
+    /// font size of the layer numbers engraved on the pieces
+    pub label_size: f32,
+}
+
+impl Default for SheetSettings {
+    fn default() -> Self {
+        return SheetSettings {
+            sheet_size: (600., 400.),
+            thickness: 3.,
+            margin: 5.,
+            spacing: 3.,
+            registration: Some(Registration {
+                diameter: 3.,
+                spacing: 20.,
+            }),
+            label_size: 4.,
+        };
+    }
+}
+
+impl SheetSettings {
+    pub fn from_profile(profile: &Profile) -> SheetSettings {
+        let sheets = &profile.sheets;
+        return SheetSettings {
+            sheet_size: (sheets.size[0], sheets.size[1]),
+            thickness: sheets.thickness,
+            margin: sheets.margin,
+            spacing: sheets.spacing,
+            registration: if sheets.registration_diameter > 0. {
+                Some(Registration {
+                    diameter: sheets.registration_diameter,
+                    spacing: sheets.registration_spacing,
+                })
+            } else {
+                None
+            },
+            label_size: sheets.label_size,
+        };
+    }
+}
+
+/// Registration holes are placed on a grid in model coordinates,
+/// so the holes of neighbouring layers are on top of each other.
+#[derive(Debug, Clone)]
+pub struct Registration {
+    pub diameter: f32,
+    /// distance between the grid points
+    pub spacing: f32,
+}
+
+/// a connected part of a layer, in sheet coordinates once it is placed
+#[derive(Debug, Clone)]
+pub struct Piece {
+    /// index of the layer the piece was cut from
+    pub layer: usize,
+    /// the outline followed by its holes
+    pub polygons: Vec<Polygon>,
+    /// centers of the registration holes
+    pub registration: Vec<Vertex>,
+    /// where the layer number is engraved
+    pub label: Vertex,
+}
+
+impl Piece {
+    fn bounds(&self) -> (Vertex, Vertex) {
+        // the outline contains everything else
+        return bounds(&self.polygons[0].points);
+    }
+
+    fn translate(&mut self, offset: Vector2<f32>) {
+        for polygon in self.polygons.iter_mut() {
+            polygon.points.iter_mut().for_each(|p| *p += offset);
+        }
+        self.registration.iter_mut().for_each(|p| *p += offset);
+        self.label += offset;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Sheet {
+    pub pieces: Vec<Piece>,
+}
+
+#[derive(Debug)]
+pub enum SheetError {
+    /// a piece does not fit onto a sheet
+    TooLarge {
+        layer: usize,
+        width: f32,
+        height: f32,
+    },
+}
+
+impl fmt::Display for SheetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SheetError::TooLarge {
+                layer,
+                width,
+                height,
+            } => write!(
+                f,
+                "a piece of layer {} ({} x {} mm) does not fit onto a sheet",
+                layer, width, height
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SheetError {}
+
+/// slices the model into layers as thick as the sheet material
+pub fn slice_sheets(model: &Model, settings: &SheetSettings, tolerance: f32) -> Vec<Layer> {
+    return model.layers(settings.thickness, tolerance);
+}
+
+/// Splits the layers into pieces and places them onto as few sheets as possible.
+///
+/// The pieces are sorted by height and placed next to each other in rows (shelves),
+/// a new row is started when a piece does not fit into the current one and a new sheet
+/// when a row does not fit onto the current sheet.
+pub fn nest(layers: &[Layer], settings: &SheetSettings) -> Result<Vec<Sheet>, SheetError> {
+    let mut pieces: Vec<Piece> = layers
+        .iter()
+        .enumerate()
+        .flat_map(|(i, layer)| pieces(i, layer, settings))
+        .collect();
+    pieces.sort_by(|a, b| {
+        let (ha, hb) = (size(a).y, size(b).y);
+        hb.partial_cmp(&ha).unwrap()
+    });
+
+    let (width, height) = settings.sheet_size;
+    let usable = Vector2::new(width - 2. * settings.margin, height - 2. * settings.margin);
+    let mut sheets: Vec<Sheet> = Vec::new();
+    // position of the next piece and height of the current row
+    let mut cursor = Point2::new(settings.margin, settings.margin);
+    let mut row_height = 0.;
+    for mut piece in pieces {
+        let s = size(&piece);
+        if s.x > usable.x || s.y > usable.y {
+            return Err(SheetError::TooLarge {
+                layer: piece.layer,
+                width: s.x,
+                height: s.y,
+            });
+        }
+        if sheets.is_empty() {
+            sheets.push(Sheet::default());
+        }
+        if cursor.x + s.x > width - settings.margin {
+            // next row
+            cursor = Point2::new(settings.margin, cursor.y + row_height + settings.spacing);
+            row_height = 0.;
+        }
+        if cursor.y + s.y > height - settings.margin {
+            sheets.push(Sheet::default());
+            cursor = Point2::new(settings.margin, settings.margin);
+            row_height = 0.;
+        }
+        let (min, _) = piece.bounds();
+        piece.translate(cursor - min);
+        cursor.x += s.x + settings.spacing;
+        row_height = f32::max(row_height, s.y);
+        sheets.last_mut().unwrap().pieces.push(piece);
+    }
+    return Ok(sheets);
+}
+
+fn size(piece: &Piece) -> Vector2<f32> {
+    let (min, max) = piece.bounds();
+    return max - min;
+}
+
+/// splits a layer into its connected parts, each outline together with its holes
+fn pieces(index: usize, layer: &Layer, settings: &SheetSettings) -> Vec<Piece> {
+    return islands(&layer.polygons)
+        .into_iter()
+        .filter(|polygons| polygons[0].points.len() > 2)
+        .map(|polygons| {
+            let registration = match &settings.registration {
+                Some(r) => registration_points(&polygons, r),
+                None => vec![],
+            };
+            let label = label_position(&polygons, &registration, settings.label_size);
+            Piece {
+                layer: index,
+                polygons,
+                registration,
+                label,
+            }
+        })
+        .collect();
+}
+
+/// grid points with enough material around them for a registration hole
+fn registration_points(polygons: &[Polygon], registration: &Registration) -> Vec<Vertex> {
+    let spacing = registration.spacing;
+    let clearance = registration.diameter;
+    let (min, max) = bounds(&polygons[0].points);
+    let mut points = Vec::new();
+    let mut x = (min.x / spacing).ceil() * spacing;
+    while x <= max.x {
+        let mut y = (min.y / spacing).ceil() * spacing;
+        while y <= max.y {
+            let p = Point2::new(x, y);
+            if inside(polygons, p) && polygons.iter().all(|poly| poly.distance(p) >= clearance) {
+                points.push(p);
+            }
+            y += spacing;
+        }
+        x += spacing;
+    }
+    return points;
+}
+
+/// the point inside of the piece furthest away from its contours and registration holes,
+/// found by sampling a grid
+fn label_position(polygons: &[Polygon], registration: &[Vertex], label_size: f32) -> Vertex {
+    let (min, max) = bounds(&polygons[0].points);
+    let steps = 20;
+    let mut best = (min, f32::NEG_INFINITY);
+    for i in 0..=steps {
+        for j in 0..=steps {
+            let p = Point2::new(
+                min.x + (max.x - min.x) * i as f32 / steps as f32,
+                min.y + (max.y - min.y) * j as f32 / steps as f32,
+            );
+            if !inside(polygons, p) {
+                continue;
+            }
+            let distance = polygons
+                .iter()
+                .map(|poly| poly.distance(p))
+                .chain(
+                    registration
+                        .iter()
+                        .map(|r| (*r - p).magnitude() - label_size),
+                )
+                .fold(f32::INFINITY, f32::min);
+            if distance > best.1 {
+                best = (p, distance);
+            }
+        }
+    }
+    return best.0;
+}
+
+fn bounds(points: &[Vertex]) -> (Vertex, Vertex) {
+    return points.iter().fold((points[0], points[0]), |(min, max), p| {
+        (
+            Point2::new(min.x.min(p.x), min.y.min(p.y)),
+            Point2::new(max.x.max(p.x), max.y.max(p.y)),
+        )
+    });
+}
+
+/// Draws a sheet to scale in mm. Cut lines are red hairlines,
+/// the engraved labels are black.
+pub fn sheet_document(sheet: &Sheet, settings: &SheetSettings) -> Document {
+    let (width, height) = settings.sheet_size;
+    let mut document = Document::physical((0., 0., width, height), "mm");
+    document.add(
+        Group::new()
+            .class("sheet")
+            .style(Style::line("#cccccc", 0.1))
+            .with(vec![svg::polygon(
+                &Polygon::rectangle(Point2::new(0., 0.), Point2::new(width, height)),
+                Style::new(),
+            )]),
+    );
+    for piece in &sheet.pieces {
+        let mut cut = Group::new()
+            .class("cut")
+            .attribute("data-layer", &piece.layer.to_string())
+            .style(Style::line("red", 0.1));
+        for polygon in &piece.polygons {
+            cut.add(svg::polygon(polygon, Style::new()));
+        }
+        if let Some(registration) = &settings.registration {
+            for center in &piece.registration {
+                cut.add(Element::circle(
+                    *center,
+                    registration.diameter / 2.,
+                    Style::new(),
+                ));
+            }
+        }
+        document.add(cut);
+
+        let text = piece.layer.to_string();
+        // roughly centers the text, a digit is about 0.6 times as wide as the font size
+        let offset = Vector2::new(
+            text.len() as f32 * settings.label_size * 0.3,
+            -settings.label_size * 0.35,
+        );
+        document.add(Element::text(
+            piece.label - offset,
+            settings.label_size,
+            &text,
+            Style::new().fill("black"),
+        ));
+    }
+    return document;
+}
+
+/// writes one svg file per sheet into the directory, which is created if needed.
+/// The files are named after the index of the sheet, e.g. `sheet_003.svg`
+pub fn write_sheets(sheets: &[Sheet], settings: &SheetSettings, dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)?;
+    for (i, sheet) in sheets.iter().enumerate() {
+        let mut file = BufWriter::new(File::create(dir.join(format!("sheet_{:03}.svg", i)))?);
+        writeln!(file, "<?xml version='1.0' encoding='UTF-8'?>")?;
+        sheet_document(sheet, settings).write(&mut file)?;
+        file.flush()?;
+    }
+    return Ok(());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::tests::boxes;
+
+    fn settings() -> SheetSettings {
+        return SheetSettings {
+            sheet_size: (100., 60.),
+            registration: Some(Registration {
+                diameter: 3.,
+                spacing: 10.,
+            }),
+            ..SheetSettings::default()
+        };
+    }
+
+    #[test]
+    fn pieces_fit_onto_the_sheets_without_overlapping() {
+        // two towers, each layer has two pieces of 30 x 20 mm
+        let model = boxes(&[
+            ([0., 0., 0.], [30., 12., 20.]),
+            ([40., 0., 0.], [70., 12., 20.]),
+        ]);
+        let settings = settings();
+        let layers = slice_sheets(&model, &settings, 0.01);
+        assert_eq!(layers.len(), 4);
+        let sheets = nest(&layers, &settings).unwrap();
+        // two rows of two pieces on each sheet
+        assert_eq!(sheets.len(), 2);
+
+        let mut placed: Vec<(Vertex, Vertex)> = Vec::new();
+        for sheet in &sheets {
+            let boxes: Vec<(Vertex, Vertex)> = sheet.pieces.iter().map(|p| p.bounds()).collect();
+            for (i, (min, max)) in boxes.iter().enumerate() {
+                assert!(min.x >= settings.margin - 1e-4 && min.y >= settings.margin - 1e-4);
+                assert!(max.x <= 100. - settings.margin + 1e-4);
+                assert!(max.y <= 60. - settings.margin + 1e-4);
+                for (other_min, other_max) in &boxes[..i] {
+                    let apart = min.x >= other_max.x + settings.spacing - 1e-4
+                        || other_min.x >= max.x + settings.spacing - 1e-4
+                        || min.y >= other_max.y + settings.spacing - 1e-4
+                        || other_min.y >= max.y + settings.spacing - 1e-4;
+                    assert!(apart);
+                }
+            }
+            placed.extend(boxes);
+        }
+        assert_eq!(placed.len(), 8);
+    }
+
+    #[test]
+    fn registration_holes_line_up_across_layers() {
+        let model = boxes(&[([0., 0., 0.], [30., 9., 20.])]);
+        let settings = settings();
+        let layers = slice_sheets(&model, &settings, 0.01);
+        let holes: Vec<Vec<Vertex>> = layers
+            .iter()
+            .enumerate()
+            .map(|(i, layer)| pieces(i, layer, &settings)[0].registration.clone())
+            .collect();
+        // the grid points with 3 mm of material around them
+        assert_eq!(holes[0], vec![Point2::new(10., 10.), Point2::new(20., 10.)]);
+        assert!(holes.iter().all(|h| *h == holes[0]));
+
+        // the label keeps away from the holes and the edges
+        let piece = &pieces(0, &layers[0], &settings)[0];
+        assert!(inside(&piece.polygons, piece.label));
+        assert!(piece
+            .registration
+            .iter()
+            .all(|r| (r - piece.label).magnitude() > settings.label_size));
+    }
+
+    #[test]
+    fn pieces_larger_than_a_sheet_are_reported() {
+        let model = boxes(&[([0., 0., 0.], [200., 3., 20.])]);
+        let settings = settings();
+        let layers = slice_sheets(&model, &settings, 0.01);
+        match nest(&layers, &settings) {
+            Err(SheetError::TooLarge { layer: 0, .. }) => {}
+            other => panic!("expected an error, got {:?}", other.map(|s| s.len())),
+        }
+    }
+}
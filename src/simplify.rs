@@ -0,0 +1,440 @@
+//! Removes points from polygons while staying within a maximum deviation.
+//!
+//! Slices of dense meshes have many tiny edges, which slow down printer firmware without
+//! improving the print. The simplified polygon never gets further than the tolerance away
+//! from the original, and edges are only removed if the new edge does not cross any other
+//! edge, so no self-intersections are introduced.
+
+use crate::model::Layer;
+use crate::polygons::{scalar, segments_cross, Normal, Polygon, Vertex};
+use crate::predicates::{orient2d, Orientation};
+use cgmath::*;
+use serde::Deserialize;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Simplification {
+    /// keeps the points furthest away from the simplified edges (Douglas-Peucker)
+    DouglasPeucker,
+    /// removes the points spanning the smallest triangles first (Visvalingam-Whyatt)
+    Visvalingam,
+}
+
+impl<S: BaseFloat> Polygon<S> {
+    /// Simplifies the polygon without crossing the edges of `others`, no point moves further
+    /// than `tolerance` away from it. Closed polygons keep their first point, so a seam stays
+    /// where it was placed.
+    pub fn simplify(
+        &self,
+        tolerance: S,
+        method: Simplification,
+        others: &[&Polygon<S>],
+    ) -> Polygon<S> {
+        let closed = self.points.len() > 3 && self.points.first() == self.points.last();
+        let mut points = self.points.clone();
+        if closed {
+            points.pop();
+        }
+        let edges = if closed {
+            points.len()
+        } else {
+            points.len() - 1
+        };
+        if points.len() < 4 || self.normals.len() != edges {
+            return self.clone();
+        }
+        let line = Line {
+            points: &points,
+            closed,
+            others,
+        };
+        let keep = match method {
+            Simplification::DouglasPeucker => line.douglas_peucker(tolerance),
+            Simplification::Visvalingam => line.visvalingam(tolerance),
+        };
+        return line.polygon(&keep, &self.normals, self.material);
+    }
+}
+
+/// simplifies every polygon of the layers, the polygons of a layer do not cross each other
+pub fn simplify_layers<S: BaseFloat>(
+    layers: &mut [Layer<S>],
+    tolerance: S,
+    method: Simplification,
+) {
+    for layer in layers.iter_mut() {
+        for i in 0..layer.polygons.len() {
+            let others: Vec<&Polygon<S>> = layer
+                .polygons
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, p)| p)
+                .collect();
+            let simplified = layer.polygons[i].simplify(tolerance, method, &others);
+            layer.polygons[i] = simplified;
+        }
+    }
+}
+
+/// the points of a polygon without the repeated last point for closed ones
+struct Line<'a, S> {
+    points: &'a [Vertex<S>],
+    closed: bool,
+    others: &'a [&'a Polygon<S>],
+}
+
+impl<'a, S: BaseFloat> Line<'a, S> {
+    fn len(&self) -> usize {
+        return self.points.len();
+    }
+
+    /// the point after `i`, wrapping around for closed polygons
+    fn point(&self, i: usize) -> Vertex<S> {
+        return self.points[i % self.len()];
+    }
+
+    /// the largest distance of the original points between `a` and `b` from the edge `a`-`b`,
+    /// together with the index of that point
+    fn deviation(&self, a: usize, b: usize) -> (S, Option<usize>) {
+        let end = if b <= a { b + self.len() } else { b };
+        let (pa, pb) = (self.point(a), self.point(end));
+        let mut result = (S::zero(), None);
+        for i in a + 1..end {
+            let d = segment_distance(self.point(i), pa, pb);
+            if d > result.0 {
+                result = (d, Some(i % self.len()));
+            }
+        }
+        return result;
+    }
+
+    /// true if the edge `a`-`b` crosses one of the kept edges or an edge of another polygon
+    fn crosses(&self, a: usize, b: usize, keep: &[bool]) -> bool {
+        let (pa, pb) = (self.point(a), self.point(b));
+        let kept: Vec<usize> = (0..self.len()).filter(|i| keep[*i]).collect();
+        let count = if self.closed {
+            kept.len()
+        } else {
+            kept.len() - 1
+        };
+        for k in 0..count {
+            let (c, d) = (self.point(kept[k]), self.point(kept[(k + 1) % kept.len()]));
+            if segments_cross(pa, pb, c, d) {
+                return true;
+            }
+        }
+        return self
+            .others
+            .iter()
+            .flat_map(|p| p.edges())
+            .any(|(c, d)| segments_cross(pa, pb, c, d));
+    }
+
+    /// the kept edges as pairs of point indices
+    fn edges(&self, keep: &[bool]) -> Vec<(usize, usize)> {
+        let kept: Vec<usize> = (0..self.len()).filter(|i| keep[*i]).collect();
+        let mut edges: Vec<(usize, usize)> = kept.windows(2).map(|w| (w[0], w[1])).collect();
+        if self.closed {
+            edges.push((kept[kept.len() - 1], kept[0]));
+        }
+        return edges;
+    }
+
+    fn douglas_peucker(&self, tolerance: S) -> Vec<bool> {
+        let n = self.len();
+        let mut keep = vec![false; n];
+        keep[0] = true;
+        keep[n - 1] = true;
+        if self.closed {
+            // a closed polygon is split at its first point and the point furthest from it
+            let far = (1..n)
+                .max_by(|a, b| {
+                    let da = (self.points[*a] - self.points[0]).magnitude2();
+                    let db = (self.points[*b] - self.points[0]).magnitude2();
+                    da.partial_cmp(&db).unwrap()
+                })
+                .unwrap();
+            keep[n - 1] = false;
+            keep[far] = true;
+            // a third point keeps the polygon from collapsing into a line
+            if let (_, Some(third)) = self.deviation(0, far) {
+                keep[third] = true;
+            } else if let (_, Some(third)) = self.deviation(far, 0) {
+                keep[third] = true;
+            }
+        }
+        let mut stack = self.edges(&keep);
+        while let Some((a, b)) = stack.pop() {
+            if let (d, Some(i)) = self.deviation(a, b) {
+                if d > tolerance {
+                    keep[i] = true;
+                    stack.push((a, i));
+                    stack.push((i, b));
+                }
+            }
+        }
+
+        // edges crossing each other are split until they do not cross anymore
+        loop {
+            let crossing: Vec<(usize, usize)> = self
+                .edges(&keep)
+                .into_iter()
+                .filter(|(a, b)| self.crosses(*a, *b, &keep))
+                .collect();
+            let mut changed = false;
+            for (a, b) in crossing {
+                if let (_, Some(i)) = self.deviation(a, b) {
+                    keep[i] = true;
+                    changed = true;
+                }
+            }
+            if !changed {
+                return keep;
+            }
+        }
+    }
+
+    fn visvalingam(&self, tolerance: S) -> Vec<bool> {
+        let n = self.len();
+        let mut keep = vec![true; n];
+        // neighbours of every kept point
+        let mut previous: Vec<usize> = (0..n).map(|i| (i + n - 1) % n).collect();
+        let mut next: Vec<usize> = (0..n).map(|i| (i + 1) % n).collect();
+        // the area of a point changes when a neighbour is removed, older entries are skipped
+        let mut version = vec![0; n];
+        let removable = |i: usize| i != 0 && (self.closed || i != n - 1);
+        // collinear points have no area at all, even if rounding says otherwise
+        let area = |a: Vertex<S>, b: Vertex<S>, c: Vertex<S>| match orient2d(a, b, c) {
+            Orientation::Collinear => S::zero(),
+            _ => (b - a).perp_dot(c - a).abs() / scalar(2.),
+        };
+
+        let mut queue = BinaryHeap::new();
+        for i in (0..n).filter(|i| removable(*i)) {
+            queue.push(Candidate {
+                area: area(self.point(previous[i]), self.points[i], self.point(next[i])),
+                index: i,
+                version: 0,
+            });
+        }
+        let mut remaining = n;
+        while let Some(candidate) = queue.pop() {
+            let i = candidate.index;
+            if !keep[i] || candidate.version != version[i] {
+                continue;
+            }
+            // closed polygons need at least three points
+            if self.closed && remaining <= 3 {
+                break;
+            }
+            let (a, b) = (previous[i], next[i]);
+            if self.deviation(a, b).0 > tolerance {
+                continue;
+            }
+            keep[i] = false;
+            if self.crosses(a, b, &keep) {
+                keep[i] = true;
+                continue;
+            }
+            remaining -= 1;
+            next[a] = b;
+            previous[b] = a;
+            for j in [a, b].iter().filter(|j| removable(**j)) {
+                version[*j] += 1;
+                queue.push(Candidate {
+                    area: area(
+                        self.point(previous[*j]),
+                        self.points[*j],
+                        self.point(next[*j]),
+                    ),
+                    index: *j,
+                    version: version[*j],
+                });
+            }
+        }
+        return keep;
+    }
+
+    /// The polygon made of the kept points. The normal of a new edge is perpendicular to it
+    /// and points to the side the normals of the edges it replaces point to on average.
+    fn polygon(&self, keep: &[bool], normals: &[Normal<S>], material: usize) -> Polygon<S> {
+        let mut points: Vec<Vertex<S>> = Vec::new();
+        let mut merged: Vec<Normal<S>> = Vec::new();
+        for (a, b) in self.edges(keep) {
+            let end = if b <= a { b + self.len() } else { b };
+            let mut average = Vector2::zero();
+            for i in a..end {
+                let length = (self.point(i + 1) - self.point(i)).magnitude();
+                average += normals[i % self.len()] * length;
+            }
+            let direction = self.point(end) - self.point(a);
+            let normal = if direction.magnitude2() == S::zero() || average.magnitude2() == S::zero()
+            {
+                normals[a]
+            } else {
+                let perpendicular = Vector2::new(direction.y, -direction.x).normalize();
+                if perpendicular.dot(average) < S::zero() {
+                    -perpendicular
+                } else {
+                    perpendicular
+                }
+            };
+            points.push(self.points[a]);
+            merged.push(normal);
+        }
+        if self.closed {
+            points.push(points[0]);
+        } else {
+            points.push(self.points[self.len() - 1]);
+        }
+        return Polygon {
+            points,
+            normals: merged,
+            material,
+        };
+    }
+}
+
+/// distance of `p` from the segment `a`-`b`
+fn segment_distance<S: BaseFloat>(p: Vertex<S>, a: Vertex<S>, b: Vertex<S>) -> S {
+    let ab = b - a;
+    let length = ab.magnitude2();
+    if length == S::zero() {
+        return (p - a).magnitude();
+    }
+    let t = ((p - a).dot(ab) / length).max(S::zero()).min(S::one());
+    return (p - (a + ab * t)).magnitude();
+}
+
+/// an entry of the Visvalingam queue, the smallest area comes first
+struct Candidate<S> {
+    area: S,
+    index: usize,
+    version: usize,
+}
+
+impl<S: BaseFloat> PartialEq for Candidate<S> {
+    fn eq(&self, other: &Self) -> bool {
+        return self.area == other.area;
+    }
+}
+
+impl<S: BaseFloat> Eq for Candidate<S> {}
+
+impl<S: BaseFloat> PartialOrd for Candidate<S> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl<S: BaseFloat> Ord for Candidate<S> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        return other
+            .area
+            .partial_cmp(&self.area)
+            .unwrap_or(Ordering::Equal);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHODS: [Simplification; 2] =
+        [Simplification::DouglasPeucker, Simplification::Visvalingam];
+
+    /// a closed counter clockwise polygon with outward normals
+    fn polygon(corners: &[(f32, f32)]) -> Polygon {
+        let mut points: Vec<Vertex> = corners.iter().map(|(x, y)| Point2::new(*x, *y)).collect();
+        points.push(points[0]);
+        let normals = points
+            .windows(2)
+            .map(|e| Vector2::new(e[1].y - e[0].y, e[0].x - e[1].x).normalize())
+            .collect();
+        return Polygon {
+            points,
+            normals,
+            material: 0,
+        };
+    }
+
+    #[test]
+    fn points_stay_within_the_tolerance() {
+        let circle = Polygon::circle(Point2::new(0., 0.), 10., 200);
+        for method in METHODS.iter() {
+            for tolerance in [0.01, 0.1, 1.].iter() {
+                let simplified = circle.simplify(*tolerance, *method, &[]);
+                assert!(simplified.points.len() < circle.points.len());
+                assert_eq!(simplified.points[0], circle.points[0]);
+                assert_eq!(simplified.points.first(), simplified.points.last());
+                assert_eq!(simplified.normals.len(), simplified.points.len() - 1);
+                for p in &circle.points {
+                    assert!(simplified.distance(*p) <= tolerance + 1e-4);
+                }
+                // the normals still point away from the center
+                for ((a, b), n) in simplified.edges().zip(&simplified.normals) {
+                    assert!((a + (b - a) * 0.5).to_vec().dot(*n) > 0.);
+                }
+            }
+            // a coarser tolerance keeps fewer points
+            let fine = circle.simplify(0.01, *method, &[]).points.len();
+            let coarse = circle.simplify(1., *method, &[]).points.len();
+            assert!(coarse < fine);
+        }
+    }
+
+    #[test]
+    fn collinear_points_are_removed() {
+        let square = polygon(&[
+            (0., 0.),
+            (5., 0.),
+            (10., 0.),
+            (10., 10.),
+            (0., 10.),
+            (0., 5.),
+        ]);
+        for method in METHODS.iter() {
+            let simplified = square.simplify(0., *method, &[]);
+            let expected = [(0., 0.), (10., 0.), (10., 10.), (0., 10.), (0., 0.)];
+            assert_eq!(simplified.points, expected.map(|(x, y)| Point2::new(x, y)));
+        }
+    }
+
+    #[test]
+    fn other_polygons_are_not_crossed() {
+        // a square with a notch from the top and a bar standing in the notch
+        let notched = polygon(&[
+            (0., 0.),
+            (10., 0.),
+            (10., 10.),
+            (6., 10.),
+            (5., 2.),
+            (4., 10.),
+            (0., 10.),
+        ]);
+        let bar = polygon(&[(4.9, 3.), (5.1, 3.), (5.1, 12.), (4.9, 12.)]);
+        let mut layers = vec![Layer {
+            height: 0.1,
+            thickness: 0.2,
+            polygons: vec![notched, bar.clone()],
+        }];
+        for method in METHODS.iter() {
+            // alone the notch is within the tolerance
+            assert!(
+                layers[0].polygons[0]
+                    .simplify(20., *method, &[])
+                    .points
+                    .len()
+                    < 8
+            );
+            simplify_layers(&mut layers, 20., *method);
+            let simplified = &layers[0].polygons[0];
+            for (a, b) in simplified.edges() {
+                assert!(bar.edges().all(|(c, d)| !segments_cross(a, b, c, d)));
+            }
+        }
+    }
+}
@@ -0,0 +1,401 @@
+use crate::intersect::{clean, difference, union};
+use crate::model::{Layer, Model, Slice};
+use crate::polygons::{offset_area, scanline, FillRule, Polygon};
+use crate::profile::{Profile, SupportKind};
+use cgmath::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SupportPattern {
+    /// parallel lines in x direction
+    Lines,
+    /// lines in x and z direction
+    Grid,
+}
+
+#[derive(Debug, Clone)]
+pub struct SupportSettings {
+    /// overhangs steeper than this angle (degrees, measured from the vertical) are supported
+    pub angle: f32,
+    /// distance between two support lines
+    pub spacing: f32,
+    /// width of a single support line
+    pub line_width: f32,
+    /// horizontal distance between support and model
+    pub xy_gap: f32,
+    /// vertical distance between support and model
+    pub z_gap: f32,
+    pub pattern: SupportPattern,
+}
+
+impl Default for SupportSettings {
+    fn default() -> Self {
+        return SupportSettings {
+            angle: 45.,
+            spacing: 2.,
+            line_width: 0.4,
+            xy_gap: 0.7,
+            z_gap: 0.2,
+            pattern: SupportPattern::Lines,
+        };
+    }
+}
+
+impl SupportSettings {
+    /// takes the settings from a profile, the lines are as wide as the nozzle
+    pub fn from_profile(profile: &Profile) -> SupportSettings {
+        let support = &profile.support;
+        return SupportSettings {
+            angle: support.angle,
+            spacing: support.spacing,
+            line_width: profile.printer.nozzle_diameter,
+            xy_gap: support.xy_gap,
+            z_gap: support.z_gap,
+            pattern: match support.kind {
+                SupportKind::Grid => SupportPattern::Grid,
+                _ => SupportPattern::Lines,
+            },
+        };
+    }
+}
+
+/// Generates the support regions for every layer of the model.
+/// The layers have to be sorted by height, the returned slices have the same order.
+///
+/// Supports are computed on a grid of columns. A column starts below every point where
+/// the model has an overhanging face (or, where no face is found, where a layer sticks out
+/// further than the layer below can carry) and continues down until it reaches the bed
+/// or the model. The lines connecting the columns are cut back where they come closer to
+/// the model than the gaps allow.
+pub fn generate_supports(
+    model: &Model,
+    layers: &[Layer],
+    settings: &SupportSettings,
+) -> Vec<Slice> {
+    if layers.is_empty() {
+        return vec![];
+    }
+    let grid = Grid::new(model, settings.spacing);
+    let overhang_faces = overhangs(model, settings.angle);
+    let layer_height = if layers.len() > 1 {
+        layers[1].height - layers[0].height
+    } else {
+        model.bounds().1.y - model.bounds().0.y
+    };
+    // an overhang is self supporting if it sticks out less than this distance
+    let max_overhang = layer_height * settings.angle.to_radians().tan();
+
+    // offsets of the rows used to check the xy gap around a point
+    let gap_rows: Vec<f32> = [-1., -0.5, 0., 0.5, 1.]
+        .iter()
+        .map(|f| f * settings.xy_gap)
+        .collect();
+
+    // crossings of each grid row with the layer's contours, for every gap row offset
+    let crossings: Vec<Vec<Vec<Vec<f32>>>> = layers
+        .iter()
+        .map(|layer| {
+            (0..grid.rows)
+                .map(|r| {
+                    gap_rows
+                        .iter()
+                        .map(|dz| scanline(&layer.polygons, grid.z(r) + dz))
+                        .collect()
+                })
+                .collect()
+        })
+        .collect();
+    let center = gap_rows.len() / 2;
+
+    // computes for a single grid point in which layers it is part of the support
+    let column = |r: usize, c: usize| -> Vec<bool> {
+        let point = Point2::new(grid.x(c), grid.z(r));
+        let footprint: Vec<(f32, bool)> = overhang_faces
+            .iter()
+            .filter_map(|face| face.height_at(point))
+            .collect();
+
+        let mut active = vec![false; layers.len()];
+        // height of the overhang the column currently supports, we move top down
+        let mut contact: Option<f32> = None;
+        for l in (0..layers.len()).rev() {
+            let height = layers[l].height;
+            let rows = &crossings[l][r];
+            if is_inside(&rows[center], point.x) {
+                // the model is here, a column above ends on it
+                contact = None;
+                if l == 0 || is_inside(&crossings[l - 1][r][center], point.x) {
+                    continue;
+                }
+                // lowest point of the model at this position, it needs support if there is
+                // an overhanging face or the layer below is too far away
+                let bottom = height - layer_height / 2.;
+                let face = footprint
+                    .iter()
+                    .filter(|(y, _)| (*y - bottom).abs() <= layer_height)
+                    .min_by(|a, b| {
+                        (a.0 - bottom)
+                            .abs()
+                            .partial_cmp(&(b.0 - bottom).abs())
+                            .unwrap()
+                    });
+                let needs_support = match face {
+                    Some((_, overhanging)) => *overhanging,
+                    None => distance_in_row(&crossings[l - 1][r][center], point.x) > max_overhang,
+                };
+                if needs_support {
+                    contact = Some(face.map(|(y, _)| *y).unwrap_or(bottom));
+                }
+            } else if let Some(top) = contact {
+                let below_gap = top - (height + layer_height / 2.) >= settings.z_gap - 1e-5;
+                // the closest crossing in a row at distance dz has to be at least
+                // sqrt(gap² - dz²) away to keep the gap
+                let free = rows.iter().zip(&gap_rows).all(|(row, dz)| {
+                    let min_distance = (settings.xy_gap.powi(2) - dz.powi(2)).max(0.).sqrt();
+                    !is_inside(row, point.x) && distance_in_row(row, point.x) >= min_distance
+                });
+                active[l] = below_gap && free;
+            }
+        }
+        // a column standing on the model keeps the gap to the surface below it as well
+        let mut surface: Option<f32> = None;
+        for (l, layer) in layers.iter().enumerate() {
+            if is_inside(&crossings[l][r][center], point.x) {
+                surface = Some(layer.height + layer_height / 2.);
+            } else if let Some(top) = surface {
+                let above_gap = layer.height - layer_height / 2. - top >= settings.z_gap - 1e-5;
+                active[l] = active[l] && above_gap;
+            }
+        }
+        return active;
+    };
+
+    // activity of every grid point, indexed by row, column and layer
+    let columns: Vec<Vec<Vec<bool>>> = (0..grid.rows)
+        .map(|r| (0..grid.columns).map(|c| column(r, c)).collect())
+        .collect();
+
+    // the model grown by the xy gap, the lines between the grid points have to stay out of it
+    let grown: Vec<Slice> = layers
+        .iter()
+        .map(|layer| {
+            clean(
+                &offset_area(&layer.polygons, settings.xy_gap),
+                FillRule::EvenOdd,
+            )
+        })
+        .collect();
+    // layers closer than the z gap are kept clear as well
+    let k = (settings.z_gap / layer_height - 1e-5).ceil().max(0.) as usize;
+
+    return (0..layers.len())
+        .map(|l| {
+            let active: Vec<Vec<bool>> = columns
+                .iter()
+                .map(|row| row.iter().map(|column| column[l]).collect())
+                .collect();
+            let regions = grid.regions(&active, settings);
+            if regions.is_empty() {
+                return regions;
+            }
+            let near = l.saturating_sub(k)..(l + k + 1).min(layers.len());
+            let clearance: Slice = grown[near].iter().flatten().cloned().collect();
+            return difference(&regions, &clean(&clearance, FillRule::Positive));
+        })
+        .collect();
+}
+
+/// a downwards facing triangle projected onto the xz plane
+struct OverhangFace {
+    corners: [Point3<f32>; 3],
+    /// true if the face is steeper than the support angle
+    overhanging: bool,
+}
+
+impl OverhangFace {
+    /// height of the face above a point of the xz plane and whether it needs support
+    fn height_at(&self, point: Point2<f32>) -> Option<(f32, bool)> {
+        let [a, b, c] = self.corners;
+        let det = (b.z - c.z) * (a.x - c.x) + (c.x - b.x) * (a.z - c.z);
+        if det.abs() < 1e-9 {
+            return None;
+        }
+        let l1 = ((b.z - c.z) * (point.x - c.x) + (c.x - b.x) * (point.y - c.z)) / det;
+        let l2 = ((c.z - a.z) * (point.x - c.x) + (a.x - c.x) * (point.y - c.z)) / det;
+        let l3 = 1. - l1 - l2;
+        if l1 < 0. || l2 < 0. || l3 < 0. {
+            return None;
+        }
+        return Some((l1 * a.y + l2 * b.y + l3 * c.y, self.overhanging));
+    }
+}
+
+/// collects all faces pointing downwards
+fn overhangs(model: &Model, angle: f32) -> Vec<OverhangFace> {
+    // a face pointing straight down has an overhang of 90 degrees
+    let limit = angle.to_radians().sin();
+    return model
+        .triangles()
+        .filter(|(_, n)| n.y < 0.)
+        .map(|(corners, n)| OverhangFace {
+            corners,
+            overhanging: -n.y > limit,
+        })
+        .collect();
+}
+
+/// checks if x lies inside of the sorted crossings of a scanline
+fn is_inside(crossings: &[f32], x: f32) -> bool {
+    return crossings.iter().filter(|c| **c < x).count() % 2 == 1;
+}
+
+/// distance to the closest crossing of a scanline
+fn distance_in_row(crossings: &[f32], x: f32) -> f32 {
+    return crossings
+        .iter()
+        .map(|c| (c - x).abs())
+        .fold(f32::INFINITY, f32::min);
+}
+
+/// regular grid of support columns covering the model
+struct Grid {
+    origin: Point2<f32>,
+    spacing: f32,
+    columns: usize,
+    rows: usize,
+}
+
+impl Grid {
+    fn new(model: &Model, spacing: f32) -> Grid {
+        let (min, max) = model.bounds();
+        return Grid {
+            origin: Point2::new(min.x, min.z),
+            spacing,
+            columns: ((max.x - min.x) / spacing).ceil() as usize + 1,
+            rows: ((max.z - min.z) / spacing).ceil() as usize + 1,
+        };
+    }
+
+    fn x(&self, column: usize) -> f32 {
+        return self.origin.x + column as f32 * self.spacing;
+    }
+
+    fn z(&self, row: usize) -> f32 {
+        return self.origin.y + row as f32 * self.spacing;
+    }
+
+    /// turns runs of active grid points into rectangles of the support line width
+    fn regions(&self, active: &[Vec<bool>], settings: &SupportSettings) -> Slice {
+        let w = settings.line_width / 2.;
+        let mut regions: Slice = Vec::new();
+        let mut columns: Slice = Vec::new();
+        for (r, row) in active.iter().enumerate() {
+            for (start, end) in runs(row) {
+                regions.push(Polygon::rectangle(
+                    Point2::new(self.x(start) - w, self.z(r) - w),
+                    Point2::new(self.x(end) + w, self.z(r) + w),
+                ));
+            }
+        }
+        if settings.pattern == SupportPattern::Grid {
+            for c in 0..self.columns {
+                let column: Vec<bool> = active.iter().map(|row| row[c]).collect();
+                for (start, end) in runs(&column) {
+                    columns.push(Polygon::rectangle(
+                        Point2::new(self.x(c) - w, self.z(start) - w),
+                        Point2::new(self.x(c) + w, self.z(end) + w),
+                    ));
+                }
+            }
+            // rows and columns cross, they are merged so the crossings stay filled
+            regions = union(&regions, &columns);
+        }
+        return regions;
+    }
+}
+
+/// start and end index of every sequence of `true` values
+fn runs(values: &[bool]) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut start: Option<usize> = None;
+    for (i, v) in values.iter().enumerate() {
+        match (start, v) {
+            (None, true) => start = Some(i),
+            (Some(s), false) => {
+                runs.push((s, i - 1));
+                start = None;
+            }
+            _ => (),
+        }
+    }
+    if let Some(s) = start {
+        runs.push((s, values.len() - 1));
+    }
+    return runs;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::tests::boxes;
+
+    /// a slab from 5 to 7 mm standing on a thin pillar in its center
+    fn table() -> Model {
+        return boxes(&[([0., 5., 0.], [10., 7., 10.]), ([4., 0., 4.], [6., 5., 6.])]);
+    }
+
+    fn area(slice: &Slice) -> f32 {
+        return slice.iter().map(|p| p.area()).sum::<f32>().abs();
+    }
+
+    #[test]
+    fn nothing_below_a_box_on_the_bed() {
+        let model = boxes(&[([0., 0., 0.], [10., 2., 10.])]);
+        let layers = model.layers(0.2, 0.01);
+        let supports = generate_supports(&model, &layers, &SupportSettings::default());
+        assert_eq!(supports.len(), layers.len());
+        assert!(supports.iter().all(|s| s.is_empty()));
+    }
+
+    #[test]
+    fn support_below_a_table_keeps_the_gaps() {
+        let model = table();
+        let layers = model.layers(0.2, 0.01);
+        let settings = SupportSettings::default();
+        let supports = generate_supports(&model, &layers, &settings);
+        let pillar = Polygon::rectangle(Point2::new(4., 4.), Point2::new(6., 6.));
+        for (layer, support) in layers.iter().zip(&supports) {
+            let top = layer.height + 0.1;
+            if layer.height > 5. {
+                assert!(support.is_empty(), "support inside the slab");
+            } else if top <= 5. - settings.z_gap + 1e-4 {
+                assert!(area(support) > 10., "no support at {}", layer.height);
+            } else {
+                assert!(support.is_empty(), "support within the z gap");
+            }
+            for point in support.iter().flat_map(|p| p.points.iter()) {
+                assert!(!pillar.contains(*point));
+                assert!(pillar.distance(*point) >= settings.xy_gap - 1e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn grid_connects_the_lines() {
+        let model = table();
+        let layers = model.layers(0.2, 0.01);
+        let lines = generate_supports(&model, &layers, &SupportSettings::default());
+        let settings = SupportSettings {
+            pattern: SupportPattern::Grid,
+            ..SupportSettings::default()
+        };
+        let grid = generate_supports(&model, &layers, &settings);
+        // the grid adds the lines in z direction, the crossings are only counted once
+        let (lines, grid) = (area(&lines[5]), area(&grid[5]));
+        assert!(
+            grid > 1.5 * lines && grid < 2. * lines,
+            "{} {}",
+            lines,
+            grid
+        );
+    }
+}
@@ -0,0 +1,514 @@
+use crate::polygons::{Polygon, Vertex};
+use std::io::{self, Write};
+
+/// presentation attributes of an element, unset values are inherited from the parent
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Style {
+    pub stroke: Option<String>,
+    pub stroke_width: Option<f32>,
+    pub stroke_opacity: Option<f32>,
+    pub fill: Option<String>,
+    /// lengths of the dashes and gaps of the stroke
+    pub dash: Option<Vec<f32>>,
+    /// rounds the ends and corners of lines
+    pub round: bool,
+}
+
+impl Style {
+    pub fn new() -> Style {
+        return Style::default();
+    }
+
+    /// a line without fill
+    pub fn line(color: &str, width: f32) -> Style {
+        return Style::new().stroke(color).stroke_width(width).fill("none");
+    }
+
+    pub fn stroke(mut self, color: &str) -> Style {
+        self.stroke = Some(color.to_string());
+        return self;
+    }
+
+    pub fn stroke_width(mut self, width: f32) -> Style {
+        self.stroke_width = Some(width);
+        return self;
+    }
+
+    pub fn stroke_opacity(mut self, opacity: f32) -> Style {
+        self.stroke_opacity = Some(opacity);
+        return self;
+    }
+
+    pub fn fill(mut self, color: &str) -> Style {
+        self.fill = Some(color.to_string());
+        return self;
+    }
+
+    pub fn dash(mut self, pattern: &[f32]) -> Style {
+        self.dash = Some(pattern.to_vec());
+        return self;
+    }
+
+    pub fn round(mut self) -> Style {
+        self.round = true;
+        return self;
+    }
+
+    fn attributes(&self) -> Vec<(&'static str, String)> {
+        let mut attributes = Vec::new();
+        if let Some(stroke) = &self.stroke {
+            attributes.push(("stroke", stroke.clone()));
+        }
+        if let Some(width) = self.stroke_width {
+            attributes.push(("stroke-width", width.to_string()));
+        }
+        if let Some(opacity) = self.stroke_opacity {
+            attributes.push(("stroke-opacity", opacity.to_string()));
+        }
+        if let Some(fill) = &self.fill {
+            attributes.push(("fill", fill.clone()));
+        }
+        if let Some(dash) = &self.dash {
+            let pattern: Vec<String> = dash.iter().map(|d| d.to_string()).collect();
+            attributes.push(("stroke-dasharray", pattern.join(" ")));
+        }
+        if self.round {
+            attributes.push(("stroke-linecap", "round".to_string()));
+            attributes.push(("stroke-linejoin", "round".to_string()));
+        }
+        return attributes;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Element {
+    /// a closed outline
+    Polygon {
+        points: Vec<Vertex>,
+        style: Style,
+    },
+    /// an open line through all points
+    Polyline {
+        points: Vec<Vertex>,
+        style: Style,
+    },
+    Line {
+        from: Vertex,
+        to: Vertex,
+        style: Style,
+    },
+    Circle {
+        center: Vertex,
+        radius: f32,
+        style: Style,
+    },
+    /// text with its baseline starting at the position
+    Text {
+        position: Vertex,
+        size: f32,
+        text: String,
+        style: Style,
+    },
+    Group(Group),
+}
+
+impl Element {
+    pub fn polygon(points: &[Vertex], style: Style) -> Element {
+        return Element::Polygon {
+            points: points.to_vec(),
+            style,
+        };
+    }
+
+    pub fn polyline(points: &[Vertex], style: Style) -> Element {
+        return Element::Polyline {
+            points: points.to_vec(),
+            style,
+        };
+    }
+
+    pub fn line(from: Vertex, to: Vertex, style: Style) -> Element {
+        return Element::Line { from, to, style };
+    }
+
+    pub fn circle(center: Vertex, radius: f32, style: Style) -> Element {
+        return Element::Circle {
+            center,
+            radius,
+            style,
+        };
+    }
+
+    pub fn text(position: Vertex, size: f32, text: &str, style: Style) -> Element {
+        return Element::Text {
+            position,
+            size,
+            text: text.to_string(),
+            style,
+        };
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        match self {
+            Element::Polygon { points, style } => {
+                write!(w, "<polygon points='{}'", format_points(points))?;
+                write_style(w, style)?;
+                writeln!(w, "/>")?;
+            }
+            Element::Polyline { points, style } => {
+                write!(w, "<polyline points='{}'", format_points(points))?;
+                write_style(w, style)?;
+                writeln!(w, "/>")?;
+            }
+            Element::Line { from, to, style } => {
+                write!(
+                    w,
+                    "<line x1='{}' y1='{}' x2='{}' y2='{}'",
+                    from.x, from.y, to.x, to.y
+                )?;
+                write_style(w, style)?;
+                writeln!(w, "/>")?;
+            }
+            Element::Circle {
+                center,
+                radius,
+                style,
+            } => {
+                write!(
+                    w,
+                    "<circle cx='{}' cy='{}' r='{}'",
+                    center.x, center.y, radius
+                )?;
+                write_style(w, style)?;
+                writeln!(w, "/>")?;
+            }
+            Element::Text {
+                position,
+                size,
+                text,
+                style,
+            } => {
+                write!(
+                    w,
+                    "<text x='{}' y='{}' font-size='{}'",
+                    position.x, position.y, size
+                )?;
+                write_style(w, style)?;
+                writeln!(w, ">{}</text>", escape(text))?;
+            }
+            Element::Group(group) => group.write(w)?,
+        }
+        return Ok(());
+    }
+}
+
+/// a group of elements sharing a style, also used for the layers of a drawing
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Group {
+    pub id: Option<String>,
+    pub class: Option<String>,
+    /// additional attributes, e.g. `data-*` attributes used by scripts
+    pub attributes: Vec<(String, String)>,
+    pub style: Style,
+    pub children: Vec<Element>,
+}
+
+impl Group {
+    pub fn new() -> Group {
+        return Group::default();
+    }
+
+    pub fn id(mut self, id: &str) -> Group {
+        self.id = Some(id.to_string());
+        return self;
+    }
+
+    pub fn class(mut self, class: &str) -> Group {
+        self.class = Some(class.to_string());
+        return self;
+    }
+
+    pub fn attribute(mut self, name: &str, value: &str) -> Group {
+        self.attributes.push((name.to_string(), value.to_string()));
+        return self;
+    }
+
+    pub fn style(mut self, style: Style) -> Group {
+        self.style = style;
+        return self;
+    }
+
+    pub fn add(&mut self, element: Element) {
+        self.children.push(element);
+    }
+
+    pub fn with<I: IntoIterator<Item = Element>>(mut self, elements: I) -> Group {
+        self.children.extend(elements);
+        return self;
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        write!(w, "<g")?;
+        if let Some(id) = &self.id {
+            write!(w, " id='{}'", escape(id))?;
+        }
+        if let Some(class) = &self.class {
+            write!(w, " class='{}'", escape(class))?;
+        }
+        for (name, value) in &self.attributes {
+            write!(w, " {}='{}'", name, escape(value))?;
+        }
+        write_style(w, &self.style)?;
+        writeln!(w, ">")?;
+        for child in &self.children {
+            child.write(w)?;
+        }
+        writeln!(w, "</g>")?;
+        return Ok(());
+    }
+}
+
+impl From<Group> for Element {
+    fn from(group: Group) -> Element {
+        return Element::Group(group);
+    }
+}
+
+/// an svg drawing, the coordinates of the elements are the ones of the view box
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    pub id: Option<String>,
+    /// min x, min y, width and height of the visible area
+    pub view_box: (f32, f32, f32, f32),
+    /// size of the drawing, in pixels unless a unit is given
+    pub width: f32,
+    pub height: f32,
+    /// unit of the size, e.g. `mm`
+    pub unit: Option<String>,
+    pub children: Vec<Element>,
+}
+
+impl Document {
+    pub fn new(view_box: (f32, f32, f32, f32), width: f32, height: f32) -> Document {
+        return Document {
+            id: None,
+            view_box,
+            width,
+            height,
+            unit: None,
+            children: Vec::new(),
+        };
+    }
+
+    /// a document showing the area around the points with the given margin
+    pub fn fit(points: &[Vertex], margin: f32, size: f32) -> Document {
+        let (mut min_x, mut min_y) = (f32::INFINITY, f32::INFINITY);
+        let (mut max_x, mut max_y) = (f32::NEG_INFINITY, f32::NEG_INFINITY);
+        for p in points {
+            min_x = min_x.min(p.x);
+            min_y = min_y.min(p.y);
+            max_x = max_x.max(p.x);
+            max_y = max_y.max(p.y);
+        }
+        if points.is_empty() {
+            return Document::new((-margin, -margin, 2. * margin, 2. * margin), size, size);
+        }
+        // a square view box, so nothing gets distorted
+        let extent = (max_x - min_x).max(max_y - min_y) + 2. * margin;
+        let (center_x, center_y) = ((min_x + max_x) / 2., (min_y + max_y) / 2.);
+        return Document::new(
+            (
+                center_x - extent / 2.,
+                center_y - extent / 2.,
+                extent,
+                extent,
+            ),
+            size,
+            size,
+        );
+    }
+
+    /// a document whose size matches the view box in the given unit, so it prints to scale
+    pub fn physical(view_box: (f32, f32, f32, f32), unit: &str) -> Document {
+        let mut document = Document::new(view_box, view_box.2, view_box.3);
+        document.unit = Some(unit.to_string());
+        return document;
+    }
+
+    pub fn id(mut self, id: &str) -> Document {
+        self.id = Some(id.to_string());
+        return self;
+    }
+
+    pub fn add<E: Into<Element>>(&mut self, element: E) {
+        self.children.push(element.into());
+    }
+
+    pub fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        let (x, y, width, height) = self.view_box;
+        write!(w, "<svg xmlns='http://www.w3.org/2000/svg'")?;
+        if let Some(id) = &self.id {
+            write!(w, " id='{}'", escape(id))?;
+        }
+        let unit = self.unit.as_deref().unwrap_or("");
+        writeln!(
+            w,
+            " viewBox='{} {} {} {}' width='{}{unit}' height='{}{unit}'>",
+            x,
+            y,
+            width,
+            height,
+            self.width,
+            self.height,
+            unit = escape(unit)
+        )?;
+        for child in &self.children {
+            child.write(w)?;
+        }
+        writeln!(w, "</svg>")?;
+        return Ok(());
+    }
+}
+
+/// additional content of an html page around a drawing, the strings are written as they are
+#[derive(Debug, Clone, Default)]
+pub struct Page<'a> {
+    pub title: &'a str,
+    pub style: &'a str,
+    /// html placed below the drawing
+    pub controls: &'a str,
+    pub script: &'a str,
+}
+
+/// writes an html page containing the drawing
+pub fn write_page<W: Write>(w: &mut W, document: &Document, page: &Page) -> io::Result<()> {
+    writeln!(w, "<!DOCTYPE html>")?;
+    writeln!(w, "<html>")?;
+    writeln!(w, "<head>")?;
+    writeln!(w, "<meta charset='utf-8'>")?;
+    writeln!(w, "<title>{}</title>", escape(page.title))?;
+    if !page.style.is_empty() {
+        writeln!(w, "<style>{}</style>", page.style)?;
+    }
+    writeln!(w, "</head>")?;
+    writeln!(w, "<body>")?;
+    document.write(w)?;
+    w.write_all(page.controls.as_bytes())?;
+    if !page.script.is_empty() {
+        writeln!(w, "<script>{}</script>", page.script)?;
+    }
+    writeln!(w, "</body>")?;
+    writeln!(w, "</html>")?;
+    return Ok(());
+}
+
+/// the outline of a polygon
+pub fn polygon(polygon: &Polygon, style: Style) -> Element {
+    return Element::polygon(&polygon.points, style);
+}
+
+/// the normals of a polygon, drawn from the center of their edges
+pub fn normals(polygon: &Polygon, length: f32, style: Style) -> Element {
+    return Group::new()
+        .style(style)
+        .with(
+            polygon
+                .edges()
+                .zip(polygon.normals.iter())
+                .map(|((p1, p2), n)| {
+                    let center = p1 + (p2 - p1) / 2.;
+                    Element::line(center, center + n * length, Style::new())
+                }),
+        )
+        .into();
+}
+
+/// escapes the characters which have a meaning in xml
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '\'' => escaped.push_str("&apos;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    return escaped;
+}
+
+fn write_style<W: Write>(w: &mut W, style: &Style) -> io::Result<()> {
+    for (name, value) in style.attributes() {
+        write!(w, " {}='{}'", name, escape(&value))?;
+    }
+    return Ok(());
+}
+
+fn format_points(points: &[Vertex]) -> String {
+    return points
+        .iter()
+        .map(|p| format!("{},{}", p.x, p.y))
+        .collect::<Vec<String>>()
+        .join(" ");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Point2;
+
+    fn written(document: &Document) -> String {
+        let mut svg = Vec::new();
+        document.write(&mut svg).unwrap();
+        return String::from_utf8(svg).unwrap();
+    }
+
+    #[test]
+    fn text_and_attributes_are_escaped() {
+        let mut document = Document::new((0., 0., 10., 10.), 100., 100.);
+        document.add(
+            Group::new()
+                .class("a'b")
+                .attribute("data-name", "<x & y>")
+                .with(vec![Element::text(
+                    Point2::new(1., 2.),
+                    3.,
+                    "1 < 2 & \"3\"",
+                    Style::new(),
+                )]),
+        );
+        let svg = written(&document);
+        assert!(svg.contains("<g class='a&apos;b' data-name='&lt;x &amp; y&gt;'>"));
+        assert!(svg.contains(">1 &lt; 2 &amp; &quot;3&quot;</text>"));
+    }
+
+    #[test]
+    fn groups_carry_their_style() {
+        let mut document = Document::physical((0., 0., 210., 297.), "mm");
+        document.add(
+            Group::new()
+                .style(Style::line("red", 0.1).dash(&[1., 2.]).round())
+                .with(vec![
+                    Element::line(Point2::new(0., 0.), Point2::new(1., 1.), Style::new()),
+                    Element::circle(Point2::new(5., 5.), 2., Style::new().fill("blue")),
+                ]),
+        );
+        let svg = written(&document);
+        assert!(svg.contains("viewBox='0 0 210 297' width='210mm' height='297mm'"));
+        assert!(svg.contains(
+            "<g stroke='red' stroke-width='0.1' fill='none' stroke-dasharray='1 2' \
+             stroke-linecap='round' stroke-linejoin='round'>"
+        ));
+        assert!(svg.contains("<line x1='0' y1='0' x2='1' y2='1'/>"));
+        assert!(svg.contains("<circle cx='5' cy='5' r='2' fill='blue'/>"));
+        assert!(svg.trim_end().ends_with("</g>\n</svg>"));
+    }
+
+    #[test]
+    fn empty_drawings_show_the_margin() {
+        let document = Document::fit(&[], 5., 100.);
+        assert_eq!(document.view_box, (-5., -5., 10., 10.));
+    }
+}
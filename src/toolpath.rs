@@ -0,0 +1,160 @@
+use crate::model::Layer;
+use crate::polygons::{offset_area, scalar, scanline, Polygon, Vertex};
+use cgmath::*;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathKind {
+    /// loops following the contours of the layer
+    Perimeter,
+    /// lines filling the area inside of the perimeters
+    Infill,
+    /// movement without extruding
+    Travel,
+}
+
+/// a polyline the nozzle follows, closed loops repeat the first point at the end
+#[derive(Debug, Clone)]
+pub struct Path<S = f32> {
+    pub kind: PathKind,
+    pub points: Vec<Vertex<S>>,
+    /// the material, which decides the extruder that prints the path
+    pub material: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ToolpathSettings<S = f32> {
+    /// width of an extruded line
+    pub line_width: S,
+    /// number of perimeters
+    pub walls: u32,
+    /// infill density between 0 and 1
+    pub infill: S,
+}
+
+impl<S: BaseFloat> Default for ToolpathSettings<S> {
+    fn default() -> Self {
+        return ToolpathSettings {
+            line_width: scalar(0.4),
+            walls: 2,
+            infill: scalar(0.2),
+        };
+    }
+}
+
+/// Generates the paths of a single layer: perimeters from the outside in, then the infill.
+/// Every material is done completely before the next one, so the extruder changes at most
+/// once per material. Consecutive paths are connected by travel moves.
+///
+/// The infill lines run in x direction on even layers and in z direction on odd ones,
+/// so the infill of neighbouring layers crosses.
+pub fn layer_toolpaths<S: BaseFloat>(
+    layer: &Layer<S>,
+    index: usize,
+    settings: &ToolpathSettings<S>,
+) -> Vec<Path<S>> {
+    let w = settings.line_width;
+    let mut materials: Vec<usize> = layer.polygons.iter().map(|p| p.material).collect();
+    materials.sort_unstable();
+    materials.dedup();
+
+    let mut paths: Vec<Path<S>> = Vec::new();
+    for material in materials {
+        let polygons: Vec<Polygon<S>> = layer
+            .polygons
+            .iter()
+            .filter(|p| p.material == material)
+            .cloned()
+            .collect();
+        for wall in 0..settings.walls {
+            // the center of the outermost line is half a line width inside of the contour
+            let distance = -(scalar::<S>(wall as f64) + scalar(0.5)) * w;
+            paths.extend(offset_area(&polygons, distance).into_iter().map(|p| Path {
+                kind: PathKind::Perimeter,
+                points: p.points,
+                material,
+            }));
+        }
+        if settings.infill > S::zero() {
+            let area = offset_area(&polygons, -scalar::<S>(settings.walls as f64) * w);
+            paths.extend(infill(
+                &area,
+                w / settings.infill.min(S::one()),
+                index % 2 == 1,
+                material,
+            ));
+        }
+    }
+    return with_travel(paths);
+}
+
+/// parallel lines `spacing` apart filling the area (even-odd rule)
+pub fn infill<S: BaseFloat>(
+    area: &[Polygon<S>],
+    spacing: S,
+    swap: bool,
+    material: usize,
+) -> Vec<Path<S>> {
+    // scanning in z direction is done by swapping the coordinates before and after
+    let flip = |v: Vertex<S>| if swap { Point2::new(v.y, v.x) } else { v };
+    let area: Vec<Polygon<S>> = area
+        .iter()
+        .map(|p| Polygon {
+            points: p.points.iter().map(|v| flip(*v)).collect(),
+            normals: p.normals.clone(),
+            material: p.material,
+        })
+        .collect();
+    let ys: Vec<S> = area
+        .iter()
+        .flat_map(|p| p.points.iter().map(|v| v.y))
+        .collect();
+    if ys.is_empty() {
+        return vec![];
+    }
+    let min = ys.iter().cloned().fold(S::infinity(), S::min);
+    let max = ys.iter().cloned().fold(S::neg_infinity(), S::max);
+
+    let mut lines = Vec::new();
+    let mut y = (min / spacing).ceil() * spacing;
+    let mut row = 0;
+    while y <= max {
+        let mut spans: Vec<(S, S)> = scanline(&area, y)
+            .chunks(2)
+            .filter(|c| c.len() == 2)
+            .map(|c| (c[0], c[1]))
+            .collect();
+        // every second row runs backwards, which keeps the travel moves short
+        if row % 2 == 1 {
+            spans.reverse();
+            spans.iter_mut().for_each(|s| *s = (s.1, s.0));
+        }
+        lines.extend(spans.into_iter().map(|(x0, x1)| Path {
+            kind: PathKind::Infill,
+            points: vec![flip(Point2::new(x0, y)), flip(Point2::new(x1, y))],
+            material,
+        }));
+        y += spacing;
+        row += 1;
+    }
+    return lines;
+}
+
+/// inserts a travel move from the end of each path to the start of the next one,
+/// the travel move belongs to the material of the next path
+pub fn with_travel<S: BaseFloat>(paths: Vec<Path<S>>) -> Vec<Path<S>> {
+    let mut result: Vec<Path<S>> = Vec::new();
+    for path in paths {
+        if path.points.is_empty() {
+            continue;
+        }
+        if let Some(end) = result.last().and_then(|p| p.points.last()) {
+            result.push(Path {
+                kind: PathKind::Travel,
+                points: vec![*end, path.points[0]],
+                material: path.material,
+            });
+        }
+        result.push(path);
+    }
+    return result;
+}
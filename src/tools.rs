@@ -0,0 +1,260 @@
+//! Printing with several extruders (tools).
+//!
+//! Every material is printed by one tool. Within a layer all paths of a tool are printed
+//! together, so a layer needs at most one change per tool. After a change the new tool
+//! primes itself on the prime tower, a small block next to the model which grows with it.
+
+use crate::model::Layer;
+use crate::polygons::{scalar, Polygon, Vertex};
+use crate::profile::Profile;
+use crate::toolpath::{with_travel, Path, PathKind};
+use cgmath::*;
+
+#[derive(Debug, Clone)]
+pub struct ToolSettings<S = f32> {
+    /// tool used for each material, materials without an entry use the tool with their index
+    pub tools: Vec<usize>,
+    /// g-code emitted before a tool change, `{from}` and `{to}` are replaced by the tools
+    pub change_before: String,
+    /// g-code emitted after a tool change, with the same replacements
+    pub change_after: String,
+    /// length the filament of the old tool is pulled back before the change (mm)
+    pub retraction: S,
+    /// length of filament pushed out of the new tool after the change (mm),
+    /// this includes the retraction of the tool
+    pub purge: S,
+    pub prime_tower: Option<PrimeTower<S>>,
+}
+
+impl<S: BaseFloat> Default for ToolSettings<S> {
+    fn default() -> Self {
+        return ToolSettings::from_profile(&Profile::default());
+    }
+}
+
+impl<S: BaseFloat> ToolSettings<S> {
+    /// takes the settings from a profile, the prime tower is placed by `PrimeTower::beside`
+    /// once the layers are known
+    pub fn from_profile(profile: &Profile) -> ToolSettings<S> {
+        let tools = &profile.tools;
+        return ToolSettings {
+            tools: tools.tools.clone(),
+            change_before: tools.change_before.clone(),
+            change_after: tools.change_after.clone(),
+            retraction: scalar(tools.retraction as f64),
+            purge: scalar(tools.purge as f64),
+            prime_tower: None,
+        };
+    }
+}
+
+impl<S> ToolSettings<S> {
+    /// the tool that prints the material
+    pub fn tool(&self, material: usize) -> usize {
+        return self.tools.get(material).copied().unwrap_or(material);
+    }
+
+    /// a material printed by the tool, used for paths which belong to no material
+    fn material(&self, tool: usize) -> usize {
+        return (0..=tool.max(self.tools.len()))
+            .find(|m| self.tool(*m) == tool)
+            .unwrap_or(tool);
+    }
+
+    /// a change script with the tools filled in
+    pub fn script(&self, script: &str, from: usize, to: usize) -> String {
+        return script
+            .replace("{from}", &from.to_string())
+            .replace("{to}", &to.to_string());
+    }
+}
+
+/// A square block printed in every layer. Each tool change in a layer prints a share of its
+/// loops, layers without a change only print the outermost loop to keep the tower growing.
+#[derive(Debug, Clone)]
+pub struct PrimeTower<S = f32> {
+    /// corner with the smallest coordinates, in the slicing plane like the layers
+    pub position: Vertex<S>,
+    /// length of a side (mm)
+    pub size: S,
+}
+
+impl<S: BaseFloat> PrimeTower<S> {
+    /// a tower next to the layers in x direction, `distance` away from the model
+    pub fn beside(layers: &[Layer<S>], size: S, distance: S) -> PrimeTower<S> {
+        let points = layers
+            .iter()
+            .flat_map(|l| l.polygons.iter())
+            .flat_map(|p| p.points.iter());
+        let (max_x, min_y) = points.fold((S::zero(), S::infinity()), |(x, y), p| {
+            (x.max(p.x), y.min(p.y))
+        });
+        let min_y = if min_y.is_finite() { min_y } else { S::zero() };
+        return PrimeTower {
+            position: Point2::new(max_x + distance, min_y),
+            size,
+        };
+    }
+
+    /// the area the tower takes up in every layer
+    pub fn footprint(&self) -> Polygon<S> {
+        return Polygon::rectangle(
+            self.position,
+            self.position + Vector2::new(self.size, self.size),
+        );
+    }
+
+    /// closed loops from the outside in, `line_width` apart
+    fn loops(&self, line_width: S) -> Vec<Vec<Vertex<S>>> {
+        let count = (self.size / scalar(2.) / line_width)
+            .floor()
+            .to_usize()
+            .unwrap_or(0);
+        return (0..count)
+            .map(|i| {
+                let inset = scalar::<S>(i as f64 + 0.5) * line_width;
+                let min = self.position + Vector2::new(inset, inset);
+                let max = self.position + Vector2::new(self.size - inset, self.size - inset);
+                Polygon::rectangle(min, max).points
+            })
+            .collect();
+    }
+}
+
+/// Reorders the paths of every layer so each tool prints all of its paths at once.
+/// A layer starts with the tool the previous layer ended with, the other tools follow in
+/// ascending order. With a prime tower every tool change is followed by priming on the tower,
+/// and the footprint of the tower is added to every layer, so travel moves treat it like a
+/// part of the model. The travel moves are created anew, `line_width` is the width of the
+/// tower lines.
+pub fn plan_tools<S: BaseFloat>(
+    layers: &mut [Layer<S>],
+    toolpaths: &[Vec<Path<S>>],
+    settings: &ToolSettings<S>,
+    line_width: S,
+) -> Vec<Vec<Path<S>>> {
+    let loops = match &settings.prime_tower {
+        Some(tower) => {
+            for layer in layers.iter_mut() {
+                layer.polygons.push(tower.footprint());
+            }
+            tower.loops(line_width)
+        }
+        None => vec![],
+    };
+    let mut current: Option<usize> = None;
+    let mut result = Vec::new();
+    for paths in toolpaths {
+        let mut tools: Vec<usize> = paths
+            .iter()
+            .filter(|p| p.kind != PathKind::Travel)
+            .map(|p| settings.tool(p.material))
+            .collect();
+        tools.sort_unstable();
+        tools.dedup();
+        // continuing with the tool of the previous layer saves a change
+        if let Some(i) = tools.iter().position(|t| Some(*t) == current) {
+            let tool = tools.remove(i);
+            tools.insert(0, tool);
+        }
+        let changes = tools.iter().filter(|t| Some(**t) != current).count();
+
+        let mut layer: Vec<Path<S>> = Vec::new();
+        let mut change = 0;
+        for tool in &tools {
+            if Some(*tool) != current {
+                // the loops are shared between the changes of the layer
+                layer.extend(
+                    loops
+                        .iter()
+                        .skip(change)
+                        .step_by(changes)
+                        .map(|points| tower_path(points, settings.material(*tool))),
+                );
+                change += 1;
+                current = Some(*tool);
+            }
+            layer.extend(
+                paths
+                    .iter()
+                    .filter(|p| p.kind != PathKind::Travel && settings.tool(p.material) == *tool)
+                    .cloned(),
+            );
+        }
+        if changes == 0 {
+            if let (Some(tool), Some(points)) = (current, loops.first()) {
+                layer.push(tower_path(points, settings.material(tool)));
+            }
+        }
+        result.push(with_travel(layer));
+    }
+    return result;
+}
+
+fn tower_path<S: BaseFloat>(points: &[Vertex<S>], material: usize) -> Path<S> {
+    return Path {
+        kind: PathKind::Perimeter,
+        points: points.to_vec(),
+        material,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn square(x: f32, material: usize) -> Path {
+        return Path {
+            kind: PathKind::Perimeter,
+            points: Polygon::rectangle(Point2::new(x, 0.), Point2::new(x + 5., 5.)).points,
+            material,
+        };
+    }
+
+    #[test]
+    fn tools_print_their_paths_together_and_prime_on_the_tower() {
+        let mut layers: Vec<Layer> = (0..3)
+            .map(|i| Layer {
+                height: 0.1 + 0.2 * i as f32,
+                thickness: 0.2,
+                polygons: vec![],
+            })
+            .collect();
+        let toolpaths = vec![
+            vec![square(0., 0), square(10., 1), square(20., 0)],
+            vec![square(10., 1)],
+            vec![square(0., 0), square(10., 1)],
+        ];
+        let tower = PrimeTower {
+            position: Point2::new(30., 0.),
+            size: 2.,
+        };
+        let settings = ToolSettings {
+            prime_tower: Some(tower.clone()),
+            ..ToolSettings::default()
+        };
+        let planned = plan_tools(&mut layers, &toolpaths, &settings, 0.4);
+        assert!(layers.iter().all(|l| l.polygons == vec![tower.footprint()]));
+
+        let printed: Vec<Vec<(usize, bool)>> = planned
+            .iter()
+            .map(|paths| {
+                paths
+                    .iter()
+                    .filter(|p| p.kind != PathKind::Travel)
+                    .map(|p| (p.material, p.points[0].x >= 30.))
+                    .collect()
+            })
+            .collect();
+        // the tower has two loops, which are shared by the changes of a layer,
+        // a layer without a change prints the outer loop after the model
+        assert_eq!(
+            printed,
+            vec![
+                vec![(0, true), (0, false), (0, false), (1, true), (1, false)],
+                vec![(1, false), (1, true)],
+                vec![(1, false), (0, true), (0, true), (0, false)],
+            ]
+        );
+    }
+}
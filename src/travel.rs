@@ -0,0 +1,346 @@
+//! Orders the paths of a layer to keep the travel moves between them short.
+//!
+//! The paths are ordered greedily, always continuing with the closest path, and the order
+//! is improved with 2-opt afterwards. Closed loops may start at any of their points and
+//! open lines may be printed in either direction.
+
+use crate::polygons::Vertex;
+use crate::toolpath::{with_travel, Path, PathKind};
+use cgmath::*;
+
+/// total length of the travel moves before and after the optimisation (mm)
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TravelStats {
+    pub before: f32,
+    pub after: f32,
+}
+
+impl std::ops::AddAssign for TravelStats {
+    fn add_assign(&mut self, other: TravelStats) {
+        self.before += other.before;
+        self.after += other.after;
+    }
+}
+
+/// sum of the lengths of the travel moves
+pub fn travel_distance(paths: &[Path]) -> f32 {
+    return paths
+        .iter()
+        .filter(|p| p.kind == PathKind::Travel)
+        .map(|p| length(&p.points))
+        .sum();
+}
+
+/// Reorders the paths of a layer and creates new travel moves between them. `start` is the
+/// position of the nozzle before the layer, e.g. the end of the previous layer.
+/// With `keep_seams` loops keep their start point, e.g. one chosen by `place_seams`.
+///
+/// Only neighbouring paths of the same kind and material are reordered, so all perimeters
+/// are still printed before the infill and the tools change as often as before.
+pub fn optimize_travel(
+    paths: &[Path],
+    start: Option<Vertex>,
+    keep_seams: bool,
+) -> (Vec<Path>, TravelStats) {
+    let extrusions: Vec<&Path> = paths
+        .iter()
+        .filter(|p| p.kind != PathKind::Travel && !p.points.is_empty())
+        .collect();
+    let before = match (start, extrusions.first()) {
+        (Some(s), Some(first)) => (first.points[0] - s).magnitude(),
+        _ => 0.,
+    } + travel_distance(paths);
+
+    let mut position = start;
+    let mut result: Vec<Path> = Vec::new();
+    let mut i = 0;
+    while i < extrusions.len() {
+        let (kind, material) = (extrusions[i].kind, extrusions[i].material);
+        let end = extrusions[i..]
+            .iter()
+            .position(|p| p.kind != kind || p.material != material)
+            .map(|n| i + n)
+            .unwrap_or(extrusions.len());
+        let here = position.unwrap_or(extrusions[i].points[0]);
+        let ordered = order(&extrusions[i..end], here, keep_seams);
+        position = ordered.last().and_then(|p| p.points.last()).copied();
+        result.extend(ordered);
+        i = end;
+    }
+
+    let result = with_travel(result);
+    let after = match (start, result.first()) {
+        (Some(s), Some(first)) => (first.points[0] - s).magnitude(),
+        _ => 0.,
+    } + travel_distance(&result);
+    return (result, TravelStats { before, after });
+}
+
+/// Optimises the layers one after another, each layer starts where the previous one ended.
+pub fn optimize_layers(toolpaths: &[Vec<Path>], keep_seams: bool) -> (Vec<Vec<Path>>, TravelStats) {
+    let mut stats = TravelStats::default();
+    let mut position: Option<Vertex> = None;
+    let mut result = Vec::new();
+    for paths in toolpaths {
+        let (paths, layer) = optimize_travel(paths, position, keep_seams);
+        position = paths
+            .last()
+            .and_then(|p| p.points.last())
+            .copied()
+            .or(position);
+        stats += layer;
+        result.push(paths);
+    }
+    return (result, stats);
+}
+
+/// a path in the order it is printed
+struct Item<'a> {
+    path: &'a Path,
+    /// index of the first point for loops
+    rotation: usize,
+    /// open lines only
+    reversed: bool,
+    /// loops keep their start point
+    fixed: bool,
+}
+
+impl<'a> Item<'a> {
+    fn closed(&self) -> bool {
+        let points = &self.path.points;
+        return points.len() > 2 && points.first() == points.last();
+    }
+
+    fn entry(&self) -> Vertex {
+        let points = &self.path.points;
+        if self.closed() {
+            return points[self.rotation];
+        }
+        return if self.reversed {
+            points[points.len() - 1]
+        } else {
+            points[0]
+        };
+    }
+
+    fn exit(&self) -> Vertex {
+        let points = &self.path.points;
+        if self.closed() {
+            return points[self.rotation];
+        }
+        return if self.reversed {
+            points[0]
+        } else {
+            points[points.len() - 1]
+        };
+    }
+
+    /// starts the item as close to `from` as possible
+    fn enter_from(&mut self, from: Vertex) {
+        let points = &self.path.points;
+        if self.closed() {
+            if !self.fixed {
+                self.rotation = closest(&points[..points.len() - 1], from);
+            }
+        } else {
+            let (first, last) = (points[0], points[points.len() - 1]);
+            self.reversed = (last - from).magnitude2() < (first - from).magnitude2();
+        }
+    }
+
+    /// distance from `from` to the closest possible start of the item
+    fn distance(&self, from: Vertex) -> f32 {
+        let points = &self.path.points;
+        if self.closed() && self.fixed {
+            return (points[0] - from).magnitude();
+        }
+        if self.closed() {
+            return points[..points.len() - 1]
+                .iter()
+                .map(|p| (p - from).magnitude())
+                .fold(f32::INFINITY, f32::min);
+        }
+        let (first, last) = (points[0], points[points.len() - 1]);
+        return (first - from).magnitude().min((last - from).magnitude());
+    }
+
+    fn into_path(self) -> Path {
+        let mut points = self.path.points.clone();
+        if self.closed() {
+            points.pop();
+            points.rotate_left(self.rotation);
+            points.push(points[0]);
+        } else if self.reversed {
+            points.reverse();
+        }
+        return Path {
+            points,
+            ..self.path.clone()
+        };
+    }
+}
+
+/// nearest neighbour order improved by 2-opt, the start points of loops are chosen last
+fn order(paths: &[&Path], start: Vertex, keep_seams: bool) -> Vec<Path> {
+    let mut remaining: Vec<Item> = paths
+        .iter()
+        .map(|path| Item {
+            path,
+            rotation: 0,
+            reversed: false,
+            fixed: keep_seams,
+        })
+        .collect();
+    let mut items: Vec<Item> = Vec::new();
+    let mut position = start;
+    while !remaining.is_empty() {
+        let next = (0..remaining.len())
+            .min_by(|a, b| {
+                let da = remaining[*a].distance(position);
+                let db = remaining[*b].distance(position);
+                da.partial_cmp(&db).unwrap()
+            })
+            .unwrap();
+        let mut item = remaining.swap_remove(next);
+        item.enter_from(position);
+        position = item.exit();
+        items.push(item);
+    }
+
+    two_opt(&mut items, start);
+
+    // the order may have changed, so the loops are entered anew,
+    // the direction of open lines was chosen by 2-opt
+    let mut position = start;
+    for item in items.iter_mut() {
+        if item.closed() {
+            item.enter_from(position);
+        }
+        position = item.exit();
+    }
+    return items.into_iter().map(|item| item.into_path()).collect();
+}
+
+/// Reverses parts of the order as long as that shortens the travel. Reversing a part also
+/// reverses the direction of the open lines in it.
+fn two_opt(items: &mut [Item], start: Vertex) {
+    // limits the work for layers with many short paths
+    let max_rounds = 10;
+    let n = items.len();
+    for _ in 0..max_rounds {
+        let mut improved = false;
+        for i in 0..n {
+            for j in i + 1..n {
+                let before = if i == 0 { start } else { items[i - 1].exit() };
+                let after = items.get(j + 1).map(|item| item.entry());
+                let current = (items[i].entry() - before).magnitude()
+                    + after.map_or(0., |a| (a - items[j].exit()).magnitude());
+                let swapped = (items[j].exit() - before).magnitude()
+                    + after.map_or(0., |a| (a - items[i].entry()).magnitude());
+                if swapped < current - 1e-4 {
+                    items[i..=j].reverse();
+                    for item in items[i..=j].iter_mut() {
+                        item.reversed = !item.reversed;
+                    }
+                    improved = true;
+                }
+            }
+        }
+        if !improved {
+            break;
+        }
+    }
+}
+
+fn closest(points: &[Vertex], to: Vertex) -> usize {
+    return (0..points.len())
+        .min_by(|a, b| {
+            let da = (points[*a] - to).magnitude2();
+            let db = (points[*b] - to).magnitude2();
+            da.partial_cmp(&db).unwrap()
+        })
+        .unwrap_or(0);
+}
+
+fn length(points: &[Vertex]) -> f32 {
+    return points
+        .iter()
+        .zip(points.iter().skip(1))
+        .map(|(a, b)| (b - a).magnitude())
+        .sum();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::polygons::Polygon;
+
+    fn square(x: f32, y: f32, size: f32, kind: PathKind) -> Path {
+        return Path {
+            kind,
+            points: Polygon::rectangle(Point2::new(x, y), Point2::new(x + size, y + size)).points,
+            material: 0,
+        };
+    }
+
+    #[test]
+    fn two_opt_untangles_crossing_moves() {
+        let paths: Vec<Path> = [(0., 1.), (1., 0.), (1., 1.)]
+            .iter()
+            .map(|(x, y)| square(*x, *y, 0.1, PathKind::Perimeter))
+            .collect();
+        let mut items: Vec<Item> = paths
+            .iter()
+            .map(|path| Item {
+                path,
+                rotation: 0,
+                reversed: false,
+                fixed: true,
+            })
+            .collect();
+        two_opt(&mut items, Point2::new(0., 0.));
+        let order: Vec<Vertex> = items.iter().map(|item| item.entry()).collect();
+        let expected = [(0., 1.), (1., 1.), (1., 0.)].map(|(x, y)| Point2::new(x, y));
+        assert_eq!(order, expected);
+    }
+
+    #[test]
+    fn paths_are_reordered_within_their_kind() {
+        let paths = vec![
+            square(20., 0., 5., PathKind::Perimeter),
+            square(10., 0., 5., PathKind::Perimeter),
+            Path {
+                kind: PathKind::Infill,
+                points: vec![Point2::new(30., 0.), Point2::new(16., 0.)],
+                material: 0,
+            },
+        ];
+        let start = Some(Point2::new(0., 6.));
+        let (ordered, stats) = optimize_travel(&with_travel(paths.clone()), start, false);
+        let printed: Vec<&Path> = ordered
+            .iter()
+            .filter(|p| p.kind != PathKind::Travel)
+            .collect();
+        // the closer loop comes first, entered at its closest corner
+        assert_eq!(printed[0].points[0], Point2::new(10., 5.));
+        assert_eq!(printed[1].kind, PathKind::Perimeter);
+        // the infill stays last and starts at its end closer to the loops
+        assert_eq!(
+            printed[2].points,
+            vec![Point2::new(16., 0.), Point2::new(30., 0.)]
+        );
+        // 10.05 to the first loop, 10 to the second and 6.4 to the infill instead of
+        // 20.9, 10 and 20 in the given order
+        assert!((stats.before - 50.9).abs() < 0.1);
+        assert!((stats.after - 26.5).abs() < 0.1);
+
+        // kept seams are not moved
+        let (ordered, _) = optimize_travel(&paths, start, true);
+        let starts: Vec<Vertex> = ordered
+            .iter()
+            .filter(|p| p.kind == PathKind::Perimeter)
+            .map(|p| p.points[0])
+            .collect();
+        assert_eq!(starts, vec![Point2::new(10., 0.), Point2::new(20., 0.)]);
+    }
+}
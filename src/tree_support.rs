@@ -0,0 +1,337 @@
+use crate::model::{Layer, Model, Slice};
+use crate::polygons::{inside, Polygon, Vertex};
+use crate::profile::Profile;
+use cgmath::*;
+
+#[derive(Debug, Clone)]
+pub struct TreeSupportSettings {
+    /// overhangs steeper than this angle (degrees, measured from the vertical) are supported
+    pub angle: f32,
+    /// distance between the contact points on an overhanging face
+    pub contact_spacing: f32,
+    /// radius of a branch where it touches the model
+    pub tip_radius: f32,
+    /// largest radius a trunk can grow to
+    pub max_radius: f32,
+    /// how much the radius grows per mm the branch descends
+    pub growth: f32,
+    /// largest angle (degrees, measured from the vertical) a branch may lean
+    pub branch_angle: f32,
+    /// branches closer than this distance lean towards each other
+    pub attraction: f32,
+    /// horizontal distance kept between branches and the model
+    pub clearance: f32,
+    /// vertical distance between the tips and the model
+    pub z_gap: f32,
+    /// number of line segments of a branch's cross-section
+    pub segments: usize,
+}
+
+impl Default for TreeSupportSettings {
+    fn default() -> Self {
+        return TreeSupportSettings {
+            angle: 50.,
+            contact_spacing: 3.,
+            tip_radius: 0.4,
+            max_radius: 4.,
+            growth: 0.05,
+            branch_angle: 40.,
+            attraction: 10.,
+            clearance: 1.,
+            z_gap: 0.2,
+            segments: 12,
+        };
+    }
+}
+
+impl TreeSupportSettings {
+    /// takes the angle, spacing and gaps from a profile, the branches keep their defaults
+    pub fn from_profile(profile: &Profile) -> TreeSupportSettings {
+        let support = &profile.support;
+        return TreeSupportSettings {
+            angle: support.angle,
+            contact_spacing: support.spacing,
+            clearance: support.xy_gap,
+            z_gap: support.z_gap,
+            ..TreeSupportSettings::default()
+        };
+    }
+}
+
+/// a point on an overhang that needs to be supported
+#[derive(Debug, Clone, Copy)]
+struct Contact {
+    position: Vertex,
+    height: f32,
+}
+
+/// a single branch of a tree, contains all branches that were merged into it
+#[derive(Debug, Clone)]
+struct Branch {
+    position: Vertex,
+    radius: f32,
+    /// number of contact points carried by the branch
+    contacts: usize,
+}
+
+/// Generates tree supports for the given layers, which have to be sorted by height.
+/// Returns the circular cross-sections of all branches for every layer.
+///
+/// Branches start at contact points sampled on the overhanging faces and grow downwards.
+/// On their way down they lean towards their nearest neighbour and merge with it once
+/// they touch. Branches move around the model's contours, a branch that can not avoid
+/// the model ends on top of it.
+pub fn generate_tree_supports(
+    model: &Model,
+    layers: &[Layer],
+    settings: &TreeSupportSettings,
+) -> Vec<Slice> {
+    if layers.is_empty() {
+        return vec![];
+    }
+    let layer_height = if layers.len() > 1 {
+        layers[1].height - layers[0].height
+    } else {
+        model.bounds().1.y - model.bounds().0.y
+    };
+    let max_move = layer_height * settings.branch_angle.to_radians().tan();
+
+    let mut contacts = contact_points(model, settings);
+    // sorted from top to bottom, so new contacts can be taken from the front
+    contacts.sort_by(|a, b| b.height.partial_cmp(&a.height).unwrap());
+    let mut contacts = contacts.into_iter().peekable();
+
+    let mut branches: Vec<Branch> = Vec::new();
+    let mut slices: Vec<Slice> = vec![vec![]; layers.len()];
+    for l in (0..layers.len()).rev() {
+        let layer = &layers[l];
+        let top = layer.height + layer_height / 2.;
+
+        // move the existing branches down by one layer
+        branches = grow(branches, layer, max_move, settings, layer_height);
+
+        // start new branches at all contacts above this layer
+        while let Some(contact) = contacts.next_if(|c| c.height - settings.z_gap >= top) {
+            let branch = Branch {
+                position: contact.position,
+                radius: settings.tip_radius,
+                contacts: 1,
+            };
+            if is_free(
+                &layer.polygons,
+                branch.position,
+                branch.radius + settings.clearance,
+            ) {
+                branches.push(branch);
+            }
+        }
+
+        slices[l] = branches
+            .iter()
+            .map(|b| Polygon::circle(b.position, b.radius, settings.segments))
+            .collect();
+    }
+    return slices;
+}
+
+/// samples contact points on a regular grid on all overhanging faces
+fn contact_points(model: &Model, settings: &TreeSupportSettings) -> Vec<Contact> {
+    let limit = settings.angle.to_radians().sin();
+    let spacing = settings.contact_spacing;
+    let mut contacts = Vec::new();
+    for ([a, b, c], normal) in model.triangles().filter(|(_, n)| -n.y > limit) {
+        // all grid points within the bounding box of the face
+        let (min_x, max_x) = (a.x.min(b.x).min(c.x), a.x.max(b.x).max(c.x));
+        let (min_z, max_z) = (a.z.min(b.z).min(c.z), a.z.max(b.z).max(c.z));
+        let mut x = (min_x / spacing).ceil() * spacing;
+        while x <= max_x {
+            let mut z = (min_z / spacing).ceil() * spacing;
+            while z <= max_z {
+                if let Some(height) = height_on_face([a, b, c], normal, Point2::new(x, z)) {
+                    contacts.push(Contact {
+                        position: Point2::new(x, z),
+                        height,
+                    });
+                }
+                z += spacing;
+            }
+            x += spacing;
+        }
+    }
+    return contacts;
+}
+
+/// height of the triangle at a point of the xz plane, if the point lies within the triangle
+fn height_on_face(
+    [a, b, c]: [Point3<f32>; 3],
+    normal: Vector3<f32>,
+    point: Point2<f32>,
+) -> Option<f32> {
+    let side = |p: Point3<f32>, q: Point3<f32>| {
+        (q.x - p.x) * (point.y - p.z) - (q.z - p.z) * (point.x - p.x)
+    };
+    let (s1, s2, s3) = (side(a, b), side(b, c), side(c, a));
+    let has_negative = s1 < 0. || s2 < 0. || s3 < 0.;
+    let has_positive = s1 > 0. || s2 > 0. || s3 > 0.;
+    if has_negative && has_positive {
+        return None;
+    }
+    // solve the plane equation for y
+    return Some(a.y - (normal.x * (point.x - a.x) + normal.z * (point.y - a.z)) / normal.y);
+}
+
+/// moves all branches one layer down, merges touching branches
+/// and removes the ones that ended on the model
+fn grow(
+    branches: Vec<Branch>,
+    layer: &Layer,
+    max_move: f32,
+    settings: &TreeSupportSettings,
+    layer_height: f32,
+) -> Vec<Branch> {
+    let mut moved: Vec<Branch> = Vec::new();
+    for (i, branch) in branches.iter().enumerate() {
+        // lean towards the closest other branch so they can merge
+        let target = branches
+            .iter()
+            .enumerate()
+            .filter(|(j, _)| *j != i)
+            .map(|(_, other)| other.position)
+            .filter(|p| (*p - branch.position).magnitude() <= settings.attraction)
+            .min_by(|p, q| {
+                let dp = (*p - branch.position).magnitude2();
+                let dq = (*q - branch.position).magnitude2();
+                dp.partial_cmp(&dq).unwrap()
+            })
+            .unwrap_or(branch.position);
+
+        let radius = (branch.radius + settings.growth * layer_height).min(settings.max_radius);
+        let clearance = radius + settings.clearance;
+        if let Some(position) = step(
+            &layer.polygons,
+            branch.position,
+            target,
+            max_move,
+            clearance,
+        ) {
+            moved.push(Branch {
+                position,
+                radius,
+                contacts: branch.contacts,
+            });
+        }
+    }
+
+    // merge branches that touch each other, unless the merged branch would touch the model
+    let mut merged: Vec<Branch> = Vec::new();
+    for branch in moved {
+        let joined = merged.iter().enumerate().find_map(|(i, b)| {
+            let distance = (b.position - branch.position).magnitude();
+            if distance > max_move.max(b.radius.min(branch.radius)) {
+                return None;
+            }
+            let contacts = b.contacts + branch.contacts;
+            let joined = Branch {
+                position: Point2::from_vec(
+                    (b.position.to_vec() * b.contacts as f32
+                        + branch.position.to_vec() * branch.contacts as f32)
+                        / contacts as f32,
+                ),
+                // the cross-section grows with the number of contacts it carries
+                radius: (b.radius.powi(2) + branch.radius.powi(2))
+                    .sqrt()
+                    .min(settings.max_radius),
+                contacts,
+            };
+            let free = is_free(
+                &layer.polygons,
+                joined.position,
+                joined.radius + settings.clearance,
+            );
+            return if free { Some((i, joined)) } else { None };
+        });
+        match joined {
+            Some((i, joined)) => merged[i] = joined,
+            None => merged.push(branch),
+        }
+    }
+    return merged;
+}
+
+/// finds the next position of a branch, as close to the target as possible
+/// while keeping the clearance to the model.
+/// Returns `None` if the branch can not avoid the model
+fn step(
+    polygons: &[Polygon],
+    position: Vertex,
+    target: Vertex,
+    max_move: f32,
+    clearance: f32,
+) -> Option<Vertex> {
+    let towards = target - position;
+    let preferred = if towards.magnitude() > max_move {
+        position + towards.normalize() * max_move
+    } else {
+        target
+    };
+    if is_free(polygons, preferred, clearance) {
+        return Some(preferred);
+    }
+
+    // try to move around the model in any direction
+    let directions = 16;
+    return (0..directions)
+        .map(|i| {
+            let angle = i as f32 / directions as f32 * std::f32::consts::PI * 2.;
+            position + Vector2::new(angle.cos(), angle.sin()) * max_move
+        })
+        .chain(std::iter::once(position))
+        .filter(|p| is_free(polygons, *p, clearance))
+        .min_by(|p, q| {
+            let dp = (*p - preferred).magnitude2();
+            let dq = (*q - preferred).magnitude2();
+            dp.partial_cmp(&dq).unwrap()
+        });
+}
+
+/// checks that a circle does not touch the area covered by the polygons
+fn is_free(polygons: &[Polygon], center: Vertex, radius: f32) -> bool {
+    return !inside(polygons, center) && polygons.iter().all(|p| p.distance(center) >= radius);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::model::tests::boxes;
+
+    #[test]
+    fn branches_avoid_the_model_and_merge() {
+        // a slab from 5 to 7 mm standing on a thin pillar in its center
+        let model = boxes(&[([0., 5., 0.], [10., 7., 10.]), ([4., 0., 4.], [6., 5., 6.])]);
+        let layers = model.layers(0.2, 0.01);
+        let settings = TreeSupportSettings::default();
+        let supports = generate_tree_supports(&model, &layers, &settings);
+        assert_eq!(supports.len(), layers.len());
+
+        let pillar = Polygon::rectangle(Point2::new(4., 4.), Point2::new(6., 6.));
+        for (layer, support) in layers.iter().zip(&supports) {
+            if layer.height + 0.1 > 5. - settings.z_gap + 1e-4 {
+                assert!(support.is_empty(), "branch at {}", layer.height);
+                continue;
+            }
+            assert!(!support.is_empty(), "no branch at {}", layer.height);
+            for point in support.iter().flat_map(|p| p.points.iter()) {
+                assert!(!pillar.contains(*point));
+                assert!(pillar.distance(*point) >= settings.clearance - 1e-3);
+            }
+        }
+        // the branches join on their way down
+        let top = supports.iter().rev().find(|s| !s.is_empty()).unwrap();
+        assert!(
+            supports[0].len() < top.len(),
+            "{} {}",
+            supports[0].len(),
+            top.len()
+        );
+    }
+}
@@ -0,0 +1,326 @@
+use crate::model::{Layer, Material};
+use crate::polygons::Vertex;
+use crate::svg::{self, write_page, Document, Element, Group, Page, Style};
+use crate::toolpath::{Path, PathKind};
+use std::io::{self, Write};
+
+#[derive(Debug, Clone)]
+pub struct ViewerSettings {
+    /// width and height of the drawing in pixels
+    pub size: u32,
+    /// length of the drawn normals (mm)
+    pub normal_length: f32,
+    /// width of the extruded lines (mm), perimeters and infill are drawn this wide
+    pub line_width: f32,
+    /// empty space around the model (mm)
+    pub margin: f32,
+}
+
+impl Default for ViewerSettings {
+    fn default() -> Self {
+        return ViewerSettings {
+            size: 800,
+            normal_length: 1.,
+            line_width: 0.4,
+            margin: 5.,
+        };
+    }
+}
+
+/// the groups every layer consists of, they can be toggled separately in the viewer
+const GROUPS: [&str; 5] = ["contours", "normals", "perimeters", "infill", "travel"];
+
+const STYLE: &str = "
+svg { border: 1px solid #ccc; cursor: grab; }
+.layer { display: none; }
+.layer.current, .layer.ghost { display: inline; }
+.layer.ghost { opacity: 0.2; }
+";
+
+/// Writes an html page showing one layer at a time.
+/// `toolpaths` holds the paths of every layer and may be empty if there are none.
+/// If the model has materials of its own, contours, perimeters and infill are drawn in the
+/// colour of their material.
+///
+/// The view box is fitted to the bounds of all layers. The page has a slider to select the
+/// layer, checkboxes to toggle the parts of a layer and the ghosted previous layer,
+/// the mouse wheel zooms and dragging pans.
+pub fn write_viewer<W: Write>(
+    w: &mut W,
+    layers: &[Layer],
+    toolpaths: &[Vec<Path>],
+    materials: &[Material],
+    settings: &ViewerSettings,
+) -> io::Result<()> {
+    let points: Vec<Vertex> = layers
+        .iter()
+        .flat_map(|l| l.polygons.iter())
+        .flat_map(|p| p.points.iter().copied())
+        .collect();
+    let mut document = Document::fit(&points, settings.margin, settings.size as f32).id("view");
+    // thin lines keep the same width on screen regardless of the model size
+    let thin = document.view_box.2 / settings.size as f32;
+    for (i, layer) in layers.iter().enumerate() {
+        let paths: &[Path] = toolpaths.get(i).map(|p| p.as_slice()).unwrap_or(&[]);
+        document.add(layer_group(i, layer, paths, materials, thin, settings));
+    }
+
+    let mut style = STYLE.to_string();
+    let mut controls = format!(
+        "<div>\n<input type='range' id='layer' min='0' max='{}' value='0' style='width: {}px'>\n<div id='info'></div>\n",
+        layers.len().saturating_sub(1),
+        settings.size
+    );
+    for group in GROUPS.iter() {
+        style.push_str(&format!("svg.hide-{0} .{0} {{ display: none; }}\n", group));
+        controls.push_str(&format!(
+            "<label><input type='checkbox' class='toggle' value='{0}' checked> {0}</label>\n",
+            group
+        ));
+    }
+    controls.push_str("<label><input type='checkbox' id='ghost'> previous layer</label>\n");
+    controls.push_str("<button id='reset'>reset view</button>\n</div>\n");
+
+    let page = Page {
+        title: "layers",
+        style: &style,
+        controls: &controls,
+        script: SCRIPT,
+    };
+    return write_page(w, &document, &page);
+}
+
+fn layer_group(
+    index: usize,
+    layer: &Layer,
+    paths: &[Path],
+    materials: &[Material],
+    thin: f32,
+    settings: &ViewerSettings,
+) -> Group {
+    // without materials from the model everything keeps the colour of its group
+    let colored = materials.iter().any(|m| *m != Material::default());
+    let color = |material: usize| match materials.get(material) {
+        Some(m) if colored => Style::new().stroke(&m.hex_color()),
+        _ => Style::new(),
+    };
+    let mut group = Group::new()
+        .id(&format!("layer_{}", index))
+        .class("layer")
+        .attribute("data-height", &layer.height.to_string());
+
+    group.add(
+        Group::new()
+            .class("contours")
+            .style(Style::line("purple", thin))
+            .with(
+                layer
+                    .polygons
+                    .iter()
+                    .map(|p| svg::polygon(p, color(p.material))),
+            )
+            .into(),
+    );
+    group.add(
+        Group::new()
+            .class("normals")
+            .style(Style::line("red", thin))
+            .with(
+                layer
+                    .polygons
+                    .iter()
+                    .map(|p| svg::normals(p, settings.normal_length, Style::new())),
+            )
+            .into(),
+    );
+
+    let styles = [
+        (
+            PathKind::Perimeter,
+            "perimeters",
+            Style::line("orange", settings.line_width),
+        ),
+        (
+            PathKind::Infill,
+            "infill",
+            Style::line("gold", settings.line_width),
+        ),
+        (
+            PathKind::Travel,
+            "travel",
+            Style::line("blue", thin).dash(&[1., 1.]),
+        ),
+    ];
+    for (kind, class, style) in styles.iter() {
+        group.add(
+            Group::new()
+                .class(class)
+                .style(style.clone().stroke_opacity(0.6).round())
+                .with(paths.iter().filter(|p| p.kind == *kind).map(|p| {
+                    let style = if p.kind != PathKind::Travel {
+                        color(p.material)
+                    } else {
+                        Style::new()
+                    };
+                    Element::polyline(&p.points, style)
+                }))
+                .into(),
+        );
+    }
+    return group;
+}
+
+const SCRIPT: &str = "
+const view = document.getElementById('view');
+const slider = document.getElementById('layer');
+const info = document.getElementById('info');
+const ghost = document.getElementById('ghost');
+const layers = document.querySelectorAll('.layer');
+const initial = view.getAttribute('viewBox').split(' ').map(parseFloat);
+let box = initial.slice();
+
+function show() {
+    const current = parseInt(slider.value);
+    layers.forEach((layer, i) => {
+        layer.classList.toggle('current', i == current);
+        layer.classList.toggle('ghost', ghost.checked && i == current - 1);
+    });
+    if (layers.length == 0) {
+        info.textContent = 'no layers';
+        return;
+    }
+    const height = parseFloat(layers[current].dataset.height);
+    info.textContent = 'layer ' + current + ' of ' + (layers.length - 1) + ', height ' + height.toFixed(3) + ' mm';
+}
+
+function update_box() {
+    view.setAttribute('viewBox', box.join(' '));
+}
+
+slider.oninput = show;
+ghost.onchange = show;
+document.querySelectorAll('.toggle').forEach(toggle => {
+    toggle.onchange = () => view.classList.toggle('hide-' + toggle.value, !toggle.checked);
+});
+document.addEventListener('keydown', event => {
+    if (event.key == 'ArrowUp' || event.key == 'ArrowRight') slider.stepUp();
+    else if (event.key == 'ArrowDown' || event.key == 'ArrowLeft') slider.stepDown();
+    else return;
+    show();
+});
+document.getElementById('reset').onclick = () => {
+    box = initial.slice();
+    update_box();
+};
+
+// zooms around the mouse position
+view.addEventListener('wheel', event => {
+    event.preventDefault();
+    const rect = view.getBoundingClientRect();
+    const x = box[0] + (event.clientX - rect.left) / rect.width * box[2];
+    const y = box[1] + (event.clientY - rect.top) / rect.height * box[3];
+    const factor = event.deltaY > 0 ? 1.2 : 1 / 1.2;
+    box = [x - (x - box[0]) * factor, y - (y - box[1]) * factor, box[2] * factor, box[3] * factor];
+    update_box();
+});
+
+let drag = null;
+view.addEventListener('mousedown', event => {
+    drag = [event.clientX, event.clientY];
+});
+window.addEventListener('mouseup', () => {
+    drag = null;
+});
+window.addEventListener('mousemove', event => {
+    if (!drag) return;
+    const rect = view.getBoundingClientRect();
+    box[0] -= (event.clientX - drag[0]) / rect.width * box[2];
+    box[1] -= (event.clientY - drag[1]) / rect.height * box[3];
+    drag = [event.clientX, event.clientY];
+    update_box();
+});
+
+show();
+";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::polygons::Polygon;
+    use cgmath::Point2;
+
+    fn layers() -> Vec<Layer> {
+        return (0..3)
+            .map(|i| Layer {
+                height: 0.1 + 0.2 * i as f32,
+                thickness: 0.2,
+                polygons: vec![Polygon::rectangle(
+                    Point2::new(0., 0.),
+                    Point2::new(10., 20.),
+                )],
+            })
+            .collect();
+    }
+
+    fn write(toolpaths: &[Vec<Path>], materials: &[Material]) -> String {
+        let mut html = Vec::new();
+        write_viewer(
+            &mut html,
+            &layers(),
+            toolpaths,
+            materials,
+            &ViewerSettings::default(),
+        )
+        .unwrap();
+        return String::from_utf8(html).unwrap();
+    }
+
+    #[test]
+    fn view_box_fits_the_layers() {
+        // the larger side plus the margin on both sides, centred on the model
+        assert!(write(&[], &[]).contains("viewBox='-10 -5 30 30'"));
+    }
+
+    #[test]
+    fn every_layer_has_its_groups() {
+        let html = write(&[], &[]);
+        assert_eq!(html.matches("class='layer'").count(), 3);
+        assert!(html.contains("id='layer_2' class='layer' data-height='0.5'"));
+        for group in GROUPS.iter() {
+            assert_eq!(html.matches(&format!("class='{}'", group)).count(), 3);
+            assert!(html.contains(&format!("value='{}'", group)));
+        }
+        assert!(html.contains("max='2'"));
+    }
+
+    #[test]
+    fn paths_are_drawn_in_the_colour_of_their_material() {
+        let path = |kind, material| Path {
+            kind,
+            points: vec![Point2::new(1., 1.), Point2::new(9., 1.)],
+            material,
+        };
+        let toolpaths = vec![vec![
+            path(PathKind::Perimeter, 0),
+            path(PathKind::Infill, 1),
+            path(PathKind::Travel, 1),
+        ]];
+        let materials = [
+            Material::default(),
+            Material {
+                name: "red".to_string(),
+                color: [1., 0., 0.],
+            },
+        ];
+        let html = write(&toolpaths, &materials);
+        assert_eq!(html.matches("<polyline").count(), 3);
+        // the contours of the three layers and the perimeter
+        assert_eq!(html.matches("stroke='#808080'").count(), 4);
+        // travel moves keep the colour of their group
+        assert_eq!(html.matches("stroke='#ff0000'").count(), 1);
+
+        // without materials of the model everything keeps the colour of its group
+        let html = write(&toolpaths, &[Material::default()]);
+        assert!(!html.contains("stroke='#"));
+    }
+}